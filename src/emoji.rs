@@ -0,0 +1,288 @@
+//! Detection of emoji sequences in text.
+//!
+//! This is used to replace emoji typed or pasted by the user with images. The
+//! detection does not use the full Unicode emoji data, but it recognizes the
+//! common forms of emoji: Single pictographs (with optional presentation
+//! selector and skin tone modifier), keycaps, flags (regional indicator pairs
+//! and tag sequences) and ZWJ sequences of all of these.
+use std::ops::Range;
+
+use crate::model::{Document, Segment};
+
+const ZWJ: char = '\u{200d}';
+const VARIATION_SELECTOR_16: char = '\u{fe0f}';
+const COMBINING_KEYCAP: char = '\u{20e3}';
+const CANCEL_TAG: char = '\u{e007f}';
+
+/// Return the byte ranges of all emoji sequences in the text.
+pub fn find_emoji(text: &str) -> Vec<Range<usize>> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let byte_index = |i: usize| chars.get(i).map_or(text.len(), |(index, _)| *index);
+    let mut ranges = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        match match_element(&chars, i) {
+            Some(mut end) => {
+                // Join elements connected by a zero width joiner
+                while chars.get(end).map(|(_, c)| *c) == Some(ZWJ) {
+                    match match_element(&chars, end + 1) {
+                        Some(next_end) => end = next_end,
+                        None => break,
+                    }
+                }
+                ranges.push(byte_index(i)..byte_index(end));
+                i = end;
+            }
+            None => i += 1,
+        }
+    }
+    ranges
+}
+
+/// Try to match a single emoji element (i.e. an emoji that is not a ZWJ
+/// sequence) starting at the char index `start`. Return the end index.
+fn match_element(chars: &[(usize, char)], start: usize) -> Option<usize> {
+    let c = |i: usize| chars.get(i).map(|(_, c)| *c);
+    let first = c(start)?;
+
+    // Flags: Pairs of regional indicators
+    if is_regional_indicator(first) {
+        return match c(start + 1) {
+            Some(second) if is_regional_indicator(second) => Some(start + 2),
+            _ => None,
+        };
+    }
+
+    // Keycaps: Digit, '#' or '*', followed by an optional VS16 and U+20E3
+    if first.is_ascii_digit() || first == '#' || first == '*' {
+        let mut end = start + 1;
+        if c(end) == Some(VARIATION_SELECTOR_16) {
+            end += 1;
+        }
+        return if c(end) == Some(COMBINING_KEYCAP) {
+            Some(end + 1)
+        } else {
+            None
+        };
+    }
+
+    // Pictographs. Characters that default to text presentation are only
+    // treated as emoji if they are followed by VS16.
+    let mut end = start + 1;
+    if is_emoji_presentation(first) {
+        if c(end) == Some(VARIATION_SELECTOR_16) {
+            end += 1;
+        }
+    } else if is_pictographic(first) && c(end) == Some(VARIATION_SELECTOR_16) {
+        end += 1;
+    } else {
+        return None;
+    }
+
+    // Skin tone modifier. A modifier on its own is an emoji as well, but it
+    // does not modify another one.
+    if !is_skin_tone_modifier(first) && c(end).map_or(false, is_skin_tone_modifier) {
+        end += 1;
+    }
+
+    // Tag sequence (e.g. subdivision flags like England)
+    if c(end).map_or(false, is_tag) {
+        let mut tag_end = end;
+        while c(tag_end).map_or(false, is_tag) {
+            tag_end += 1;
+        }
+        if c(tag_end) == Some(CANCEL_TAG) {
+            end = tag_end + 1;
+        }
+    }
+
+    Some(end)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+fn is_skin_tone_modifier(c: char) -> bool {
+    ('\u{1f3fb}'..='\u{1f3ff}').contains(&c)
+}
+
+fn is_tag(c: char) -> bool {
+    ('\u{e0020}'..='\u{e007e}').contains(&c)
+}
+
+/// Return whether the character is a pictograph that is displayed as emoji by
+/// default (the `Emoji_Presentation` property of Unicode 13.0).
+fn is_emoji_presentation(c: char) -> bool {
+    matches!(c,
+        '\u{231a}' | '\u{231b}' | '\u{23e9}'..='\u{23ec}' | '\u{23f0}' | '\u{23f3}'
+        | '\u{25fd}' | '\u{25fe}' | '\u{2614}' | '\u{2615}' | '\u{2648}'..='\u{2653}'
+        | '\u{267f}' | '\u{2693}' | '\u{26a1}' | '\u{26aa}' | '\u{26ab}' | '\u{26bd}'
+        | '\u{26be}' | '\u{26c4}' | '\u{26c5}' | '\u{26ce}' | '\u{26d4}' | '\u{26ea}'
+        | '\u{26f2}' | '\u{26f3}' | '\u{26f5}' | '\u{26fa}' | '\u{26fd}' | '\u{2705}'
+        | '\u{270a}' | '\u{270b}' | '\u{2728}' | '\u{274c}' | '\u{274e}'
+        | '\u{2753}'..='\u{2755}' | '\u{2757}' | '\u{2795}'..='\u{2797}'
+        | '\u{27b0}' | '\u{27bf}' | '\u{2b1b}' | '\u{2b1c}' | '\u{2b50}' | '\u{2b55}'
+        | '\u{1f004}' | '\u{1f0cf}' | '\u{1f18e}' | '\u{1f191}'..='\u{1f19a}'
+        | '\u{1f201}' | '\u{1f21a}' | '\u{1f22f}' | '\u{1f232}'..='\u{1f236}'
+        | '\u{1f238}'..='\u{1f23a}' | '\u{1f250}' | '\u{1f251}'
+        | '\u{1f300}'..='\u{1f320}' | '\u{1f32d}'..='\u{1f335}' | '\u{1f337}'..='\u{1f37c}'
+        | '\u{1f37e}'..='\u{1f393}' | '\u{1f3a0}'..='\u{1f3ca}' | '\u{1f3cf}'..='\u{1f3d3}'
+        | '\u{1f3e0}'..='\u{1f3f0}' | '\u{1f3f4}' | '\u{1f3f8}'..='\u{1f43e}'
+        | '\u{1f440}' | '\u{1f442}'..='\u{1f4fc}'
+        | '\u{1f4ff}'..='\u{1f53d}' | '\u{1f54b}'..='\u{1f54e}' | '\u{1f550}'..='\u{1f567}'
+        | '\u{1f57a}' | '\u{1f595}' | '\u{1f596}' | '\u{1f5a4}' | '\u{1f5fb}'..='\u{1f64f}'
+        | '\u{1f680}'..='\u{1f6c5}' | '\u{1f6cc}' | '\u{1f6d0}'..='\u{1f6d2}'
+        | '\u{1f6d5}'..='\u{1f6d7}' | '\u{1f6eb}' | '\u{1f6ec}' | '\u{1f6f4}'..='\u{1f6fc}'
+        | '\u{1f7e0}'..='\u{1f7eb}' | '\u{1f90c}'..='\u{1f93a}' | '\u{1f93c}'..='\u{1f945}'
+        | '\u{1f947}'..='\u{1f978}' | '\u{1f97a}'..='\u{1f9cb}' | '\u{1f9cd}'..='\u{1f9ff}'
+        | '\u{1fa70}'..='\u{1fa74}' | '\u{1fa78}'..='\u{1fa7a}' | '\u{1fa80}'..='\u{1fa86}'
+        | '\u{1fa90}'..='\u{1faa8}' | '\u{1fab0}'..='\u{1fab6}' | '\u{1fac0}'..='\u{1fac2}'
+        | '\u{1fad0}'..='\u{1fad6}'
+    )
+}
+
+/// Return whether the character is a pictograph that may be displayed as
+/// emoji (if followed by VS16).
+fn is_pictographic(c: char) -> bool {
+    matches!(c,
+        '\u{a9}' | '\u{ae}' | '\u{203c}' | '\u{2049}' | '\u{2122}' | '\u{2139}'
+        | '\u{2194}'..='\u{21aa}' | '\u{2300}'..='\u{23ff}' | '\u{24c2}'
+        | '\u{25aa}'..='\u{25fe}' | '\u{2600}'..='\u{27bf}' | '\u{2934}' | '\u{2935}'
+        | '\u{2b05}'..='\u{2b55}' | '\u{3030}' | '\u{303d}' | '\u{3297}' | '\u{3299}'
+        | '\u{1f170}' | '\u{1f171}' | '\u{1f17e}' | '\u{1f17f}' | '\u{1f202}' | '\u{1f237}'
+        | '\u{1f321}' | '\u{1f324}'..='\u{1f32c}' | '\u{1f336}' | '\u{1f37d}'
+        | '\u{1f396}' | '\u{1f397}' | '\u{1f399}'..='\u{1f39b}' | '\u{1f39e}' | '\u{1f39f}'
+        | '\u{1f3cb}'..='\u{1f3ce}' | '\u{1f3d4}'..='\u{1f3df}' | '\u{1f3f3}' | '\u{1f3f5}'
+        | '\u{1f3f7}' | '\u{1f43f}' | '\u{1f441}' | '\u{1f4fd}' | '\u{1f549}' | '\u{1f54a}'
+        | '\u{1f56f}' | '\u{1f570}' | '\u{1f573}'..='\u{1f579}' | '\u{1f587}'
+        | '\u{1f58a}'..='\u{1f58d}' | '\u{1f590}' | '\u{1f5a5}' | '\u{1f5a8}' | '\u{1f5b1}'
+        | '\u{1f5b2}' | '\u{1f5bc}' | '\u{1f5c2}'..='\u{1f5c4}' | '\u{1f5d1}'..='\u{1f5d3}'
+        | '\u{1f5dc}'..='\u{1f5de}' | '\u{1f5e1}' | '\u{1f5e3}' | '\u{1f5e8}' | '\u{1f5ef}'
+        | '\u{1f5f3}' | '\u{1f5fa}' | '\u{1f6cb}' | '\u{1f6cd}'..='\u{1f6cf}'
+        | '\u{1f6e0}'..='\u{1f6e5}' | '\u{1f6e9}' | '\u{1f6f0}' | '\u{1f6f3}'
+    )
+}
+
+/// Replace all emoji in the text segments of the document with images.
+///
+/// The `resolve` function is called with every emoji sequence and returns the
+/// `src` and `class` attributes of the image, or `None` if the emoji should
+/// be kept as text. The emoji sequence is used as alt text of the image, so
+/// the text representation (and thus all logical offsets) of the document
+/// stays the same.
+pub fn replace_emoji<F>(document: &Document, mut resolve: F) -> Document
+where
+    F: FnMut(&str) -> Option<(String, String)>,
+{
+    let mut result = Document::new();
+    for segment in document.segments() {
+        let text = match segment {
+            Segment::Text(text) => text,
+            other => {
+                result.push(other.clone());
+                continue;
+            }
+        };
+        let mut last = 0;
+        for range in find_emoji(text) {
+            let emoji = &text[range.clone()];
+            if let Some((src, cls)) = resolve(emoji) {
+                result.push(Segment::Text(text[last..range.start].to_string()));
+                result.push(Segment::image(&src, emoji, &cls));
+                last = range.end;
+            }
+        }
+        result.push(Segment::Text(text[last..].to_string()));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emoji(text: &str) -> Vec<&str> {
+        find_emoji(text)
+            .into_iter()
+            .map(|range| &text[range])
+            .collect()
+    }
+
+    #[test]
+    fn simple() {
+        assert_eq!(emoji("hi 😀!"), vec!["😀"]);
+        assert_eq!(emoji("⚡🍻"), vec!["⚡", "🍻"]);
+        assert!(emoji("hello 123 # * ©").is_empty());
+    }
+
+    #[test]
+    fn non_emoji_symbols() {
+        // Mahjong tiles, dominos and playing cards, except for the red dragon
+        // and the joker
+        assert!(emoji("🀀🁣🂡").is_empty());
+        assert_eq!(emoji("🀄🃏"), vec!["🀄", "🃏"]);
+        // Enclosed alphanumerics, geometric shapes and arrows
+        assert!(emoji("🄰 🞀 🡐").is_empty());
+        assert_eq!(emoji("🟠🆗"), vec!["🟠", "🆗"]);
+        // Text presentation by default
+        assert!(emoji("🅰 🌡").is_empty());
+        assert_eq!(emoji("🅰️🌡️"), vec!["🅰️", "🌡️"]);
+    }
+
+    #[test]
+    fn variation_selector() {
+        assert_eq!(emoji("I ❤️ you"), vec!["❤️"]);
+        assert!(emoji("I ❤ you").is_empty());
+        assert_eq!(emoji("©️"), vec!["©️"]);
+    }
+
+    #[test]
+    fn modifiers_and_sequences() {
+        assert_eq!(emoji("👍🏽👍"), vec!["👍🏽", "👍"]);
+        assert_eq!(emoji("a👩‍👩‍👧b"), vec!["👩‍👩‍👧"]);
+        assert_eq!(emoji("🏳️‍🌈"), vec!["🏳️‍🌈"]);
+        assert_eq!(emoji("👩🏽‍💻"), vec!["👩🏽‍💻"]);
+        // Standalone skin tone modifiers
+        assert_eq!(emoji("a🏽b"), vec!["🏽"]);
+        assert_eq!(emoji("🏻🏿"), vec!["🏻", "🏿"]);
+        // Dangling ZWJ
+        assert_eq!(emoji("😀\u{200d}a"), vec!["😀"]);
+    }
+
+    #[test]
+    fn flags_and_keycaps() {
+        assert_eq!(emoji("🇨🇭🇩🇪"), vec!["🇨🇭", "🇩🇪"]);
+        assert!(emoji("🇨").is_empty());
+        assert_eq!(
+            emoji("🏴\u{e0067}\u{e0062}\u{e0065}\u{e006e}\u{e0067}\u{e007f}"),
+            vec!["🏴\u{e0067}\u{e0062}\u{e0065}\u{e006e}\u{e0067}\u{e007f}"]
+        );
+        assert_eq!(emoji("1️⃣ #⃣"), vec!["1️⃣", "#⃣"]);
+    }
+
+    #[test]
+    fn replace() {
+        let document = Document::from_segments(vec![
+            Segment::Text("a😀b\n".into()),
+            Segment::image("beer.png", "🍻", "em"),
+            Segment::Text("🤷c".into()),
+        ]);
+        let replaced = replace_emoji(&document, |emoji| match emoji {
+            "😀" => Some(("smile.png".into(), "em".into())),
+            _ => None,
+        });
+        assert_eq!(
+            replaced.segments(),
+            &[
+                Segment::Text("a".into()),
+                Segment::image("smile.png", "😀", "em"),
+                Segment::Text("b".into()),
+                Segment::Newline,
+                Segment::image("beer.png", "🍻", "em"),
+                Segment::Text("🤷c".into()),
+            ][..]
+        );
+        assert_eq!(replaced.to_text(), document.to_text());
+    }
+}
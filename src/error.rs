@@ -0,0 +1,153 @@
+//! Error handling.
+//!
+//! Internally, fallible functions return a `ComposeAreaError`. Public methods
+//! return `Result<_, JsValue>`, the errors are converted into JS `Error`
+//! objects that can be inspected by the caller.
+use std::fmt;
+
+use wasm_bindgen::{JsCast, JsValue};
+
+/// An error that occurred in the compose area.
+///
+/// When returned to JS, the error is converted to an `Error` object with the
+/// name `ComposeAreaError`. Its `kind` property contains the name of the
+/// variant (e.g. `"DetachedWrapper"`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ComposeAreaError {
+    /// There is no global `window` or `document` object.
+    NoDocument,
+
+    /// The wrapper element is not attached to the document.
+    DetachedWrapper,
+
+    /// The window selection could not be accessed.
+    NoSelection,
+
+    /// A DOM operation failed.
+    Dom {
+        /// A description of the operation that failed.
+        operation: &'static str,
+        /// The error message of the exception thrown by the browser.
+        message: String,
+    },
+
+    /// A serialized state could not be restored.
+    InvalidState(String),
+
+    /// The compose area is in use, because a JS function called by it (e.g.
+    /// the emoji resolver or an element handler) called one of its methods.
+    Busy,
+}
+
+impl ComposeAreaError {
+    /// Return the name of the error variant.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ComposeAreaError::NoDocument => "NoDocument",
+            ComposeAreaError::DetachedWrapper => "DetachedWrapper",
+            ComposeAreaError::NoSelection => "NoSelection",
+            ComposeAreaError::Dom { .. } => "Dom",
+            ComposeAreaError::InvalidState(_) => "InvalidState",
+            ComposeAreaError::Busy => "Busy",
+        }
+    }
+}
+
+impl fmt::Display for ComposeAreaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ComposeAreaError::NoDocument => write!(f, "No global window or document exists"),
+            ComposeAreaError::DetachedWrapper => {
+                write!(f, "The wrapper element is not attached to the document")
+            }
+            ComposeAreaError::NoSelection => write!(f, "Could not access the window selection"),
+            ComposeAreaError::Dom { operation, message } => {
+                write!(f, "Could not {}: {}", operation, message)
+            }
+            ComposeAreaError::InvalidState(reason) => write!(f, "Invalid state: {}", reason),
+            ComposeAreaError::Busy => write!(f, "The compose area is in use"),
+        }
+    }
+}
+
+impl std::error::Error for ComposeAreaError {}
+
+impl From<ComposeAreaError> for JsValue {
+    fn from(error: ComposeAreaError) -> Self {
+        let js_error = js_sys::Error::new(&error.to_string());
+        js_error.set_name("ComposeAreaError");
+        if js_sys::Reflect::set(&js_error, &"kind".into(), &error.kind().into()).is_err() {
+            warn!("[compose_area] Could not set error kind");
+        }
+        js_error.into()
+    }
+}
+
+/// Convert errors returned by DOM methods into a `ComposeAreaError`.
+pub(crate) trait DomResultExt<T> {
+    /// Convert the error. The operation should complete the sentence
+    /// "Could not ...".
+    fn dom_err(self, operation: &'static str) -> Result<T, ComposeAreaError>;
+}
+
+impl<T> DomResultExt<T> for Result<T, JsValue> {
+    fn dom_err(self, operation: &'static str) -> Result<T, ComposeAreaError> {
+        self.map_err(|e| ComposeAreaError::Dom {
+            operation,
+            message: js_error_message(&e),
+        })
+    }
+}
+
+/// Return the message of a JS exception.
+fn js_error_message(value: &JsValue) -> String {
+    value
+        .dyn_ref::<js_sys::Error>()
+        .map(|e| String::from(e.message()))
+        .or_else(|| value.as_string())
+        .unwrap_or_else(|| format!("{:?}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn to_js() {
+        let value: JsValue = ComposeAreaError::DetachedWrapper.into();
+        let error: &js_sys::Error = value.dyn_ref().unwrap();
+        assert_eq!(String::from(error.name()), "ComposeAreaError");
+        assert_eq!(
+            String::from(error.message()),
+            "The wrapper element is not attached to the document"
+        );
+        assert_eq!(
+            js_sys::Reflect::get(&value, &"kind".into()).unwrap(),
+            "DetachedWrapper"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn dom_err() {
+        let result: Result<(), JsValue> = Err(js_sys::Error::new("oops").into());
+        assert_eq!(
+            result.dom_err("do something"),
+            Err(ComposeAreaError::Dom {
+                operation: "do something",
+                message: "oops".into(),
+            })
+        );
+        assert_eq!(
+            ComposeAreaError::Dom {
+                operation: "do something",
+                message: "oops".into(),
+            }
+            .to_string(),
+            "Could not do something: oops"
+        );
+    }
+}
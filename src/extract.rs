@@ -1,13 +1,20 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
 use wasm_bindgen::{prelude::*, JsCast};
-use web_sys::{Element, HtmlImageElement, Node};
+use web_sys::{Element, HtmlImageElement, Node, Text};
+
+use crate::model::{utf16_len, Document, Segment};
+use crate::render::{is_filler, KIND_ATTRIBUTE, MENTION_ATTRIBUTE, TOKEN_ATTRIBUTE, TOKEN_CLASS};
+use crate::utils::child_index;
 
 /// Process a DOM node recursively and extract text.
 ///
 /// Convert elements like images to alt text.
 #[wasm_bindgen]
 pub fn extract_text(root_element: &Element, no_trim: bool) -> String {
-    let mut text = String::new();
-    visit_child_nodes(root_element, &mut text);
+    let text = Extraction::new(root_element).document.to_text();
     if no_trim {
         text
     } else {
@@ -15,63 +22,536 @@ pub fn extract_text(root_element: &Element, no_trim: bool) -> String {
     }
 }
 
-/// Used by `extract_text`.
+/// Process a DOM node recursively and extract the contents as an array of
+/// `ExtractedSegment` objects.
+#[wasm_bindgen]
+pub fn extract_segments(root_element: &Element) -> js_sys::Array {
+    Extraction::new(root_element)
+        .segments
+        .into_iter()
+        .map(JsValue::from)
+        .collect()
+}
+
+/// The type of an `ExtractedSegment`.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    Text,
+    Image,
+    Newline,
+    /// A mention (see `ComposeArea.insert_mention`). Its text is the token.
+    Mention,
+    /// An inline token (see `ComposeArea.insert_token`).
+    Token,
+    /// An element that is not supported by the compose area. It does not
+    /// contribute to the text and thus has a length of 0.
+    Unknown,
+}
+
+/// A piece of content of the compose area, together with its logical offsets
+/// (see `ComposeArea::get_selection_offsets`).
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct ExtractedSegment {
+    kind: SegmentKind,
+    text: String,
+    element: Option<Element>,
+    start: u32,
+    end: u32,
+}
+
+#[wasm_bindgen]
+impl ExtractedSegment {
+    /// Return the type of the segment.
+    pub fn kind(&self) -> SegmentKind {
+        self.kind
+    }
+
+    /// Return the text representation (the alt text for images, an empty
+    /// string for unknown elements).
+    pub fn text(&self) -> String {
+        self.text.clone()
+    }
+
+    /// Return the DOM element (for images, mentions, tokens, line breaks and
+    /// unknown elements).
+    pub fn element(&self) -> Option<Element> {
+        self.element.clone()
+    }
+
+    /// Return the lowercase tag name of the element.
+    pub fn tag_name(&self) -> Option<String> {
+        self.element.as_ref().map(|e| e.tag_name().to_lowercase())
+    }
+
+    /// Return the `src` attribute of an image.
+    pub fn src(&self) -> Option<String> {
+        self.attribute("src")
+    }
+
+    /// Return the alt text of an image.
+    pub fn alt(&self) -> Option<String> {
+        match self.kind {
+            SegmentKind::Image => Some(self.text.clone()),
+            _ => None,
+        }
+    }
+
+    /// Return the `class` attribute of an image.
+    pub fn cls(&self) -> Option<String> {
+        self.attribute("class")
+    }
+
+    /// Return the `id` attribute of an image.
+    pub fn id(&self) -> Option<String> {
+        self.attribute("id")
+    }
+
+    /// Return the kind of a token.
+    pub fn token_kind(&self) -> Option<String> {
+        match self.kind {
+            SegmentKind::Token => self.element.as_ref()?.get_attribute(KIND_ATTRIBUTE),
+            _ => None,
+        }
+    }
+
+    /// Return the logical offset where the segment starts.
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    /// Return the logical offset where the segment ends.
+    pub fn end(&self) -> u32 {
+        self.end
+    }
+
+    /// Return an attribute of an image element.
+    fn attribute(&self, name: &str) -> Option<String> {
+        match self.kind {
+            SegmentKind::Image => self.element.as_ref()?.get_attribute(name),
+            _ => None,
+        }
+    }
+}
+
+/// The result of an element handler.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ElementOutput {
+    /// The text produced by the element. If the child nodes are visited, the
+    /// text is placed before them. Newlines are converted to line breaks.
+    pub text: String,
+    /// Whether the child nodes of the element should be visited.
+    pub recurse: bool,
+}
+
+/// A function that decides how an element is extracted. If it returns
+/// `None`, the default behavior is used.
+pub type ElementHandler = Rc<dyn Fn(&Element) -> Option<ElementOutput>>;
+
+/// A registry of element handlers, by tag name or by CSS class.
 ///
-/// TODO: This could be optimized by avoiding copies and re-allocations.
-fn visit_child_nodes(parent_node: &Element, text: &mut String) {
-    let mut last_node_type = "".to_string();
-    let children = parent_node.child_nodes();
-    for i in 0..children.length() {
-        let node = match children.item(i) {
-            Some(n) => n,
-            None => {
-                warn!("visit_child_nodes: Index out of bounds");
-                return;
+/// Class handlers take precedence over tag handlers. If multiple class
+/// handlers match, the one that was registered first is used.
+#[derive(Clone, Default)]
+pub struct ElementHandlers {
+    by_tag: HashMap<String, ElementHandler>,
+    by_class: Vec<(String, ElementHandler)>,
+}
+
+impl ElementHandlers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a handler for elements with the specified tag name
+    /// (case insensitive). An existing handler for that tag is replaced.
+    pub fn set_tag_handler(&mut self, tag: &str, handler: ElementHandler) {
+        self.by_tag.insert(tag.to_lowercase(), handler);
+    }
+
+    /// Register a handler for elements with the specified CSS class. An
+    /// existing handler for that class is replaced.
+    pub fn set_class_handler(&mut self, cls: &str, handler: ElementHandler) {
+        match self.by_class.iter_mut().find(|(c, _)| c == cls) {
+            Some(entry) => entry.1 = handler,
+            None => self.by_class.push((cls.to_string(), handler)),
+        }
+    }
+
+    /// Remove the handler for the specified tag name. Return whether a
+    /// handler was registered.
+    pub fn remove_tag_handler(&mut self, tag: &str) -> bool {
+        self.by_tag.remove(&tag.to_lowercase()).is_some()
+    }
+
+    /// Remove the handler for the specified CSS class. Return whether a
+    /// handler was registered.
+    pub fn remove_class_handler(&mut self, cls: &str) -> bool {
+        let len = self.by_class.len();
+        self.by_class.retain(|(c, _)| c != cls);
+        self.by_class.len() != len
+    }
+
+    /// Return whether no handlers are registered.
+    pub fn is_empty(&self) -> bool {
+        self.by_tag.is_empty() && self.by_class.is_empty()
+    }
+
+    /// Run the matching handler for the element, if any.
+    fn handle(&self, element: &Element) -> Option<ElementOutput> {
+        if self.is_empty() {
+            return None;
+        }
+        let class_list = element.class_list();
+        self.by_class
+            .iter()
+            .find_map(|(cls, handler)| {
+                if class_list.contains(cls) {
+                    Some(handler)
+                } else {
+                    None
+                }
+            })
+            .or_else(|| self.by_tag.get(&element.tag_name().to_lowercase()))
+            .and_then(|handler| handler(element))
+    }
+}
+
+impl fmt::Debug for ElementHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ElementHandlers")
+            .field("tags", &self.by_tag.keys().collect::<Vec<_>>())
+            .field(
+                "classes",
+                &self.by_class.iter().map(|(c, _)| c).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// A link between a DOM location and a logical offset in the document model.
+#[derive(Debug, Clone)]
+enum Anchor {
+    /// A text node whose contents start at the specified logical offset.
+    Text { node: Node, offset: u32, len: u32 },
+
+    /// The boundary point right before the child node with the specified
+    /// index (or at the end of the parent, if the index equals the number of
+    /// child nodes).
+    Boundary {
+        parent: Node,
+        index: u32,
+        offset: u32,
+    },
+}
+
+/// The result of walking the DOM tree below a root element.
+///
+/// Apart from the document model, this also contains the information needed
+/// to map DOM positions to logical offsets and back.
+#[derive(Debug, Clone)]
+pub(crate) struct Extraction {
+    pub document: Document,
+    /// The contents, split into segments that map to DOM nodes. Unlike the
+    /// segments of the document, this includes unknown elements.
+    pub segments: Vec<ExtractedSegment>,
+    anchors: Vec<Anchor>,
+    handlers: ElementHandlers,
+}
+
+impl Extraction {
+    /// Walk the DOM tree below the specified root element.
+    pub fn new(root_element: &Element) -> Self {
+        Self::with_handlers(root_element, &ElementHandlers::new())
+    }
+
+    /// Walk the DOM tree below the specified root element, using custom
+    /// element handlers.
+    pub fn with_handlers(root_element: &Element, handlers: &ElementHandlers) -> Self {
+        let mut extraction = Self {
+            document: Document::new(),
+            segments: vec![],
+            anchors: vec![],
+            handlers: handlers.clone(),
+        };
+        extraction.visit_child_nodes(root_element);
+        extraction
+    }
+
+    /// Return the logical offset of the specified DOM boundary point.
+    ///
+    /// Boundary points inside elements that don't contribute to the text
+    /// (e.g. inside an image) are mapped to the position of that element.
+    /// If the node is not part of the walked tree, `None` is returned.
+    pub fn offset_of(&self, node: &Node, offset: u32) -> Option<u32> {
+        let mut node = node.clone();
+        let mut offset = offset;
+        loop {
+            let found = self.anchors.iter().find_map(|anchor| match anchor {
+                Anchor::Text {
+                    node: n,
+                    offset: start,
+                    len,
+                } if n == &node => Some(start + offset.min(*len)),
+                Anchor::Boundary {
+                    parent,
+                    index,
+                    offset: start,
+                } if parent == &node && *index == offset => Some(*start),
+                _ => None,
+            });
+            if found.is_some() {
+                return found;
             }
+            let parent = node.parent_node()?;
+            offset = child_index(&parent, &node)?;
+            node = parent;
+        }
+    }
+
+    /// Return the DOM boundary point for the specified logical offset.
+    ///
+    /// Positions inside text nodes are preferred. Offsets pointing into an
+    /// atomic segment are mapped to the position before that segment, offsets
+    /// past the end are mapped to the end of the root element.
+    pub fn position_at(&self, offset: u32) -> Option<(Node, u32)> {
+        let in_text = self.anchors.iter().find_map(|anchor| match anchor {
+            Anchor::Text {
+                node,
+                offset: start,
+                len,
+            } if *start <= offset && offset <= start + len => Some((node.clone(), offset - start)),
+            _ => None,
+        });
+        let boundaries = || {
+            self.anchors.iter().filter_map(|anchor| match anchor {
+                Anchor::Boundary {
+                    parent,
+                    index,
+                    offset: start,
+                } => Some((parent.clone(), *index, *start)),
+                Anchor::Text { .. } => None,
+            })
         };
-        match node.node_type() {
-            Node::TEXT_NODE => {
-                if last_node_type == "div" {
-                    // A text node following a div should go on a new line
-                    text.push('\n');
-                }
-                last_node_type = "text".to_string();
-                // Append text, but strip leading and trailing newlines
-                if let Some(ref val) = node.node_value() {
-                    text.push_str(val);
+        in_text
+            .or_else(|| {
+                boundaries().find_map(|(parent, index, start)| {
+                    if start == offset {
+                        Some((parent, index))
+                    } else {
+                        None
+                    }
+                })
+            })
+            .or_else(|| {
+                boundaries()
+                    .filter(|(_, _, start)| *start < offset)
+                    .last()
+                    .map(|(parent, index, _)| (parent, index))
+            })
+    }
+
+    /// Append a segment to the document and to the extracted segments.
+    fn push(&mut self, segment: Segment, element: Option<&Element>) {
+        let mut start = self.document.len();
+        match &segment {
+            Segment::Text(text) => {
+                for (i, line) in text.split('\n').enumerate() {
+                    if i > 0 {
+                        self.push_extracted(SegmentKind::Newline, "\n", None, start);
+                        start += 1;
+                    }
+                    if !line.is_empty() {
+                        self.push_extracted(SegmentKind::Text, line, None, start);
+                        start += utf16_len(line);
+                    }
                 }
             }
-            Node::ELEMENT_NODE => {
-                let element: &Element = node.unchecked_ref();
-                let tag = element.tag_name().to_lowercase();
-                let last_node_type_clone = last_node_type.clone();
-                last_node_type = tag.clone();
-                match &*tag {
-                    "span" => {
-                        visit_child_nodes(element, text);
+            Segment::Image { alt, .. } => {
+                self.push_extracted(SegmentKind::Image, alt, element, start);
+            }
+            Segment::Mention { token, .. } => {
+                self.push_extracted(SegmentKind::Mention, token, element, start);
+            }
+            Segment::Token { text, .. } => {
+                self.push_extracted(SegmentKind::Token, text, element, start);
+            }
+            Segment::Newline => {
+                self.push_extracted(SegmentKind::Newline, "\n", element, start);
+            }
+        }
+        self.document.push(segment);
+    }
+
+    /// Append an extracted segment. Adjacent text segments are merged.
+    fn push_extracted(
+        &mut self,
+        kind: SegmentKind,
+        text: &str,
+        element: Option<&Element>,
+        start: u32,
+    ) {
+        if let Some(last) = self.segments.last_mut() {
+            if kind == SegmentKind::Text && last.kind == SegmentKind::Text {
+                last.text.push_str(text);
+                last.end += utf16_len(text);
+                return;
+            }
+        }
+        self.segments.push(ExtractedSegment {
+            kind,
+            text: text.to_string(),
+            element: element.cloned(),
+            start,
+            end: start + utf16_len(text),
+        });
+    }
+
+    /// Record the boundary point before the child node at `index`.
+    fn add_boundary(&mut self, parent: &Element, index: u32) {
+        self.anchors.push(Anchor::Boundary {
+            parent: parent.clone().unchecked_into(),
+            index,
+            offset: self.document.len(),
+        });
+    }
+
+    /// Used by `Extraction::new`.
+    ///
+    /// TODO: This could be optimized by avoiding copies and re-allocations.
+    fn visit_child_nodes(&mut self, parent_node: &Element) {
+        let mut last_node_type = "".to_string();
+        let children = parent_node.child_nodes();
+        for i in 0..children.length() {
+            let node = match children.item(i) {
+                Some(n) => n,
+                None => {
+                    warn!("visit_child_nodes: Index out of bounds");
+                    return;
+                }
+            };
+            self.add_boundary(parent_node, i);
+            match node.node_type() {
+                Node::TEXT_NODE => {
+                    if last_node_type == "div" {
+                        // A text node following a div should go on a new line
+                        self.push(Segment::Newline, None);
                     }
-                    "div" => {
-                        text.push('\n');
-                        visit_child_nodes(element, text);
+                    last_node_type = "text".to_string();
+                    // Append text, but strip leading and trailing newlines
+                    if let Some(ref val) = node.node_value() {
+                        self.anchors.push(Anchor::Text {
+                            node: node.clone(),
+                            offset: self.document.len(),
+                            len: node.unchecked_ref::<Text>().length(),
+                        });
+                        self.push(Segment::Text(val.clone()), None);
+                    }
+                }
+                Node::ELEMENT_NODE => {
+                    let element: &Element = node.unchecked_ref();
+                    let tag = element.tag_name().to_lowercase();
+                    let last_node_type_clone = last_node_type.clone();
+                    last_node_type = tag.clone();
+                    // Mentions and tokens are not passed to the handlers, so
+                    // that they are kept when the contents are rendered
+                    let atomic = match &*tag {
+                        "span" => mention_segment(element).or_else(|| token_segment(element)),
+                        _ => None,
+                    };
+                    if let Some(segment) = atomic {
+                        self.push(segment, Some(element));
+                        continue;
                     }
-                    "img" => {
-                        if last_node_type_clone == "div" {
-                            // An image following a div should go on a new line
-                            text.push('\n');
+                    if let Some(output) = self.handlers.handle(element) {
+                        self.push(Segment::Text(output.text), None);
+                        if output.recurse {
+                            self.visit_child_nodes(element);
                         }
-                        text.push_str(&node.unchecked_ref::<HtmlImageElement>().alt());
+                        continue;
                     }
-                    "br" => {
-                        text.push('\n');
+                    match &*tag {
+                        "span" => self.visit_child_nodes(element),
+                        "div" => {
+                            self.push(Segment::Newline, None);
+                            self.visit_child_nodes(element);
+                        }
+                        "img" => {
+                            if last_node_type_clone == "div" {
+                                // An image following a div should go on a new line
+                                self.push(Segment::Newline, None);
+                            }
+                            self.push(
+                                Segment::image_with_id(
+                                    &element.get_attribute("src").unwrap_or_default(),
+                                    &node.unchecked_ref::<HtmlImageElement>().alt(),
+                                    &element.get_attribute("class").unwrap_or_default(),
+                                    element.get_attribute("id").as_deref(),
+                                ),
+                                Some(element),
+                            );
+                        }
+                        "br" if is_filler(&node) => {}
+                        "br" => {
+                            self.push(Segment::Newline, Some(element));
+                        }
+                        _other => {
+                            let offset = self.document.len();
+                            self.push_extracted(SegmentKind::Unknown, "", Some(element), offset);
+                        }
                     }
-                    _other => {}
                 }
+                other => warn!("visit_child_nodes: Unhandled node type: {}", other),
             }
-            other => warn!("visit_child_nodes: Unhandled node type: {}", other),
         }
+        self.add_boundary(parent_node, children.length());
     }
 }
 
+/// Return the mention segment for a mention element, or `None` if the
+/// element is not a mention (or has no token).
+fn mention_segment(element: &Element) -> Option<Segment> {
+    let id = element.get_attribute(MENTION_ATTRIBUTE)?;
+    let token = element
+        .get_attribute(TOKEN_ATTRIBUTE)
+        .filter(|token| !token.is_empty())?;
+    let name = element.text_content().unwrap_or_default();
+    Some(Segment::mention(&id, &name, &token))
+}
+
+/// Return the token segment for a token element, or `None` if the element is
+/// not a token (or has no text).
+///
+/// The attributes of the segment are the attributes of the element, except
+/// for the ones added by the compose area.
+pub(crate) fn token_segment(element: &Element) -> Option<Segment> {
+    let kind = element.get_attribute(KIND_ATTRIBUTE)?;
+    let text = element
+        .get_attribute(TOKEN_ATTRIBUTE)
+        .filter(|text| !text.is_empty())?;
+    let mut attrs = vec![];
+    for name in element.get_attribute_names().iter() {
+        let name = name.as_string().unwrap_or_default();
+        let value = element.get_attribute(&name).unwrap_or_default();
+        match &*name {
+            "contenteditable" => {}
+            name if name.starts_with("data-ca-") => {}
+            "class" => {
+                let cls: Vec<&str> = value
+                    .split_whitespace()
+                    .filter(|cls| *cls != TOKEN_CLASS)
+                    .collect();
+                if !cls.is_empty() {
+                    attrs.push((name, cls.join(" ")));
+                }
+            }
+            _ => attrs.push((name, value)),
+        }
+    }
+    Some(Segment::token(&kind, &text, attrs))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -203,4 +683,119 @@ mod tests {
             .test();
         }
     }
+    mod extraction {
+        use super::*;
+
+        fn wrapper(html: &str) -> Element {
+            let window = web_sys::window().expect("No global `window` exists");
+            let document = window.document().expect("Should have a document on window");
+            let wrapper = document
+                .create_element("div")
+                .expect("Could not create test wrapper");
+            wrapper.set_inner_html(html);
+            wrapper
+        }
+
+        #[wasm_bindgen_test]
+        fn offset_of() {
+            let wrapper = wrapper(r#"ab<img src="img.jpg" alt="😀"><br><span>cd</span>"#);
+            let extraction = Extraction::new(&wrapper);
+            let children = wrapper.child_nodes();
+            let span_text = children.item(3).unwrap().first_child().unwrap();
+            assert_eq!(extraction.offset_of(&children.item(0).unwrap(), 1), Some(1));
+            assert_eq!(extraction.offset_of(&wrapper, 2), Some(4));
+            assert_eq!(extraction.offset_of(&wrapper, 3), Some(5));
+            assert_eq!(extraction.offset_of(&span_text, 1), Some(6));
+            assert_eq!(extraction.offset_of(&children.item(1).unwrap(), 0), Some(2));
+        }
+
+        #[wasm_bindgen_test]
+        fn position_at() {
+            let wrapper = wrapper(r#"ab<img src="img.jpg" alt="😀"><br>"#);
+            let extraction = Extraction::new(&wrapper);
+            let text = wrapper.first_child().unwrap();
+            assert_eq!(extraction.position_at(1), Some((text.clone(), 1)));
+            assert_eq!(extraction.position_at(2), Some((text, 2)));
+            assert_eq!(extraction.position_at(3), Some((wrapper.clone().into(), 1)));
+            assert_eq!(extraction.position_at(4), Some((wrapper.clone().into(), 2)));
+            assert_eq!(extraction.position_at(99), Some((wrapper.into(), 3)));
+        }
+
+        #[wasm_bindgen_test]
+        fn segments() {
+            let wrapper = wrapper(concat!(
+                r#"ab<span>c</span><img src="img.jpg" alt="😀" class="em" id="i1">"#,
+                r#"<b>x</b>d<br>e"#,
+            ));
+            let segments = Extraction::new(&wrapper).segments;
+            let summary: Vec<_> = segments
+                .iter()
+                .map(|s| (s.kind(), s.text(), s.start(), s.end()))
+                .collect();
+            assert_eq!(
+                summary,
+                vec![
+                    (SegmentKind::Text, "abc".to_string(), 0, 3),
+                    (SegmentKind::Image, "😀".to_string(), 3, 5),
+                    (SegmentKind::Unknown, "".to_string(), 5, 5),
+                    (SegmentKind::Text, "d".to_string(), 5, 6),
+                    (SegmentKind::Newline, "\n".to_string(), 6, 7),
+                    (SegmentKind::Text, "e".to_string(), 7, 8),
+                ]
+            );
+            let img = &segments[1];
+            assert_eq!(img.src().as_deref(), Some("img.jpg"));
+            assert_eq!(img.alt().as_deref(), Some("😀"));
+            assert_eq!(img.cls().as_deref(), Some("em"));
+            assert_eq!(img.id().as_deref(), Some("i1"));
+            assert_eq!(segments[2].tag_name().as_deref(), Some("b"));
+            assert_eq!(segments[0].src(), None);
+        }
+
+        #[wasm_bindgen_test]
+        fn mentions() {
+            let wrapper = wrapper(concat!(
+                r#"a<span class="ca-mention" contenteditable="false" "#,
+                r#"data-ca-mention="X1" data-ca-token="@[X1]">Alice</span>b"#,
+                r#"<span data-ca-mention="X2">Bob</span>"#,
+            ));
+            let extraction = Extraction::new(&wrapper);
+            assert_eq!(
+                extraction.document.segments(),
+                &[
+                    Segment::Text("a".into()),
+                    Segment::mention("X1", "Alice", "@[X1]"),
+                    Segment::Text("bBob".into()),
+                ][..]
+            );
+            assert_eq!(extraction.segments[1].kind(), SegmentKind::Mention);
+
+            // Positions inside the mention are mapped to its start
+            let name = wrapper
+                .child_nodes()
+                .item(1)
+                .unwrap()
+                .first_child()
+                .unwrap();
+            assert_eq!(extraction.offset_of(&name, 2), Some(1));
+        }
+
+        #[wasm_bindgen_test]
+        fn segments_newlines_in_text() {
+            let wrapper = wrapper("a\n\nb");
+            let segments = Extraction::new(&wrapper).segments;
+            let kinds: Vec<_> = segments.iter().map(ExtractedSegment::kind).collect();
+            assert_eq!(
+                kinds,
+                vec![
+                    SegmentKind::Text,
+                    SegmentKind::Newline,
+                    SegmentKind::Newline,
+                    SegmentKind::Text,
+                ]
+            );
+            assert_eq!(segments[3].start(), 3);
+            assert_eq!(extract_segments(&wrapper).length(), 4);
+        }
+    }
 }
@@ -0,0 +1,152 @@
+//! Undo / redo history, independent of the browser's undo stack.
+use crate::model::Document;
+
+/// Consecutive typing changes within this time span (in milliseconds) are
+/// coalesced into a single undo step.
+const COALESCE_TIMEOUT_MS: f64 = 1000.0;
+
+/// The maximum number of undo steps that are kept.
+const MAX_UNDO_STEPS: usize = 100;
+
+/// The contents and selection of the compose area at a certain point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub document: Document,
+    /// The logical selection offsets, if there is a selection.
+    pub selection: Option<(u32, u32)>,
+}
+
+/// The kind of a change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// Typing by the user. Consecutive typing changes are coalesced.
+    Typing,
+
+    /// Any other change (e.g. insertions through the API). These changes
+    /// always result in a separate undo step.
+    Other,
+}
+
+/// The undo and redo stacks.
+#[derive(Debug, Default)]
+pub struct History {
+    undo_stack: Vec<Snapshot>,
+    redo_stack: Vec<Snapshot>,
+    /// Kind and timestamp of the last recorded change.
+    last_change: Option<(ChangeKind, f64)>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a change. The `before` snapshot is the state right before the
+    /// change was applied. The timestamp is in milliseconds.
+    pub fn record(&mut self, before: Snapshot, kind: ChangeKind, timestamp: f64) {
+        let coalesce = match self.last_change {
+            Some((ChangeKind::Typing, last)) => {
+                kind == ChangeKind::Typing
+                    && timestamp - last < COALESCE_TIMEOUT_MS
+                    && !self.undo_stack.is_empty()
+            }
+            _ => false,
+        };
+        if !coalesce {
+            self.undo_stack.push(before);
+            if self.undo_stack.len() > MAX_UNDO_STEPS {
+                self.undo_stack.remove(0);
+            }
+        }
+        self.redo_stack.clear();
+        self.last_change = Some((kind, timestamp));
+    }
+
+    /// Undo the last change. The `current` snapshot is moved to the redo
+    /// stack. Return the snapshot that should be restored.
+    pub fn undo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let snapshot = self.undo_stack.pop()?;
+        self.redo_stack.push(current);
+        self.last_change = None;
+        Some(snapshot)
+    }
+
+    /// Redo the last undone change. The `current` snapshot is moved to the
+    /// undo stack. Return the snapshot that should be restored.
+    pub fn redo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let snapshot = self.redo_stack.pop()?;
+        self.undo_stack.push(current);
+        self.last_change = None;
+        Some(snapshot)
+    }
+
+    /// Return whether there is a change that can be undone.
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    /// Return whether there is a change that can be redone.
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(text: &str) -> Snapshot {
+        Snapshot {
+            document: Document::from_text(text),
+            selection: Some((0, 0)),
+        }
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut history = History::new();
+        assert!(!history.can_undo());
+        history.record(snapshot(""), ChangeKind::Other, 0.0);
+        history.record(snapshot("a"), ChangeKind::Other, 1.0);
+        assert!(history.can_undo());
+        assert!(!history.can_redo());
+
+        assert_eq!(history.undo(snapshot("ab")), Some(snapshot("a")));
+        assert_eq!(history.undo(snapshot("a")), Some(snapshot("")));
+        assert_eq!(history.undo(snapshot("")), None);
+        assert!(history.can_redo());
+
+        assert_eq!(history.redo(snapshot("")), Some(snapshot("a")));
+        assert_eq!(history.redo(snapshot("a")), Some(snapshot("ab")));
+        assert_eq!(history.redo(snapshot("ab")), None);
+    }
+
+    #[test]
+    fn coalesce_typing() {
+        let mut history = History::new();
+        history.record(snapshot(""), ChangeKind::Typing, 0.0);
+        history.record(snapshot("a"), ChangeKind::Typing, 100.0);
+        history.record(snapshot("ab"), ChangeKind::Typing, 200.0);
+        // Pause
+        history.record(snapshot("abc"), ChangeKind::Typing, 2000.0);
+        // Insertion through API
+        history.record(snapshot("abcd"), ChangeKind::Other, 2100.0);
+        history.record(snapshot("abcd😀"), ChangeKind::Typing, 2200.0);
+
+        assert_eq!(history.undo(snapshot("abcd😀e")), Some(snapshot("abcd😀")));
+        assert_eq!(history.undo(snapshot("abcd😀")), Some(snapshot("abcd")));
+        assert_eq!(history.undo(snapshot("abcd")), Some(snapshot("abc")));
+        assert_eq!(history.undo(snapshot("abc")), Some(snapshot("")));
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn record_clears_redo() {
+        let mut history = History::new();
+        history.record(snapshot(""), ChangeKind::Other, 0.0);
+        history.undo(snapshot("a"));
+        assert!(history.can_redo());
+        history.record(snapshot(""), ChangeKind::Other, 1.0);
+        assert!(!history.can_redo());
+    }
+}
@@ -0,0 +1,404 @@
+//! Editing engine based on `beforeinput` events.
+//!
+//! Instead of letting the browser modify the DOM (which every browser does
+//! differently), the input events are translated into editing operations that
+//! are applied to the document model.
+use crate::history::ChangeKind;
+use crate::model::Document;
+
+/// An editing operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditOperation {
+    /// Replace the selection with the specified text.
+    InsertText(String),
+
+    /// Replace the selection with a line break.
+    InsertLineBreak,
+
+    /// Delete the selection or the character before the caret.
+    DeleteBackward,
+
+    /// Delete the selection or the character after the caret.
+    DeleteForward,
+
+    /// Delete the selection or the word before the caret.
+    DeleteWordBackward,
+
+    /// Delete the selection or the word after the caret.
+    DeleteWordForward,
+
+    /// Delete the selection or everything between the start of the line and
+    /// the caret.
+    DeleteLineBackward,
+
+    /// Delete the selection or everything between the caret and the end of
+    /// the line.
+    DeleteLineForward,
+
+    /// Delete the selection (e.g. when cutting).
+    DeleteSelection,
+
+    /// Undo the last change.
+    Undo,
+
+    /// Redo the last undone change.
+    Redo,
+
+    /// Don't do anything. Used for input types that are not supported by the
+    /// compose area (e.g. formatting).
+    Ignore,
+}
+
+/// An editing operation together with the kind of change it causes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub operation: EditOperation,
+    pub kind: ChangeKind,
+}
+
+impl Edit {
+    /// Create an edit from the `inputType` of a `beforeinput` event.
+    ///
+    /// The `data` argument must contain the inserted text (either from the
+    /// `data` attribute or from the `dataTransfer` attribute of the event).
+    /// If the input type should be handled by the browser (e.g. composition
+    /// events), `None` is returned.
+    ///
+    /// See <https://w3c.github.io/input-events/#interface-InputEvent-Attributes>
+    pub fn from_input_type(input_type: &str, data: Option<String>) -> Option<Self> {
+        let (operation, kind) = match input_type {
+            // IME composition must be handled by the browser
+            "insertCompositionText" | "deleteCompositionText" | "insertFromComposition" => {
+                return None;
+            }
+            "insertText" => (EditOperation::InsertText(data?), ChangeKind::Typing),
+            "insertReplacementText" | "insertFromPaste" | "insertFromDrop" | "insertFromYank" => {
+                (EditOperation::InsertText(data?), ChangeKind::Other)
+            }
+            "insertLineBreak" | "insertParagraph" => {
+                (EditOperation::InsertLineBreak, ChangeKind::Other)
+            }
+            "deleteContentBackward" => (EditOperation::DeleteBackward, ChangeKind::Typing),
+            "deleteContentForward" => (EditOperation::DeleteForward, ChangeKind::Typing),
+            "deleteWordBackward" => (EditOperation::DeleteWordBackward, ChangeKind::Typing),
+            "deleteWordForward" => (EditOperation::DeleteWordForward, ChangeKind::Typing),
+            "deleteSoftLineBackward" | "deleteHardLineBackward" => {
+                (EditOperation::DeleteLineBackward, ChangeKind::Other)
+            }
+            "deleteSoftLineForward" | "deleteHardLineForward" => {
+                (EditOperation::DeleteLineForward, ChangeKind::Other)
+            }
+            "deleteByCut" | "deleteByDrag" | "deleteContent" => {
+                (EditOperation::DeleteSelection, ChangeKind::Other)
+            }
+            "historyUndo" => (EditOperation::Undo, ChangeKind::Other),
+            "historyRedo" => (EditOperation::Redo, ChangeKind::Other),
+            other if other.starts_with("format") || other.starts_with("insert") => {
+                // Formatting and rich content (lists, links, ...) are not
+                // supported by the compose area.
+                (EditOperation::Ignore, ChangeKind::Other)
+            }
+            _ => return None,
+        };
+        Some(Self { operation, kind })
+    }
+}
+
+impl EditOperation {
+    /// Return the operation that applies this operation to the target range
+    /// of a `beforeinput` event instead of the selection (see
+    /// `uses_target_range`). The target range of a deletion already contains
+    /// the deleted characters, so it is simply deleted.
+    pub fn on_target_range(self) -> Self {
+        match self {
+            EditOperation::DeleteBackward
+            | EditOperation::DeleteForward
+            | EditOperation::DeleteWordBackward
+            | EditOperation::DeleteWordForward => EditOperation::DeleteSelection,
+            other => other,
+        }
+    }
+}
+
+/// Return whether an input type must be applied to the target range of the
+/// `beforeinput` event (see `InputEvent.getTargetRanges()`) instead of the
+/// selection.
+///
+/// Spellchecking and autocorrection replace text that is not selected, and
+/// the browser knows best which characters or words are deleted on the
+/// current platform.
+pub fn uses_target_range(input_type: &str) -> bool {
+    matches!(
+        input_type,
+        "insertReplacementText"
+            | "deleteContentBackward"
+            | "deleteContentForward"
+            | "deleteWordBackward"
+            | "deleteWordForward"
+    )
+}
+
+/// Apply an editing operation to the document.
+///
+/// The `selection` contains the logical selection offsets. Return the new
+/// (collapsed) caret offset. Undo, redo and ignored operations must be handled
+/// by the caller, they leave the document unmodified.
+pub fn apply(document: &mut Document, selection: (u32, u32), operation: &EditOperation) -> u32 {
+    let (start, end) = if selection.0 <= selection.1 {
+        selection
+    } else {
+        (selection.1, selection.0)
+    };
+    let collapsed = start == end;
+    match operation {
+        EditOperation::InsertText(text) => {
+            document.replace_range(start, end, &Document::from_text(text))
+        }
+        EditOperation::InsertLineBreak => {
+            document.replace_range(start, end, &Document::from_text("\n"))
+        }
+        EditOperation::Undo | EditOperation::Redo | EditOperation::Ignore => start,
+        _ if !collapsed => document.delete_range(start, end),
+        EditOperation::DeleteSelection => start,
+        EditOperation::DeleteBackward => {
+            let prev = document.prev_offset(start);
+            document.delete_range(prev, start)
+        }
+        EditOperation::DeleteForward => {
+            let next = document.next_offset(start);
+            document.delete_range(start, next)
+        }
+        EditOperation::DeleteWordBackward => {
+            let prev = word_boundary_before(&document.to_text(), start);
+            document.delete_range(prev, start)
+        }
+        EditOperation::DeleteWordForward => {
+            let next = word_boundary_after(&document.to_text(), start);
+            document.delete_range(start, next)
+        }
+        EditOperation::DeleteLineBackward => {
+            let prev = line_boundary_before(&document.to_text(), start);
+            document.delete_range(prev, start)
+        }
+        EditOperation::DeleteLineForward => {
+            let next = line_boundary_after(&document.to_text(), start);
+            document.delete_range(start, next)
+        }
+    }
+}
+
+/// Return the UTF-16 offset of the start of the word before `offset`.
+/// Whitespace directly before the offset is skipped.
+fn word_boundary_before(text: &str, offset: u32) -> u32 {
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let mut i = (offset as usize).min(units.len());
+    while i > 0 && is_whitespace(units[i - 1]) {
+        i -= 1;
+    }
+    while i > 0 && !is_whitespace(units[i - 1]) {
+        i -= 1;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let boundary = i as u32;
+    boundary
+}
+
+/// Return the UTF-16 offset of the end of the word after `offset`.
+/// Whitespace directly after the offset is skipped.
+fn word_boundary_after(text: &str, offset: u32) -> u32 {
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let mut i = (offset as usize).min(units.len());
+    while i < units.len() && is_whitespace(units[i]) {
+        i += 1;
+    }
+    while i < units.len() && !is_whitespace(units[i]) {
+        i += 1;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let boundary = i as u32;
+    boundary
+}
+
+/// Return the UTF-16 offset of the start of the line containing `offset`.
+/// If the offset is already at the start of a line, the preceding line break
+/// is included.
+fn line_boundary_before(text: &str, offset: u32) -> u32 {
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let mut i = (offset as usize).min(units.len());
+    if i > 0 && units[i - 1] == 0x0a {
+        i -= 1;
+    } else {
+        while i > 0 && units[i - 1] != 0x0a {
+            i -= 1;
+        }
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let boundary = i as u32;
+    boundary
+}
+
+/// Return the UTF-16 offset of the end of the line containing `offset`.
+/// If the offset is already at the end of a line, the following line break
+/// is included.
+fn line_boundary_after(text: &str, offset: u32) -> u32 {
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let mut i = (offset as usize).min(units.len());
+    if i < units.len() && units[i] == 0x0a {
+        i += 1;
+    } else {
+        while i < units.len() && units[i] != 0x0a {
+            i += 1;
+        }
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let boundary = i as u32;
+    boundary
+}
+
+/// Return whether the UTF-16 code unit is a whitespace character.
+fn is_whitespace(unit: u16) -> bool {
+    std::char::from_u32(u32::from(unit)).map_or(false, char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::Segment;
+
+    fn edit(input_type: &str, data: Option<&str>) -> EditOperation {
+        Edit::from_input_type(input_type, data.map(str::to_string))
+            .expect("Unsupported input type")
+            .operation
+    }
+
+    #[test]
+    fn from_input_type() {
+        assert_eq!(
+            Edit::from_input_type("insertText", Some("a".into())),
+            Some(Edit {
+                operation: EditOperation::InsertText("a".into()),
+                kind: ChangeKind::Typing,
+            })
+        );
+        assert_eq!(
+            Edit::from_input_type("insertFromPaste", Some("a".into())).map(|e| e.kind),
+            Some(ChangeKind::Other)
+        );
+        assert_eq!(
+            edit("insertParagraph", None),
+            EditOperation::InsertLineBreak
+        );
+        assert_eq!(edit("formatBold", None), EditOperation::Ignore);
+        assert_eq!(edit("insertOrderedList", None), EditOperation::Ignore);
+        assert_eq!(edit("historyUndo", None), EditOperation::Undo);
+        assert_eq!(Edit::from_input_type("insertText", None), None);
+        assert_eq!(Edit::from_input_type("insertCompositionText", None), None);
+    }
+
+    #[test]
+    fn target_range() {
+        assert!(uses_target_range("insertReplacementText"));
+        assert!(uses_target_range("deleteWordBackward"));
+        assert!(!uses_target_range("insertText"));
+        assert!(!uses_target_range("deleteByCut"));
+
+        let mut document = Document::from_text("hello wrold");
+        let operation = edit("insertReplacementText", Some("world")).on_target_range();
+        assert_eq!(apply(&mut document, (6, 11), &operation), 11);
+        assert_eq!(document.to_text(), "hello world");
+
+        let operation = edit("deleteWordBackward", None).on_target_range();
+        assert_eq!(operation, EditOperation::DeleteSelection);
+        assert_eq!(apply(&mut document, (3, 5), &operation), 3);
+        assert_eq!(document.to_text(), "hel world");
+    }
+
+    #[test]
+    fn insert() {
+        let mut document = Document::from_text("ad");
+        assert_eq!(
+            apply(&mut document, (1, 1), &edit("insertText", Some("bc"))),
+            3
+        );
+        assert_eq!(document.to_text(), "abcd");
+
+        assert_eq!(
+            apply(&mut document, (3, 1), &edit("insertLineBreak", None)),
+            2
+        );
+        assert_eq!(document.to_text(), "a\nd");
+    }
+
+    #[test]
+    fn delete_characters() {
+        let mut document = Document::from_segments(vec![
+            Segment::Text("a😀".into()),
+            Segment::image("img.jpg", "🍻", "em"),
+        ]);
+        assert_eq!(
+            apply(&mut document, (5, 5), &edit("deleteContentBackward", None)),
+            3
+        );
+        assert_eq!(document.to_text(), "a😀");
+        assert_eq!(
+            apply(&mut document, (3, 3), &edit("deleteContentBackward", None)),
+            1
+        );
+        assert_eq!(document.to_text(), "a");
+        assert_eq!(
+            apply(&mut document, (0, 0), &edit("deleteContentForward", None)),
+            0
+        );
+        assert!(document.is_empty());
+        assert_eq!(
+            apply(&mut document, (0, 0), &edit("deleteContentBackward", None)),
+            0
+        );
+    }
+
+    #[test]
+    fn delete_selection() {
+        let mut document = Document::from_text("hello world");
+        assert_eq!(
+            apply(&mut document, (2, 8), &edit("deleteContentBackward", None)),
+            2
+        );
+        assert_eq!(document.to_text(), "herld");
+    }
+
+    #[test]
+    fn delete_words() {
+        let mut document = Document::from_text("hello big world");
+        assert_eq!(
+            apply(&mut document, (10, 10), &edit("deleteWordBackward", None)),
+            6
+        );
+        assert_eq!(document.to_text(), "hello world");
+        assert_eq!(
+            apply(&mut document, (5, 5), &edit("deleteWordForward", None)),
+            5
+        );
+        assert_eq!(document.to_text(), "hello");
+    }
+
+    #[test]
+    fn delete_lines() {
+        let mut document = Document::from_text("ab\ncd\nef");
+        assert_eq!(
+            apply(&mut document, (5, 5), &edit("deleteHardLineBackward", None)),
+            3
+        );
+        assert_eq!(document.to_text(), "ab\n\nef");
+        assert_eq!(
+            apply(&mut document, (3, 3), &edit("deleteSoftLineBackward", None)),
+            2
+        );
+        assert_eq!(document.to_text(), "ab\nef");
+        assert_eq!(
+            apply(&mut document, (0, 0), &edit("deleteHardLineForward", None)),
+            0
+        );
+        assert_eq!(document.to_text(), "\nef");
+    }
+}
@@ -0,0 +1,296 @@
+//! Measuring the length of the contents in different units.
+//!
+//! Images are measured by their alt text, like in the text returned by
+//! `get_text`.
+use unicode_segmentation::UnicodeSegmentation;
+use wasm_bindgen::prelude::*;
+
+use crate::model::{Document, Segment};
+
+/// A unit in which the length of the contents can be measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LengthUnit {
+    Utf8Bytes,
+    Utf16,
+    Scalars,
+    Graphemes,
+}
+
+impl LengthUnit {
+    /// Parse a unit name: `utf8`, `utf16`, `scalars` or `graphemes`.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "utf8" => Some(LengthUnit::Utf8Bytes),
+            "utf16" => Some(LengthUnit::Utf16),
+            "scalars" => Some(LengthUnit::Scalars),
+            "graphemes" => Some(LengthUnit::Graphemes),
+            _ => None,
+        }
+    }
+
+    /// Return the length of the text in this unit.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn measure(self, text: &str) -> u32 {
+        (match self {
+            LengthUnit::Utf8Bytes => text.len(),
+            LengthUnit::Utf16 => text.encode_utf16().count(),
+            LengthUnit::Scalars => text.chars().count(),
+            LengthUnit::Graphemes => text.graphemes(true).count(),
+        }) as u32
+    }
+
+    /// Return the byte offsets at which the text may be truncated: The ends
+    /// of all characters (or grapheme clusters).
+    fn boundaries(self, text: &str) -> Vec<usize> {
+        match self {
+            LengthUnit::Graphemes => text
+                .grapheme_indices(true)
+                .map(|(index, grapheme)| index + grapheme.len())
+                .collect(),
+            _ => text
+                .char_indices()
+                .map(|(index, c)| index + c.len_utf8())
+                .collect(),
+        }
+    }
+}
+
+/// Return the length of a document in the specified unit.
+pub(crate) fn measure_document(document: &Document, unit: LengthUnit) -> u32 {
+    unit.measure(&document.to_text())
+}
+
+/// Return the longest prefix of a document for which `length` returns at
+/// most `max` units. Images are only included as a whole, text is never split
+/// within a character (or grapheme cluster).
+///
+/// The `length` function must measure the contents that result from a prefix,
+/// e.g. after inserting it into the existing contents. Lengths are not
+/// additive (a combining mark joins the preceding grapheme cluster), so every
+/// candidate prefix is measured as a whole.
+pub(crate) fn truncate_document<F>(
+    document: &Document,
+    max: u32,
+    unit: LengthUnit,
+    length: F,
+) -> Document
+where
+    F: Fn(&Document) -> u32,
+{
+    let mut truncated = Document::new();
+    for segment in document.segments() {
+        let mut candidate = truncated.clone();
+        candidate.push(segment.clone());
+        if length(&candidate) <= max {
+            truncated = candidate;
+            continue;
+        }
+        if let Segment::Text(text) = segment {
+            let with_prefix = |end: usize| {
+                let mut candidate = truncated.clone();
+                candidate.push(Segment::Text(text[..end].to_string()));
+                candidate
+            };
+            // Binary search for the longest prefix that fits
+            let ends = unit.boundaries(text);
+            let (mut low, mut high) = (0, ends.len());
+            while low < high {
+                let mid = (low + high) / 2;
+                if length(&with_prefix(ends[mid])) <= max {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            if low > 0 {
+                truncated = with_prefix(ends[low - 1]);
+            }
+        }
+        break;
+    }
+    truncated
+}
+
+/// The length of the contents in different units, returned by
+/// `ComposeArea.get_length`.
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentLength {
+    utf8_bytes: u32,
+    utf16: u32,
+    scalars: u32,
+    graphemes: u32,
+}
+
+impl ContentLength {
+    pub(crate) fn of(text: &str) -> Self {
+        Self {
+            utf8_bytes: LengthUnit::Utf8Bytes.measure(text),
+            utf16: LengthUnit::Utf16.measure(text),
+            scalars: LengthUnit::Scalars.measure(text),
+            graphemes: LengthUnit::Graphemes.measure(text),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl ContentLength {
+    /// The number of bytes of the UTF-8 encoded text.
+    pub fn utf8_bytes(&self) -> u32 {
+        self.utf8_bytes
+    }
+
+    /// The number of UTF-16 code units (the length of a JS string).
+    pub fn utf16(&self) -> u32 {
+        self.utf16
+    }
+
+    /// The number of Unicode scalar values (code points).
+    pub fn scalars(&self) -> u32 {
+        self.scalars
+    }
+
+    /// The number of extended grapheme clusters (user-perceived
+    /// characters).
+    pub fn graphemes(&self) -> u32 {
+        self.graphemes
+    }
+}
+
+/// Details about an operation that exceeded the maximum length, passed to
+/// the `on_over_limit` callback.
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitExceeded {
+    length: u32,
+    max_length: u32,
+    rejected: bool,
+}
+
+impl LimitExceeded {
+    pub(crate) fn new(length: u32, max_length: u32, rejected: bool) -> Self {
+        Self {
+            length,
+            max_length,
+            rejected,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl LimitExceeded {
+    /// The length the contents would have had, in the unit of the maximum
+    /// length.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// The maximum length.
+    pub fn max_length(&self) -> u32 {
+        self.max_length
+    }
+
+    /// Whether the operation was rejected. Otherwise, the inserted contents
+    /// were truncated.
+    pub fn rejected(&self) -> bool {
+        self.rejected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNITS: [LengthUnit; 4] = [
+        LengthUnit::Utf8Bytes,
+        LengthUnit::Utf16,
+        LengthUnit::Scalars,
+        LengthUnit::Graphemes,
+    ];
+
+    #[test]
+    fn measure() {
+        let lengths =
+            |text: &str| -> Vec<u32> { UNITS.iter().map(|unit| unit.measure(text)).collect() };
+        assert_eq!(lengths(""), vec![0, 0, 0, 0]);
+        assert_eq!(lengths("abc"), vec![3, 3, 3, 3]);
+        assert_eq!(lengths("äö"), vec![4, 2, 2, 2]);
+        assert_eq!(lengths("e\u{301}"), vec![3, 2, 2, 1]);
+        assert_eq!(lengths("😀"), vec![4, 2, 1, 1]);
+        assert_eq!(lengths("👍🏽"), vec![8, 4, 2, 1]);
+        assert_eq!(lengths("👩‍👩‍👧"), vec![18, 8, 5, 1]);
+    }
+
+    #[test]
+    fn truncate() {
+        let text = "ae\u{301}😀b";
+        let truncated = |unit: LengthUnit, max: u32| {
+            truncate_document(&Document::from_text(text), max, unit, |document| {
+                measure_document(document, unit)
+            })
+            .to_text()
+        };
+        assert_eq!(truncated(LengthUnit::Utf8Bytes, 0), "");
+        assert_eq!(truncated(LengthUnit::Utf8Bytes, 7), "ae\u{301}");
+        assert_eq!(truncated(LengthUnit::Utf8Bytes, 8), "ae\u{301}😀");
+        assert_eq!(truncated(LengthUnit::Utf16, 4), "ae\u{301}");
+        assert_eq!(truncated(LengthUnit::Utf16, 5), "ae\u{301}😀");
+        assert_eq!(truncated(LengthUnit::Scalars, 2), "ae");
+        assert_eq!(truncated(LengthUnit::Graphemes, 2), "ae\u{301}");
+        assert_eq!(truncated(LengthUnit::Graphemes, 10), text);
+    }
+
+    #[test]
+    fn truncate_document_images() {
+        let document = Document::from_segments(vec![
+            Segment::Text("ab".into()),
+            Segment::image("e.png", "😀", "em"),
+            Segment::Text("cd".into()),
+        ]);
+        let truncated = |max: u32, unit: LengthUnit| {
+            truncate_document(&document, max, unit, |document| {
+                measure_document(document, unit)
+            })
+        };
+        assert_eq!(truncated(3, LengthUnit::Utf16).to_text(), "ab");
+        assert_eq!(truncated(5, LengthUnit::Utf16).to_text(), "ab😀c");
+        assert_eq!(truncated(10, LengthUnit::Utf16), document);
+        assert_eq!(truncated(3, LengthUnit::Graphemes).to_text(), "ab😀");
+    }
+
+    #[test]
+    fn truncate_in_context() {
+        // Insert after existing contents, measured in grapheme clusters
+        let length = |before: &str| {
+            let before = Document::from_text(before);
+            move |inserted: &Document| {
+                let mut result = before.clone();
+                result.replace_range(result.len(), result.len(), inserted);
+                measure_document(&result, LengthUnit::Graphemes)
+            }
+        };
+        let inserted = Document::from_text("\u{301}xy");
+        let truncated = |before: &str, max: u32| {
+            truncate_document(&inserted, max, LengthUnit::Graphemes, length(before)).to_text()
+        };
+        // The combining mark joins the existing "e"
+        assert_eq!(truncated("e", 1), "\u{301}");
+        assert_eq!(truncated("e", 2), "\u{301}x");
+        // A regional indicator pair is a single grapheme cluster
+        let flag = Document::from_text("\u{1f1ed}");
+        assert_eq!(
+            truncate_document(&flag, 1, LengthUnit::Graphemes, length("\u{1f1e8}")),
+            flag
+        );
+    }
+
+    #[test]
+    fn unit_names() {
+        assert_eq!(LengthUnit::from_name("utf8"), Some(LengthUnit::Utf8Bytes));
+        assert_eq!(
+            LengthUnit::from_name("graphemes"),
+            Some(LengthUnit::Graphemes)
+        );
+        assert_eq!(LengthUnit::from_name("bytes"), None);
+    }
+}
@@ -13,19 +13,66 @@
 #[macro_use]
 extern crate log;
 
+mod emoji;
+mod error;
 mod extract;
+mod history;
+mod input;
+mod length;
+mod listeners;
+mod markup;
+mod mention;
+pub mod model;
+mod notify;
+mod options;
+mod rect;
+mod render;
+mod sanitize;
 mod selection;
+mod state;
 mod utils;
+mod words;
+
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicU32, Ordering};
 
 use cfg_if::cfg_if;
-use log::Level;
 use wasm_bindgen::{prelude::*, JsCast};
-use web_sys::{self, Element, HtmlDocument, HtmlElement, Node, Range, Selection, Text};
+use web_sys::{
+    self, DataTransfer, DocumentFragment, Element, HtmlElement, InputEvent, KeyboardEvent, Node,
+    Range, Selection,
+};
 
-use crate::extract::extract_text;
+pub use crate::error::ComposeAreaError;
+use crate::error::DomResultExt;
+use crate::extract::Extraction;
+pub use crate::extract::{
+    extract_segments, extract_text, ElementHandler, ElementHandlers, ElementOutput,
+    ExtractedSegment, SegmentKind,
+};
+use crate::history::{ChangeKind, History, Snapshot};
+use crate::input::{uses_target_range, Edit, EditOperation};
+use crate::length::{measure_document, truncate_document};
+pub use crate::length::{ContentLength, LimitExceeded};
+use crate::listeners::Listeners;
+use crate::markup::toggle_markup;
+use crate::mention::replace_mentions;
+use crate::model::{Document, Segment};
+pub use crate::notify::ContentSummary;
+use crate::notify::{Event, Notifier};
+pub use crate::options::ComposeAreaOptions;
+use crate::rect::range_rect;
+pub use crate::rect::CaretRect;
+use crate::render::{create_image, render, render_fragment, update_filler};
+use crate::sanitize::{
+    normalize_newlines, parse_html, sanitize, sanitize_document, token_attributes,
+};
 use crate::selection::{
-    activate_selection_range, glue_range_to_text, set_selection_range, Position,
+    activate_selection_range, find_range, offset_to_position, position_to_offset, range_to_offsets,
+    set_range_positions, set_selection_range, Position,
 };
+use crate::words::WordMode;
 
 cfg_if! {
     // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
@@ -38,8 +85,21 @@ cfg_if! {
 }
 
 /// The context object containing the state.
+///
+/// The methods must not be called re-entrantly, i.e. from a JS function that
+/// the compose area is running: the emoji resolver, the mention resolver,
+/// element handlers, or event listeners that are called synchronously (e.g.
+/// a `focus` listener during `focus`). Such calls fail with a `Busy` error.
 #[wasm_bindgen]
 pub struct ComposeArea {
+    /// The editor is shared with the event listeners that handle input
+    /// events (see `ComposeAreaOptions.handle_beforeinput`).
+    editor: Rc<RefCell<Editor>>,
+}
+
+/// The state of a compose area and the implementation of its methods. The
+/// methods exported to JS are documented on `ComposeArea`.
+pub(crate) struct Editor {
     window: web_sys::Window,
     document: web_sys::Document,
     wrapper: Element,
@@ -49,9 +109,29 @@ pub struct ComposeArea {
     /// NOTE: When setting this value to a range, make sure that the range was
     /// cloned, so that updates to the range in the browser aren't reflected in
     /// this instance.
-    selection_range: Option<Range>,
+    ///
+    /// The range is shared with the event listeners that track the selection.
+    selection_range: Rc<RefCell<Option<Range>>>,
+    /// The document model. It is parsed from the DOM whenever the selection
+    /// range is stored and rendered into the DOM on programmatic changes.
+    model: Document,
+    /// The stored selection range as logical offsets into the document model.
+    model_selection: Option<(u32, u32)>,
+    /// The undo / redo history.
+    history: History,
+    /// Unique ID of this instance, used as part of element IDs.
+    instance_id: u32,
     /// Counter used for creating unique element IDs.
     counter: u32,
+    /// The options passed to `bind_with_options`.
+    options: ComposeAreaOptions,
+    /// Custom handlers used when extracting the contents from the DOM.
+    element_handlers: ElementHandlers,
+    /// The event listeners attached by the compose area (`None` after
+    /// `destroy`).
+    listeners: Option<Listeners>,
+    /// Dispatches change notifications to the registered callbacks.
+    notifier: Notifier,
 }
 
 /// This enum is relevant when determining the current node while the caret is
@@ -107,6 +187,19 @@ impl RangeResult {
         }
     }
 
+    fn format_boundary(
+        container: Result<Node, JsValue>,
+        offset: Result<u32, JsValue>,
+        separator: &str,
+    ) -> String {
+        match (container, offset) {
+            (Ok(node), Ok(offset)) => {
+                format!("{}{}{}", Self::format_node(&node), separator, offset)
+            }
+            _ => "?".to_string(),
+        }
+    }
+
     /// Used by JS code to show a string representation of the range.
     #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
@@ -115,13 +208,11 @@ impl RangeResult {
             (None, _) => "None".to_string(),
             (Some(range), false) => format!(
                 "Range {{\n  \
-                  start: {} ~ {}\n  \
-                  end: {} ~ {}\n\
+                  start: {}\n  \
+                  end: {}\n\
                 }}",
-                Self::format_node(&range.start_container().unwrap()),
-                &range.start_offset().unwrap(),
-                Self::format_node(&range.end_container().unwrap()),
-                &range.end_offset().unwrap(),
+                Self::format_boundary(range.start_container(), range.start_offset(), " ~ "),
+                Self::format_boundary(range.end_container(), range.end_offset(), " ~ "),
             ),
         }
     }
@@ -132,29 +223,136 @@ impl RangeResult {
             (_, true) => "Outside".to_string(),
             (None, _) => "None".to_string(),
             (Some(range), false) => format!(
-                "Range({}~{}, {}~{})",
-                Self::format_node(&range.start_container().unwrap()),
-                &range.start_offset().unwrap(),
-                Self::format_node(&range.end_container().unwrap()),
-                &range.end_offset().unwrap(),
+                "Range({}, {})",
+                Self::format_boundary(range.start_container(), range.start_offset(), "~"),
+                Self::format_boundary(range.end_container(), range.end_offset(), "~"),
             ),
         }
     }
 }
 
+/// Counter used for assigning a unique ID to every compose area instance.
+static INSTANCE_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// Methods that are only available from Rust.
+impl ComposeArea {
+    /// Borrow the editor.
+    ///
+    /// This fails with a `Busy` error if the editor is already in use, i.e.
+    /// if a JS function called by the compose area (e.g. the emoji resolver
+    /// or an element handler) calls one of its methods.
+    fn editor(&self) -> Result<Ref<Editor>, ComposeAreaError> {
+        self.editor.try_borrow().ok().ok_or(ComposeAreaError::Busy)
+    }
+
+    /// Borrow the editor mutably. See `editor`.
+    fn editor_mut(&self) -> Result<RefMut<Editor>, ComposeAreaError> {
+        self.editor
+            .try_borrow_mut()
+            .ok()
+            .ok_or(ComposeAreaError::Busy)
+    }
+
+    /// Replace the element handlers (see `set_tag_handler`).
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called from an element handler.
+    pub fn set_element_handlers(
+        &mut self,
+        handlers: ElementHandlers,
+    ) -> Result<(), ComposeAreaError> {
+        let mut editor = self.editor_mut()?;
+        editor.element_handlers = handlers;
+        editor.element_handlers_changed();
+        Ok(())
+    }
+
+    /// Return a copy of the registered element handlers.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called from an element handler.
+    pub fn element_handlers(&self) -> Result<ElementHandlers, ComposeAreaError> {
+        Ok(self.editor()?.element_handlers.clone())
+    }
+}
+
+/// Wrap a JS function as element handler (see
+/// `ComposeArea::set_tag_handler`).
+fn js_element_handler(handler: js_sys::Function) -> ElementHandler {
+    Rc::new(move |element| {
+        let output = handler.call1(&JsValue::NULL, element).ok()?;
+        if output.is_undefined() || output.is_null() {
+            return None;
+        }
+        Some(ElementOutput {
+            text: utils::get_string_property(&output, "text").unwrap_or_default(),
+            recurse: js_sys::Reflect::get(&output, &"recurse".into())
+                .ok()
+                .and_then(|recurse| recurse.as_bool())
+                .unwrap_or(false),
+        })
+    })
+}
+
+/// Replace the emoji in the text segments of a document with images, using a
+/// JS resolver function (see `ComposeArea::set_emoji_resolver`).
+fn resolve_emoji(resolver: &js_sys::Function, document: &Document) -> Document {
+    emoji::replace_emoji(document, |emoji| {
+        let image = resolver.call1(&JsValue::NULL, &emoji.into()).ok()?;
+        if image.is_undefined() || image.is_null() {
+            return None;
+        }
+        let src = utils::get_string_property(&image, "src")?;
+        let cls = utils::get_string_property(&image, "cls").unwrap_or_default();
+        Some((src, cls))
+    })
+}
+
+/// A selection range, expressed as logical offsets into the text of the
+/// compose area. See `ComposeArea::get_selection_offsets`.
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectionOffsets {
+    start: u32,
+    end: u32,
+}
+
+#[wasm_bindgen]
+impl SelectionOffsets {
+    /// Return the offset where the selection starts.
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    /// Return the offset where the selection ends.
+    pub fn end(&self) -> u32 {
+        self.end
+    }
+
+    /// Return whether the selection is collapsed (i.e. only a caret).
+    pub fn is_collapsed(&self) -> bool {
+        self.start == self.end
+    }
+}
+
 #[wasm_bindgen]
 #[derive(Debug, Clone)]
 pub struct WordAtCaret {
-    node: Node,
+    range: Range,
     before: String,
     after: String,
     offsets: (u32, u32),
+    mode: WordMode,
 }
 
 #[wasm_bindgen]
 impl WordAtCaret {
-    pub fn node(&self) -> Node {
-        self.node.clone()
+    /// Return a range spanning the word. The range may start and end in
+    /// different nodes.
+    pub fn range(&self) -> Range {
+        self.range.clone_range()
     }
 
     pub fn before(&self) -> String {
@@ -165,15 +363,23 @@ impl WordAtCaret {
         self.after.clone()
     }
 
-    /// Return the UTF16 offset from the start node where the current word starts (inclusive).
+    /// Return the logical offset where the current word starts (inclusive),
+    /// see `ComposeArea.get_selection_offsets`.
     pub fn start_offset(&self) -> u32 {
         self.offsets.0
     }
 
-    /// Return the UTF16 offset from the start node where the current word ends (exclusive).
+    /// Return the logical offset where the current word ends (exclusive),
+    /// see `ComposeArea.get_selection_offsets`.
     pub fn end_offset(&self) -> u32 {
         self.offsets.1
     }
+
+    /// Return the word mode that was used to find the word: `boundaries`,
+    /// `unicode` or `token` (see `ComposeAreaOptions.word_mode`).
+    pub fn mode(&self) -> String {
+        self.mode.name().to_string()
+    }
 }
 
 #[wasm_bindgen]
@@ -183,946 +389,4346 @@ impl ComposeArea {
     /// If the `log_level` argument is supplied, the console logger is
     /// initialized. Valid log levels: `trace`, `debug`, `info`, `warn` or
     /// `error`.
-    pub fn bind_to(wrapper: Element, log_level: Option<String>) -> Self {
-        utils::set_panic_hook();
+    ///
+    /// This is a shortcut for `bind_with_options` with the default options.
+    ///
+    /// # Errors
+    ///
+    /// See `bind_with_options`.
+    #[allow(clippy::needless_pass_by_value)] // Optional arguments must be owned
+    pub fn bind_to(wrapper: Element, log_level: Option<String>) -> Result<ComposeArea, JsValue> {
+        let options = match log_level {
+            Some(level) => ComposeAreaOptions::new().log_level(&level),
+            None => ComposeAreaOptions::new(),
+        };
+        Self::bind_with_options(wrapper, &options)
+    }
 
-        // Set log level
-        if let Some(level) = log_level {
-            match &*level {
-                "trace" => utils::init_log(Level::Trace),
-                "debug" => utils::init_log(Level::Debug),
-                "info" => utils::init_log(Level::Info),
-                "warn" => utils::init_log(Level::Warn),
-                "error" => utils::init_log(Level::Error),
-                other => web_sys::console::warn_1(
-                    &format!("bind_to: Invalid log level: {}", other).into(),
-                ),
-            }
-        }
-        trace!("[compose_area] bind_to");
+    /// Initialize a new compose area wrapper with the specified options (see
+    /// `ComposeAreaOptions`).
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NoDocument` error if there is no global `window` or
+    /// `document`, or a `Dom` error if the wrapper element cannot be
+    /// initialized or the event listeners cannot be attached.
+    pub fn bind_with_options(
+        wrapper: Element,
+        options: &ComposeAreaOptions,
+    ) -> Result<ComposeArea, JsValue> {
+        let editor = Rc::new(RefCell::new(Editor::new(wrapper, options)?));
+        editor.borrow_mut().handle_events(&Rc::downgrade(&editor))?;
+        Ok(Self { editor })
+    }
 
-        let window = web_sys::window().expect("No global `window` exists");
-        let document = window.document().expect("Should have a document on window");
+    /// Detach the compose area from the wrapper element: Remove the event
+    /// listeners, the wrapper classes and the `contenteditable` attribute.
+    ///
+    /// Pending notifications are cancelled and the callbacks are removed.
+    ///
+    /// The contents of the wrapper are kept, so it can be bound again.
+    /// Freeing the compose area removes the event listeners as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the wrapper classes or the `contenteditable`
+    /// attribute cannot be removed. Returns a `Busy` error if called
+    /// re-entrantly (see `ComposeArea`).
+    pub fn destroy(&mut self) -> Result<(), JsValue> {
+        self.editor_mut()?.destroy()
+    }
 
-        // Initialize the wrapper element
-        wrapper
-            .class_list()
-            .add_2("cawrapper", "initialized")
-            .expect("Could not add wrapper classes");
-        wrapper
-            .set_attribute("contenteditable", "true")
-            .expect("Could not set contenteditable attr");
+    /// Store the current selection range.
+    /// Return the stored range.
+    ///
+    /// Note: Unless disabled with `ComposeAreaOptions.track_selection`, the
+    /// selection range is stored automatically.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NoSelection` error if the window selection cannot be
+    /// accessed. Returns a `Busy` error if called re-entrantly (see
+    /// `ComposeArea`).
+    pub fn store_selection_range(&mut self) -> Result<RangeResult, JsValue> {
+        self.editor_mut()?.store_selection_range()
+    }
 
-        info!("[compose_area] Initialized");
+    /// Return the stored selection range as logical offsets.
+    ///
+    /// The offsets are measured in UTF-16 code units (like JS string
+    /// indices) of the text returned by `get_text(true)`. Images count with
+    /// the length of their alt text.
+    ///
+    /// Note: This methods uses the range that was last set with
+    /// `store_selection_range`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn get_selection_offsets(&mut self) -> Result<Option<SelectionOffsets>, JsValue> {
+        Ok(self.editor_mut()?.get_selection_offsets())
+    }
 
-        Self {
-            window,
-            document,
-            wrapper,
-            selection_range: None,
-            counter: 0,
-        }
+    /// Select the text between the specified logical offsets (see
+    /// `get_selection_offsets`) and store the selection range. If `end` is
+    /// omitted, the selection is collapsed at `start`. Offsets pointing into an
+    /// image are moved to the image boundary.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NoSelection` or `Dom` error if the window selection cannot be
+    /// set. Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn set_selection_offsets(&mut self, start: u32, end: Option<u32>) -> Result<(), JsValue> {
+        self.editor_mut()?.set_selection_offsets(start, end)
     }
 
-    /// Store the current selection range.
-    /// Return the stored range.
-    pub fn store_selection_range(&mut self) -> RangeResult {
-        trace!("[compose_area] store_selection_range");
-        let range_result = self.fetch_range();
-        trace!(
-            "[compose_area]   Range: {}",
-            range_result.to_string().replace('\n', "")
-        );
+    /// Undo the last change.
+    ///
+    /// Return a boolean indicating whether there was a change to undo.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the contents cannot be rendered, or a
+    /// `NoSelection` error if the caret cannot be moved because the window
+    /// selection is not accessible. Returns a `Busy` error if called
+    /// re-entrantly (see `ComposeArea`).
+    pub fn undo(&mut self) -> Result<bool, JsValue> {
+        self.editor_mut()?.undo()
+    }
 
-        // Ignore selections outside the wrapper
-        if !range_result.outside {
-            // Note: We need to clone the range object. Otherwise, changes to the
-            // range in the DOM will be reflected in our stored reference.
-            self.selection_range = range_result.clone().range.map(|range| range.clone_range());
-        }
+    /// Redo the last undone change.
+    ///
+    /// Return a boolean indicating whether there was a change to redo.
+    ///
+    /// # Errors
+    ///
+    /// See `undo`.
+    pub fn redo(&mut self) -> Result<bool, JsValue> {
+        self.editor_mut()?.redo()
+    }
 
-        range_result
+    /// Return whether there is a change that can be undone.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn can_undo(&self) -> Result<bool, JsValue> {
+        Ok(self.editor()?.can_undo())
+    }
+
+    /// Return whether there is a change that can be redone.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn can_redo(&self) -> Result<bool, JsValue> {
+        Ok(self.editor()?.can_redo())
     }
 
     /// Restore the stored selection range.
     ///
     /// Return a boolean indicating whether a selection range was stored (and
     /// thus restored).
-    pub fn restore_selection_range(&self) -> bool {
-        trace!("[compose_area] restore_selection_range");
-        if let Some(ref range) = self.selection_range {
-            // Get the current selection
-            let selection = match self.fetch_selection() {
-                Some(selection) => selection,
-                None => {
-                    error!("[compose_area] No selection found");
-                    return false;
-                }
-            };
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DetachedWrapper` error if the wrapper element is not attached
+    /// to the document, or a `NoSelection` or `Dom` error if the window
+    /// selection cannot be set. Returns a `Busy` error if called re-entrantly
+    /// (see `ComposeArea`).
+    pub fn restore_selection_range(&self) -> Result<bool, JsValue> {
+        self.editor()?.restore_selection_range()
+    }
 
-            // Restore the range
-            if selection.remove_all_ranges().is_err() {
-                error!("[compose_area] Removing all ranges failed");
-            }
-            match selection.add_range(range) {
-                Ok(_) => true,
-                Err(_) => {
-                    error!("[compose_area] Adding range failed");
-                    false
-                }
-            }
-        } else {
-            trace!("[compose_area]   No stored range");
-            false
-        }
-    }
-
-    /// Ensure that there's an active selection inside the compose are. Then
-    /// exec the specified command, normalize the compose area and store the
-    /// new selection range.
-    fn exec_command(&mut self, command_id: &str, value: &str) {
-        // Ensure that there's an active selection inside the compose area.
-        let active_range = self.fetch_range();
-        if active_range.range.is_none() || active_range.outside {
-            // No active selection range inside the compose area.
-            match self.selection_range {
-                Some(ref range) => {
-                    activate_selection_range(
-                        &self
-                            .fetch_selection()
-                            .expect("Could not get window selection"),
-                        range,
-                    );
-                }
-                None => {
-                    // No stored selection range. Create a new selection at the end end.
-                    let last_child_node = utils::get_last_child(&self.wrapper);
-                    self.selection_range = match last_child_node {
-                        Some(ref node) => {
-                            // Insert at the very end, unless the last element in the
-                            // area is a `<br>` node. This is needed because Firefox
-                            // always adds a trailing newline that isn't rendered
-                            let mut insert_before = false;
-                            if let Some(ref element) = node.dyn_ref::<Element>() {
-                                if element.tag_name() == "BR" {
-                                    insert_before = true;
-                                }
-                            }
-                            if insert_before {
-                                set_selection_range(&Position::Before(node), None)
-                            } else {
-                                set_selection_range(&Position::After(node), None)
-                            }
-                        }
-                        None => set_selection_range(&Position::Offset(&self.wrapper, 0), None),
-                    }
-                    .map(|range| range.clone_range());
-                }
-            }
-        }
+    /// Handle a `beforeinput` event.
+    ///
+    /// Supported input types (typing, deleting, line breaks, pasting, ...) are
+    /// applied to the document model instead of letting the browser modify
+    /// the DOM. In that case, the default action of the event is prevented and
+    /// `true` is returned. Other input types (e.g. IME composition) are left to
+    /// the browser.
+    ///
+    /// Replacements (e.g. by spellchecking) and character or word deletions
+    /// are applied to the target range of the event. Replacements without a
+    /// target range are left to the browser.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the contents cannot be rendered, or a
+    /// `NoSelection` error if the caret cannot be moved because the window
+    /// selection is not accessible. Returns a `Busy` error if called
+    /// re-entrantly (see `ComposeArea`).
+    pub fn handle_beforeinput(&mut self, event: &InputEvent) -> Result<bool, JsValue> {
+        self.editor_mut()?.handle_beforeinput(event)
+    }
 
-        // Execute command
-        self.document
-            .dyn_ref::<HtmlDocument>()
-            .expect("Document is not a HtmlDocument")
-            .exec_command_with_show_ui_and_value(command_id, false, value)
-            .expect("Could not exec command");
-        self.normalize();
-        self.store_selection_range();
+    /// Update the markup highlighting (see
+    /// `ComposeAreaOptions.highlight_markup`) after the browser modified the
+    /// contents, e.g. at the end of an IME composition. The caret stays at
+    /// the same logical offset.
+    ///
+    /// Changes applied by `handle_beforeinput` and the other methods of the
+    /// compose area are highlighted automatically. Call this method on
+    /// `input` events that were not handled by `handle_beforeinput`, but not
+    /// during a composition. Without highlighting, it does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the contents cannot be rendered, or a
+    /// `NoSelection` error if the caret cannot be moved because the window
+    /// selection is not accessible. Returns a `Busy` error if called
+    /// re-entrantly (see `ComposeArea`).
+    pub fn update_markup(&mut self) -> Result<(), JsValue> {
+        self.editor_mut()?.update_markup()
     }
 
-    /// Return and increment the counter variable.
-    fn get_counter(&mut self) -> u32 {
-        let val = self.counter;
-        self.counter += 1;
-        val
+    /// Wrap the stored selection in bold markup (`*`), or remove the markup
+    /// if the selection is already wrapped. See `toggle_markup`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the contents cannot be rendered, or a
+    /// `NoSelection` error if the caret cannot be moved because the window
+    /// selection is not accessible. Returns a `Busy` error if called
+    /// re-entrantly (see `ComposeArea`).
+    pub fn toggle_bold(&mut self) -> Result<bool, JsValue> {
+        self.editor_mut()?.toggle_bold()
     }
 
-    /// Insert an image at the current caret position.
+    /// Wrap the stored selection in italic markup (`_`), or remove the
+    /// markup if the selection is already wrapped. See `toggle_markup`.
     ///
-    /// Return a reference to the inserted image element.
-    pub fn insert_image(&mut self, src: &str, alt: &str, cls: &str) -> HtmlElement {
-        debug!("[compose_area] insert_image ({})", &alt);
+    /// # Errors
+    ///
+    /// See `toggle_bold`.
+    pub fn toggle_italic(&mut self) -> Result<bool, JsValue> {
+        self.editor_mut()?.toggle_italic()
+    }
 
-        // NOTE: Ideally we'd create an image node here and would then use
-        //       `insert_node`. But unfortunately that will not modify the undo
-        //       stack of the browser (see https://stackoverflow.com/a/15895618).
-        //       Thus, we need to resort to an ugly `execCommand` with a HTML
-        //       string. Furthermore, we need to create a random ID in order
-        //       to be able to find the image again in the DOM.
-
-        let img_id = format!("__$$compose_area_img_{}", self.get_counter());
-        let html = format!(
-            "<img id=\"{}\" src=\"{}\" alt=\"{}\" class=\"{}\">",
-            img_id,
-            src.replace('"', ""),
-            alt.replace('"', ""),
-            cls.replace('"', ""),
-        );
-        self.insert_html(&html);
+    /// Wrap the stored selection in strikethrough markup (`~`), or remove the
+    /// markup if the selection is already wrapped. See `toggle_markup`.
+    ///
+    /// # Errors
+    ///
+    /// See `toggle_bold`.
+    pub fn toggle_strikethrough(&mut self) -> Result<bool, JsValue> {
+        self.editor_mut()?.toggle_strikethrough()
+    }
 
-        self.document
-            .get_element_by_id(&img_id)
-            .expect("Could not find inserted image node")
-            .dyn_into::<HtmlElement>()
-            .expect("Could not cast image element into HtmlElement")
+    /// Wrap the stored selection in monospace markup (`` ` ``), or remove the
+    /// markup if the selection is already wrapped. See `toggle_markup`.
+    ///
+    /// # Errors
+    ///
+    /// See `toggle_bold`.
+    pub fn toggle_monospace(&mut self) -> Result<bool, JsValue> {
+        self.editor_mut()?.toggle_monospace()
     }
 
-    /// Insert plain text at the current caret position.
-    pub fn insert_text(&mut self, text: &str) {
-        debug!("[compose_area] insert_text ({})", text);
-        self.exec_command("insertText", text);
+    /// Handle a `keydown` event.
+    ///
+    /// The formatting shortcuts Ctrl+B (`toggle_bold`) and Ctrl+I
+    /// (`toggle_italic`) are applied to the selection (Cmd instead of Ctrl
+    /// on macOS). If this changed the contents, the default action of the
+    /// event is prevented and `true` is returned. Other keys (and shortcuts
+    /// that did not change anything) are left to the browser.
+    ///
+    /// # Errors
+    ///
+    /// See `toggle_bold`.
+    pub fn handle_keydown(&mut self, event: &KeyboardEvent) -> Result<bool, JsValue> {
+        self.editor_mut()?.handle_keydown(event)
     }
 
-    /// Insert HTML at the current caret position.
+    /// Handle a `paste` event.
     ///
-    /// Note: This is potentially dangerous, make sure that you only insert
-    /// HTML from trusted sources!
-    pub fn insert_html(&mut self, html: &str) {
-        debug!("[compose_area] insert_html ({})", html);
-        self.exec_command("insertHTML", html);
+    /// The clipboard contents are inserted like with `paste`. The default
+    /// action of the event is prevented and `true` is returned, unless the
+    /// event has no clipboard data.
+    ///
+    /// # Errors
+    ///
+    /// See `paste`.
+    pub fn handle_paste(&mut self, event: &web_sys::Event) -> Result<bool, JsValue> {
+        self.editor_mut()?.handle_paste(event)
     }
 
-    /// Insert the specified node at the previously stored selection range.
-    /// Set the caret position to right after the newly inserted node.
+    /// Insert pasted contents in place of the current selection.
     ///
-    /// **NOTE:** Due to browser limitations, this will not result in a new
-    /// entry in the browser's internal undo stack. This means that the node
-    /// insertion cannot be undone using Ctrl+Z.
-    pub fn insert_node(&mut self, node_ref: &Node) {
-        debug!("[compose_area] insert_node");
+    /// The HTML is sanitized: Only text, line breaks and images with a safe
+    /// source and one of the configured classes (see
+    /// `ComposeAreaOptions.paste_image_classes`) are kept. If the HTML is
+    /// empty (or contains nothing that is kept), the plain text is inserted
+    /// instead. Emoji are replaced and the contents are truncated to the
+    /// maximum length, like typed text.
+    ///
+    /// The insertion is recorded as a single step in the undo history.
+    /// Return whether anything was inserted.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the HTML cannot be parsed or the contents
+    /// cannot be rendered, or a `NoSelection` error if the caret cannot be
+    /// moved. Returns a `Busy` error if called re-entrantly (see
+    /// `ComposeArea`).
+    pub fn paste(&mut self, html: &str, text: &str) -> Result<bool, JsValue> {
+        self.editor_mut()?.paste(html, text)
+    }
 
-        // Insert the node
-        if let Some(ref range) = self.selection_range {
-            range
-                .delete_contents()
-                .expect("Could not remove selection contents");
-            range.insert_node(node_ref).expect("Could not insert node");
-        } else {
-            // No current selection. Append at end, unless the last element in
-            // the area is a `<br>` node. This is needed because Firefox always
-            // adds a trailing newline that isn't rendered.
-            let last_child_node = utils::get_last_child(&self.wrapper);
-            match last_child_node.and_then(|n| n.dyn_into::<Element>().ok()) {
-                Some(ref element) if element.tag_name() == "BR" => {
-                    self.wrapper
-                        .insert_before(node_ref, Some(element))
-                        .expect("Could not insert child");
-                }
-                Some(_) | None => {
-                    self.wrapper
-                        .append_child(node_ref)
-                        .expect("Could not append child");
-                }
-            };
-        }
+    /// Handle a `copy` event.
+    ///
+    /// The selected contents are written to the clipboard as `text/plain`
+    /// (using the same rules as `extract_text`) and in a private format, so
+    /// that pasting them into a compose area restores the images exactly.
+    /// The default action of the event is prevented and `true` is returned,
+    /// unless the selection is empty or the event has no clipboard data.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the clipboard data cannot be set. Returns a
+    /// `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn handle_copy(&mut self, event: &web_sys::Event) -> Result<bool, JsValue> {
+        self.editor_mut()?.handle_copy(event)
+    }
 
-        // Update selection
-        self.selection_range =
-            set_selection_range(&Position::After(node_ref), None).map(|range| range.clone_range());
+    /// Handle a `cut` event.
+    ///
+    /// Like `handle_copy`, but the selected contents are removed afterwards.
+    /// The removal is recorded in the undo history.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the clipboard data cannot be set or the
+    /// contents cannot be rendered, or a `NoSelection` error if the caret
+    /// cannot be moved. Returns a `Busy` error if called re-entrantly (see
+    /// `ComposeArea`).
+    pub fn handle_cut(&mut self, event: &web_sys::Event) -> Result<bool, JsValue> {
+        self.editor_mut()?.handle_cut(event)
+    }
 
-        // Normalize elements
-        self.normalize();
+    /// Return the length of the contents in different units. Images are
+    /// measured by their alt text.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn get_length(&mut self) -> Result<ContentLength, JsValue> {
+        Ok(self.editor_mut()?.get_length())
     }
 
-    /// Normalize the contents of the wrapper element.
+    /// Register a callback that is called when typed, pasted or inserted
+    /// contents exceed the maximum length (see
+    /// `ComposeAreaOptions.max_length`). Pass `undefined` to remove it.
     ///
-    /// See https://developer.mozilla.org/en-US/docs/Web/API/Node/normalize
-    fn normalize(&self) {
-        trace!("[compose_area]   normalize");
-        self.wrapper.normalize();
+    /// The callback is called with a `LimitExceeded` object right after the
+    /// operation (without debouncing).
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn on_over_limit(&self, callback: Option<js_sys::Function>) -> Result<(), JsValue> {
+        self.editor()?.on_over_limit(callback);
+        Ok(())
     }
 
-    /// Return the DOM selection.
-    fn fetch_selection(&self) -> Option<Selection> {
-        trace!("[compose_area]   fetch_selection");
-        self.window
-            .get_selection()
-            .expect("Could not get selection from window")
+    /// Enable automatic replacement of emoji in typed, pasted and inserted
+    /// text with images. Pass `undefined` to disable it again.
+    ///
+    /// The resolver function is called with every emoji sequence (e.g.
+    /// `"👍🏽"`) and must return an object with a `src` and an optional `cls`
+    /// property, or `undefined` if the emoji should be kept as text. The emoji
+    /// is used as alt text of the image, so `get_text` still returns the
+    /// original codepoints.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn set_emoji_resolver(
+        &mut self,
+        resolver: Option<js_sys::Function>,
+    ) -> Result<(), JsValue> {
+        self.editor_mut()?.set_emoji_resolver(resolver);
+        Ok(())
     }
 
-    /// Return the last range of the selection that is within the wrapper
-    /// element.
-    pub fn fetch_range(&self) -> RangeResult {
-        trace!("[compose_area] fetch_range");
-        let selection = match self.fetch_selection() {
-            Some(sel) => sel,
-            None => {
-                error!("[compose_area] Could not find selection");
-                return RangeResult::none();
-            }
-        };
-        let mut candidate: Option<Range> = None;
-        for i in 0..selection.range_count() {
-            let range = selection
-                .get_range_at(i)
-                .expect("Could not get range from selection");
-            candidate = Some(range.clone());
-            let container = range
-                .common_ancestor_container()
-                .expect("Could not get common ancestor container for range");
-            if self.wrapper.contains(Some(&container)) {
-                return RangeResult::contained(range);
-            }
-        }
-        match candidate {
-            Some(range) => RangeResult::outside(range),
-            None => RangeResult::none(),
-        }
+    /// Register a callback that is called when the contents change, either
+    /// by user input or programmatically. Pass `undefined` to remove it.
+    ///
+    /// The callback is called with a `ContentSummary`. Changes are debounced
+    /// (see `ComposeAreaOptions.change_debounce`).
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn on_change(&self, callback: Option<js_sys::Function>) -> Result<(), JsValue> {
+        self.editor()?.on_change(callback);
+        Ok(())
     }
 
-    /// Extract the text in the compose area.
+    /// Register a callback that is called when the stored selection range
+    /// changes. Pass `undefined` to remove it.
     ///
-    /// Convert elements like images to alt text.
+    /// The callback is called with the new `SelectionOffsets` (or
+    /// `undefined`). Changes are debounced.
     ///
-    /// Args:
-    /// - `no_trim`: If set to `true`, don't trim leading / trailing whitespace
-    ///   from returned text. Default: `false`.
-    pub fn get_text(&self, no_trim: Option<bool>) -> String {
-        debug!("[compose_area] get_text");
-        extract_text(&self.wrapper, no_trim.unwrap_or(false))
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn on_selection_change(&self, callback: Option<js_sys::Function>) -> Result<(), JsValue> {
+        self.editor()?.on_selection_change(callback);
+        Ok(())
     }
 
-    /// Focus the compose area.
-    pub fn focus(&self) {
-        debug!("[compose_area] focus");
-        self.restore_selection_range();
-        if let Some(e) = self.wrapper.dyn_ref::<HtmlElement>() {
-            e.focus()
-                .unwrap_or_else(|_| error!("[compose_area] Could not focus compose area"));
-        }
+    /// Register a callback that is called when the compose area gains the
+    /// focus. Pass `undefined` to remove it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn on_focus(&self, callback: Option<js_sys::Function>) -> Result<(), JsValue> {
+        self.editor()?.on_focus(callback);
+        Ok(())
     }
 
-    /// Clear the contents of the compose area.
-    pub fn clear(&mut self) {
-        debug!("[compose_area] clear");
-        while self.wrapper.has_child_nodes() {
-            let last_child = self
-                .wrapper
-                .last_child()
-                .expect("Could not find last child");
-            self.wrapper
-                .remove_child(&last_child)
-                .expect("Could not remove last child");
-        }
-        self.selection_range = None;
+    /// Register a callback that is called when the compose area loses the
+    /// focus. Pass `undefined` to remove it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn on_blur(&self, callback: Option<js_sys::Function>) -> Result<(), JsValue> {
+        self.editor()?.on_blur(callback);
+        Ok(())
     }
 
-    /// Return the word (whitespace delimited) at the current caret position.
+    /// Register a callback that is called when the compose area becomes
+    /// empty or non-empty (e.g. to show a placeholder). Pass `undefined` to
+    /// remove it.
     ///
-    /// Note: This methods uses the range that was last set with
-    /// `store_selection_range`.
-    pub fn get_word_at_caret(&mut self) -> Option<WordAtCaret> {
-        debug!("[compose_area] get_word_at_caret");
+    /// The callback is called with a boolean indicating whether the compose
+    /// area is empty. Changes are debounced.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn on_empty_state_change(&self, callback: Option<js_sys::Function>) -> Result<(), JsValue> {
+        self.editor()?.on_empty_state_change(callback);
+        Ok(())
+    }
+
+    /// Register a handler that decides how elements with the specified tag
+    /// name are extracted (e.g. for `get_text`). Pass `undefined` to remove
+    /// the handler again.
+    ///
+    /// The handler is called with the element. It must return an object with
+    /// a `text` property (the text the element produces) and a `recurse`
+    /// property (whether the child nodes should be visited), or `undefined`
+    /// to use the default behavior.
+    ///
+    /// Note: Handled elements are converted to their text representation when
+    /// the contents are modified by the compose area (e.g. on undo). Mentions
+    /// and tokens (see `insert_token`) are never passed to handlers.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn set_tag_handler(
+        &mut self,
+        tag: &str,
+        handler: Option<js_sys::Function>,
+    ) -> Result<(), JsValue> {
+        self.editor_mut()?.set_tag_handler(tag, handler);
+        Ok(())
+    }
+
+    /// Register a handler that decides how elements with the specified CSS
+    /// class are extracted. Class handlers take precedence over tag handlers.
+    /// Pass `undefined` to remove the handler again.
+    ///
+    /// See `set_tag_handler` for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn set_class_handler(
+        &mut self,
+        cls: &str,
+        handler: Option<js_sys::Function>,
+    ) -> Result<(), JsValue> {
+        self.editor_mut()?.set_class_handler(cls, handler);
+        Ok(())
+    }
+
+    /// Insert an image at the current caret position.
+    ///
+    /// Return a reference to the inserted image element.
+    ///
+    /// Unless disabled with `set_strip_image_ids`, the image gets an ID that
+    /// is unique across all compose area instances on the page.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the image cannot be inserted, or a
+    /// `NoSelection` error if the caret cannot be moved after it. Returns a
+    /// `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn insert_image(
+        &mut self,
+        src: &str,
+        alt: &str,
+        cls: &str,
+    ) -> Result<Option<HtmlElement>, JsValue> {
+        self.editor_mut()?.insert_image(src, alt, cls)
+    }
+
+    /// Specify whether images inserted with `insert_image` should not get an
+    /// ID attribute. The compose area itself does not need the IDs, they are
+    /// only provided for convenience. Default: `false`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn set_strip_image_ids(&mut self, strip: bool) -> Result<(), JsValue> {
+        self.editor_mut()?.set_strip_image_ids(strip);
+        Ok(())
+    }
+
+    /// Insert a mention of a contact at the current caret position.
+    ///
+    /// The mention is displayed as a non-editable element showing the
+    /// `display_name`. It is handled as a single unit: The caret skips over
+    /// it and it is deleted as a whole. In the text representation (e.g.
+    /// `get_text`), it is represented by a token containing the ID (see
+    /// `ComposeAreaOptions.mention_format`), so the ID must only consist of
+    /// ASCII letters, digits, `_` and `-`.
+    ///
+    /// Return whether the mention was inserted (it is not inserted if the ID
+    /// is invalid or if it exceeds the maximum length).
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the mention cannot be rendered, or a
+    /// `NoSelection` error if the caret cannot be moved after it. Returns a
+    /// `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn insert_mention(&mut self, id: &str, display_name: &str) -> Result<bool, JsValue> {
+        self.editor_mut()?.insert_mention(id, display_name)
+    }
+
+    /// Insert an inline token of a custom kind (e.g. a hashtag, a file
+    /// placeholder or a reference to a quoted message) at the current caret
+    /// position.
+    ///
+    /// Like mentions (see `insert_mention`), tokens are non-editable elements
+    /// that are handled as a single unit. In the text representation (e.g.
+    /// `get_text`), a token is represented by `text_repr`, which is also
+    /// displayed. The element has the class `ca-token` and the kind in the
+    /// `data-ca-kind` attribute. Element handlers (see `set_class_handler`)
+    /// are not called for tokens and mentions, so to serialize a token
+    /// differently, choose the `text_repr` accordingly.
+    ///
+    /// `html_attrs` is an optional object with additional attributes. Only
+    /// `class`, `dir`, `lang`, `title`, `data-*` and `aria-*` attributes with
+    /// string values are allowed, other attributes are ignored.
+    ///
+    /// Return whether the token was inserted (it is not inserted if
+    /// `text_repr` is empty or exceeds the maximum length).
+    ///
+    /// # Errors
+    ///
+    /// See `insert_mention`.
+    pub fn insert_token(
+        &mut self,
+        kind: &str,
+        text_repr: &str,
+        html_attrs: &JsValue,
+    ) -> Result<bool, JsValue> {
+        self.editor_mut()?.insert_token(kind, text_repr, html_attrs)
+    }
+
+    /// Insert plain text at the current caret position.
+    ///
+    /// If a maximum length is configured, the text is truncated to fit.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the text cannot be inserted, or a `NoSelection`
+    /// error if the caret cannot be moved after it. Returns a `Busy` error if
+    /// called re-entrantly (see `ComposeArea`).
+    pub fn insert_text(&mut self, text: &str) -> Result<(), JsValue> {
+        self.editor_mut()?.insert_text(text)
+    }
+
+    /// Insert HTML at the current caret position.
+    ///
+    /// If a maximum length is configured and the contents do not fit, the
+    /// truncated contents are inserted as plain text and images instead.
+    ///
+    /// Note: This is potentially dangerous, make sure that you only insert
+    /// HTML from trusted sources! Elements that the document model cannot
+    /// represent are not kept, see `insert_node`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the HTML cannot be parsed or inserted, or a
+    /// `NoSelection` error if the caret cannot be moved after it. Returns a
+    /// `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn insert_html(&mut self, html: &str) -> Result<(), JsValue> {
+        self.editor_mut()?.insert_html(html)
+    }
+
+    /// Insert the specified node at the previously stored selection range.
+    /// Set the caret position to right after the newly inserted node.
+    ///
+    /// The insertion is recorded in the undo history, see `undo`.
+    ///
+    /// Note: The document model only represents text, images, line breaks,
+    /// mentions and tokens. Other elements are dropped (and elements handled
+    /// by an element handler are replaced by their text) whenever the compose
+    /// area renders the model, e.g. on `undo` or `set_text`, when handling
+    /// input events and, if markup highlighting is enabled, right after the
+    /// insertion.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the node cannot be inserted, or a `NoSelection`
+    /// error if the caret cannot be moved after it. Returns a `Busy` error if
+    /// called re-entrantly (see `ComposeArea`).
+    pub fn insert_node(&mut self, node_ref: &Node) -> Result<(), JsValue> {
+        self.editor_mut()?.insert_node(node_ref)
+    }
+
+    /// Return the last range of the selection that is within the wrapper
+    /// element.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NoSelection` error if the window selection cannot be
+    /// accessed. Returns a `Busy` error if called re-entrantly (see
+    /// `ComposeArea`).
+    pub fn fetch_range(&self) -> Result<RangeResult, JsValue> {
+        self.editor()?.fetch_range()
+    }
+
+    /// Extract the text in the compose area.
+    ///
+    /// Convert elements like images to alt text.
+    ///
+    /// Args:
+    /// - `no_trim`: If set to `true`, don't trim leading / trailing whitespace
+    ///   from returned text. Default: `false`, unless configured otherwise
+    ///   with `ComposeAreaOptions.trim_text`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn get_text(&self, no_trim: Option<bool>) -> Result<String, JsValue> {
+        Ok(self.editor()?.get_text(no_trim))
+    }
+
+    /// Return the contents of the compose area as an array of
+    /// `ExtractedSegment` objects (text, images, line breaks and unknown
+    /// elements), each with its logical offsets.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn extract_segments(&self) -> Result<js_sys::Array, JsValue> {
+        Ok(self.editor()?.extract_segments())
+    }
+
+    /// Focus the compose area.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DetachedWrapper` error if the wrapper element is not attached
+    /// to the document (see `restore_selection_range`), or a `Dom` error if it
+    /// cannot be focused. Returns a `Busy` error if called re-entrantly (see
+    /// `ComposeArea`).
+    pub fn focus(&self) -> Result<(), JsValue> {
+        self.editor()?.focus()
+    }
+
+    /// Replace the contents of the compose area with the specified text.
+    ///
+    /// Newlines are converted to line breaks. If an emoji resolver is passed
+    /// (or was set with `set_emoji_resolver`), emoji are replaced with images
+    /// (see `set_emoji_resolver`). Mention tokens are converted to mentions
+    /// (see `insert_mention`). The contents are rendered in one pass and the
+    /// change can be undone as a whole.
+    ///
+    /// The `caret` argument specifies where the caret is placed afterwards:
+    /// `"start"`, `"end"` (the default) or a logical offset (see
+    /// `get_selection_offsets`).
+    ///
+    /// If a maximum length is configured, the text is truncated to fit (or
+    /// rejected, leaving the contents unchanged, see
+    /// `ComposeAreaOptions.reject_over_limit`).
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the contents cannot be rendered, or a
+    /// `NoSelection` error if the caret cannot be moved because the window
+    /// selection is not accessible. Returns a `Busy` error if called
+    /// re-entrantly (see `ComposeArea`).
+    #[allow(clippy::needless_pass_by_value)] // Optional arguments must be owned
+    pub fn set_text(
+        &mut self,
+        text: &str,
+        emoji_resolver: Option<js_sys::Function>,
+        caret: &JsValue,
+    ) -> Result<(), JsValue> {
+        self.editor_mut()?.set_text(text, emoji_resolver, caret)
+    }
+
+    /// Serialize the contents (including images) and the stored selection
+    /// range of the compose area, e.g. to store a draft.
+    ///
+    /// The returned string uses a compact, versioned format. It can be passed
+    /// to `restore_state`, also by later versions of this library.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn save_state(&mut self) -> Result<String, JsValue> {
+        Ok(self.editor_mut()?.save_state())
+    }
+
+    /// Restore the contents and the stored selection range from a string
+    /// created by `save_state`. The undo history is cleared.
+    ///
+    /// The restored contents are sanitized like pasted contents: Images with
+    /// an unsafe source are replaced by their alt text, and disallowed token
+    /// attributes are removed (see `insert_token`).
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidState` error if the state is invalid. In that case,
+    /// the compose area is not modified. Returns a `Dom` error if the contents
+    /// cannot be rendered, or a `NoSelection` error if the caret cannot be
+    /// moved because the window selection is not accessible. Returns a `Busy`
+    /// error if called re-entrantly (see `ComposeArea`).
+    pub fn restore_state(&mut self, state: &str) -> Result<(), JsValue> {
+        self.editor_mut()?.restore_state(state)
+    }
+
+    /// Clear the contents of the compose area.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the contents cannot be removed. Returns a
+    /// `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn clear(&mut self) -> Result<(), JsValue> {
+        self.editor_mut()?.clear()
+    }
+
+    /// Return the word at the current caret position. How words are found
+    /// can be configured, see `ComposeAreaOptions.word_mode`. By default,
+    /// words are delimited by whitespace.
+    ///
+    /// Words may span multiple text nodes (e.g. formatted text). Images are
+    /// word boundaries unless `ComposeAreaOptions.images_in_words` is set.
+    /// Line breaks are always word boundaries.
+    ///
+    /// Note: This methods uses the range that was last set with
+    /// `store_selection_range`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn get_word_at_caret(&mut self) -> Result<Option<WordAtCaret>, JsValue> {
+        Ok(self.editor_mut()?.get_word_at_caret())
+    }
+
+    /// Select the word at the current caret position (see
+    /// `get_word_at_caret`).
+    ///
+    /// Note: This methods uses the range that was last set with
+    /// `store_selection_range`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NoSelection` or `Dom` error if the window selection cannot be
+    /// set. Returns a `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn select_word_at_caret(&mut self) -> Result<bool, JsValue> {
+        self.editor_mut()?.select_word_at_caret()
+    }
+
+    /// Return the position of the caret relative to the viewport, e.g. to
+    /// place an autocomplete popup next to it. If text is selected, the
+    /// position of the end of the selection is returned.
+    ///
+    /// Return `undefined` if there is no stored selection range.
+    ///
+    /// Note: This methods uses the range that was last set with
+    /// `store_selection_range`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the rectangle cannot be measured. Returns a
+    /// `Busy` error if called re-entrantly (see `ComposeArea`).
+    pub fn caret_rect(&self) -> Result<Option<CaretRect>, JsValue> {
+        self.editor()?.caret_rect()
+    }
+
+    /// Return the position of the word at the caret (see
+    /// `get_word_at_caret`) relative to the viewport. If there is no word at
+    /// the caret, the position of the caret is returned.
+    ///
+    /// Return `undefined` if there is no stored selection range or it is not
+    /// collapsed.
+    ///
+    /// Note: This methods uses the range that was last set with
+    /// `store_selection_range`.
+    ///
+    /// # Errors
+    ///
+    /// See `caret_rect`.
+    pub fn word_at_caret_rect(&mut self) -> Result<Option<CaretRect>, JsValue> {
+        self.editor_mut()?.word_at_caret_rect()
+    }
+
+    /// Replace the word at the current caret position (see
+    /// `get_word_at_caret`) with plain text. If the word at the caret is
+    /// empty (e.g. right after a space), the text is inserted at the caret.
+    /// The caret is moved to right after the replacement.
+    ///
+    /// The replacement is a single step in the undo history. Emoji are
+    /// replaced and the maximum length is applied like in `insert_text`.
+    ///
+    /// Return whether the contents were changed. Nothing is changed if there
+    /// is no stored caret (i.e. the stored range is missing or not
+    /// collapsed), or if the replacement was rejected because of the maximum
+    /// length.
+    ///
+    /// Note: This methods uses the range that was last set with
+    /// `store_selection_range`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Dom` error if the contents cannot be rendered, or a
+    /// `NoSelection` error if the caret cannot be moved because the window
+    /// selection is not accessible. Returns a `Busy` error if called
+    /// re-entrantly (see `ComposeArea`).
+    pub fn replace_word_at_caret_with_text(&mut self, text: &str) -> Result<bool, JsValue> {
+        self.editor_mut()?.replace_word_at_caret_with_text(text)
+    }
+
+    /// Replace the word at the current caret position (see
+    /// `get_word_at_caret`) with an image, e.g. to complete an emoji
+    /// shortcode like `:smile`. Otherwise like
+    /// `replace_word_at_caret_with_text`.
+    ///
+    /// Note: Unlike `insert_image`, the image does not get an ID.
+    ///
+    /// # Errors
+    ///
+    /// See `replace_word_at_caret_with_text`.
+    pub fn replace_word_at_caret_with_image(
+        &mut self,
+        src: &str,
+        alt: &str,
+        cls: &str,
+    ) -> Result<bool, JsValue> {
+        self.editor_mut()?
+            .replace_word_at_caret_with_image(src, alt, cls)
+    }
+}
+
+impl Editor {
+    /// Update the model (and the notifier) after the element handlers were
+    /// modified.
+    fn element_handlers_changed(&mut self) {
+        self.notifier.set_element_handlers(&self.element_handlers);
+        self.update_model();
+    }
+
+    /// Initialize the editor for a compose area (see
+    /// `ComposeArea.bind_with_options`).
+    fn new(wrapper: Element, options: &ComposeAreaOptions) -> Result<Self, JsValue> {
+        utils::set_panic_hook();
+
+        // Set log level
+        if let Some(level) = options.log_level {
+            utils::init_log(level);
+        }
+        trace!("[compose_area] bind_with_options");
+
+        let window = web_sys::window().ok_or(ComposeAreaError::NoDocument)?;
+        let document = window.document().ok_or(ComposeAreaError::NoDocument)?;
+
+        // Initialize the wrapper element
+        for class in &options.wrapper_classes {
+            wrapper
+                .class_list()
+                .add_1(class)
+                .dom_err("add wrapper classes")?;
+        }
+        wrapper
+            .set_attribute("contenteditable", "true")
+            .dom_err("set contenteditable attr")?;
+
+        // Attach event listeners
+        let selection_range = Rc::default();
+        let notifier = Notifier::new(&window, &wrapper, &selection_range, options.change_debounce);
+        let mut listeners = Listeners::new();
+        if options.track_selection {
+            listeners.track_selection(&window, &wrapper, &selection_range, &notifier)?;
+        }
+        listeners.notify(&wrapper, &notifier)?;
+
+        info!("[compose_area] Initialized");
+
+        let mut editor = Self {
+            window,
+            document,
+            wrapper,
+            selection_range,
+            model: Document::new(),
+            model_selection: None,
+            history: History::new(),
+            instance_id: INSTANCE_COUNTER.fetch_add(1, Ordering::Relaxed),
+            counter: 0,
+            options: options.clone(),
+            element_handlers: ElementHandlers::new(),
+            listeners: Some(listeners),
+            notifier,
+        };
+        editor.update_model();
+        Ok(editor)
+    }
+
+    /// Attach the event listeners that let the compose area handle input
+    /// events itself (see `ComposeAreaOptions.handle_beforeinput`,
+    /// `handle_paste`, `handle_clipboard` and `formatting_shortcuts`).
+    fn handle_events(&mut self, editor: &Weak<RefCell<Editor>>) -> Result<(), ComposeAreaError> {
+        let listeners = match self.listeners.as_mut() {
+            Some(listeners) => listeners,
+            None => return Ok(()),
+        };
+        let wrapper = &self.wrapper;
+        if self.options.handle_beforeinput {
+            listeners.handle(wrapper, "beforeinput", editor, Self::handle_beforeinput)?;
+        }
+        if self.options.handle_paste {
+            listeners.handle(wrapper, "paste", editor, Self::handle_paste)?;
+        }
+        if self.options.handle_clipboard {
+            listeners.handle(wrapper, "copy", editor, Self::handle_copy)?;
+            listeners.handle(wrapper, "cut", editor, Self::handle_cut)?;
+        }
+        if self.options.formatting_shortcuts {
+            listeners.handle(wrapper, "keydown", editor, Self::handle_keydown)?;
+        }
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<(), JsValue> {
+        debug!("[compose_area] destroy");
+        self.listeners = None;
+        self.notifier.destroy();
+        self.store_range(None);
+        self.model_selection = None;
+        for class in &self.options.wrapper_classes {
+            self.wrapper
+                .class_list()
+                .remove_1(class)
+                .dom_err("remove wrapper classes")?;
+        }
+        self.wrapper
+            .remove_attribute("contenteditable")
+            .dom_err("remove contenteditable attr")?;
+        Ok(())
+    }
+
+    fn store_selection_range(&mut self) -> Result<RangeResult, JsValue> {
+        trace!("[compose_area] store_selection_range");
+        let range_result = self.fetch_range()?;
+        trace!(
+            "[compose_area]   Range: {}",
+            range_result.to_string().replace('\n', "")
+        );
+
+        // Ignore selections outside the wrapper
+        if !range_result.outside {
+            // Note: We need to clone the range object. Otherwise, changes to the
+            // range in the DOM will be reflected in our stored reference.
+            self.store_range(range_result.clone().range.map(|range| range.clone_range()));
+        }
+        self.sync_model();
+
+        Ok(range_result)
+    }
+
+    /// Return the stored selection range.
+    fn stored_range(&self) -> Option<Range> {
+        self.selection_range.borrow().clone()
+    }
+
+    /// Replace the stored selection range.
+    fn store_range(&self, range: Option<Range>) {
+        self.selection_range.replace(range);
+        self.notifier.selection_changed();
+    }
 
-        if let Some(ref range) = self.selection_range {
-            // Clone the current range so we don't modify any existing selection
-            let mut range = range.clone_range();
+    /// Update the document model from the DOM. If the contents were changed
+    /// since the last update (e.g. because the user typed something), the
+    /// change is recorded in the undo history.
+    fn sync_model(&mut self) {
+        let before = self.snapshot();
+        self.update_model();
+        if self.options.undo_history && self.model != before.document {
+            self.history
+                .record(before, ChangeKind::Typing, js_sys::Date::now());
+        }
+    }
+
+    /// Walk the DOM tree below the wrapper element.
+    fn extract(&self) -> Extraction {
+        Extraction::with_handlers(&self.wrapper, &self.element_handlers)
+    }
 
-            // Ensure that range is relative to a text node
-            if !glue_range_to_text(&mut range) {
-                return None;
+    /// Parse the contents of the wrapper element into the document model.
+    /// The stored selection range is converted to logical offsets.
+    fn update_model(&mut self) {
+        trace!("[compose_area]   update_model");
+        let extraction = self.extract();
+        self.model_selection = self
+            .stored_range()
+            .and_then(|range| range_to_offsets(&extraction, &range));
+        self.model = extraction.document;
+    }
+
+    /// Render the document model into the wrapper element.
+    ///
+    /// If logical selection offsets are specified, the selection range is
+    /// moved there. Otherwise, the stored selection range is removed.
+    fn render_model(&mut self, selection: Option<(u32, u32)>) -> Result<(), ComposeAreaError> {
+        render(
+            &self.document,
+            &self.wrapper,
+            &self.model,
+            self.options.highlight_markup,
+        )?;
+        match selection {
+            Some((start, end)) => self.select_offsets(start, end),
+            None => {
+                self.store_range(None);
+                self.model_selection = None;
+                Ok(())
             }
+        }
+    }
 
-            // Get the container element (which is the same for start and end
-            // since the range is collapsed) and offset. After having called
-            // the `glue_range_to_text` function, this will be a text node.
-            let node: Text = range
-                .start_container()
-                .expect("Could not get start container")
-                .dyn_into::<Text>()
-                .expect("Node is not a text node");
-            let offset: u32 = range.start_offset().expect("Could not get start offset");
-
-            // Note that the offset refers to JS characters, not bytes.
-            let text: String = node.data();
-            let mut before: Vec<u16> = vec![];
-            let mut after: Vec<u16> = vec![];
-            let mut start = 0;
-            let mut end = 0;
-            let is_word_boundary = |c: u16| c == 0x20 /* space */ || c == 0x09 /* tab */;
-            #[allow(clippy::collapsible_if)]
-            for (i, c) in text.encode_utf16().enumerate() {
-                if i < offset as usize {
-                    if is_word_boundary(c) {
-                        before.clear();
-                        start = i + 1;
-                    } else {
-                        before.push(c);
-                    }
-                } else {
-                    if is_word_boundary(c) {
-                        end = i;
-                        break;
-                    } else {
-                        after.push(c);
-                    }
-                }
+    /// Move the selection to the specified logical offsets and store it.
+    fn select_offsets(&mut self, start: u32, end: u32) -> Result<(), ComposeAreaError> {
+        let start = self.model.snap_offset(start, false);
+        let end = self.model.snap_offset(end, start < end);
+        let extraction = self.extract();
+        match (
+            offset_to_position(&extraction, start),
+            offset_to_position(&extraction, end),
+        ) {
+            (Some((start_node, start_offset)), Some((end_node, end_offset))) => {
+                self.select_positions(
+                    &Position::Offset(&start_node, start_offset),
+                    Some(&Position::Offset(&end_node, end_offset)),
+                )?;
+                self.model_selection = Some((start, end));
             }
-            if end <= start {
-                end = text.encode_utf16().count();
+            _ => {
+                self.store_range(None);
+                self.model_selection = None;
             }
+        }
+        Ok(())
+    }
 
-            // Note: Decoding should not be able to fail since it was
-            // previously encoded from a string.
-            #[allow(clippy::cast_possible_truncation)]
-            Some(WordAtCaret {
-                node: node
-                    .dyn_into::<Node>()
-                    .expect("Could not turn Text into Node"),
-                before: String::from_utf16(&before).expect("Could not decode UTF16 value"),
-                after: String::from_utf16(&after).expect("Could not decode UTF16 value"),
-                offsets: (start as u32, end as u32),
-            })
+    /// Move the selection to the specified positions and store it.
+    ///
+    /// If the wrapper element is not attached to the document, the DOM
+    /// selection cannot be set. In that case, only the stored selection range
+    /// is updated. It is activated by `restore_selection_range` once the
+    /// wrapper has been attached.
+    fn select_positions(
+        &mut self,
+        start: &Position,
+        end: Option<&Position>,
+    ) -> Result<(), ComposeAreaError> {
+        let range = if self.wrapper.is_connected() {
+            set_selection_range(start, end)?
         } else {
-            None
+            let range = self.document.create_range().dom_err("create range")?;
+            set_range_positions(&range, start, end)?;
+            range
+        };
+        self.store_range(Some(range.clone_range()));
+        Ok(())
+    }
+
+    fn get_selection_offsets(&mut self) -> Option<SelectionOffsets> {
+        debug!("[compose_area] get_selection_offsets");
+        self.sync_model();
+        self.model_selection
+            .map(|(start, end)| SelectionOffsets { start, end })
+    }
+
+    fn set_selection_offsets(&mut self, start: u32, end: Option<u32>) -> Result<(), JsValue> {
+        debug!(
+            "[compose_area] set_selection_offsets ({}, {:?})",
+            start, end
+        );
+        self.sync_model();
+        Ok(self.select_offsets(start, end.unwrap_or(start))?)
+    }
+
+    /// Return a snapshot of the document model and the selection offsets.
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            document: self.model.clone(),
+            selection: self.model_selection,
         }
     }
 
-    /// Select the word (whitespace delimited) at the current caret position.
+    /// Prepare a programmatic change of the contents.
     ///
-    /// Note: This methods uses the range that was last set with
-    /// `store_selection_range`.
-    pub fn select_word_at_caret(&mut self) -> bool {
-        debug!("[compose_area] select_word_at_caret");
+    /// Return the snapshot that must be passed to `finish_change` once the
+    /// change has been applied.
+    fn begin_change(&mut self) -> Snapshot {
+        self.sync_model();
+        self.snapshot()
+    }
+
+    /// Record a change in the undo history, if the contents were modified.
+    fn finish_change(&mut self, before: Snapshot, kind: ChangeKind) {
+        if self.model == before.document {
+            return;
+        }
+        self.notifier.content_changed();
+        if self.options.undo_history {
+            self.history.record(before, kind, js_sys::Date::now());
+        }
+    }
+
+    fn undo(&mut self) -> Result<bool, JsValue> {
+        debug!("[compose_area] undo");
+        self.sync_model();
+        match self.history.undo(self.snapshot()) {
+            Some(snapshot) => {
+                self.restore_snapshot(snapshot)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn redo(&mut self) -> Result<bool, JsValue> {
+        debug!("[compose_area] redo");
+        self.sync_model();
+        match self.history.redo(self.snapshot()) {
+            Some(snapshot) => {
+                self.restore_snapshot(snapshot)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    /// Render the contents and selection of a snapshot.
+    fn restore_snapshot(&mut self, snapshot: Snapshot) -> Result<(), ComposeAreaError> {
+        self.model = snapshot.document;
+        self.notifier.content_changed();
+        self.render_model(snapshot.selection)
+    }
+
+    fn restore_selection_range(&self) -> Result<bool, JsValue> {
+        trace!("[compose_area] restore_selection_range");
+        if let Some(range) = self.stored_range() {
+            if !self.wrapper.is_connected() {
+                return Err(ComposeAreaError::DetachedWrapper.into());
+            }
+            let selection = self.fetch_selection()?;
+            activate_selection_range(&selection, &range)?;
+            Ok(true)
+        } else {
+            trace!("[compose_area]   No stored range");
+            Ok(false)
+        }
+    }
+
+    fn handle_beforeinput(&mut self, event: &InputEvent) -> Result<bool, JsValue> {
+        let input_type = utils::get_string_property(event, "inputType").unwrap_or_default();
+        trace!("[compose_area] handle_beforeinput ({})", input_type);
+        if event.is_composing() {
+            return Ok(false);
+        }
+        if input_type == "insertFromPaste" || input_type == "insertFromDrop" {
+            if let Some(data_transfer) = utils::get_data_transfer_property(event, "dataTransfer") {
+                event.prevent_default();
+                self.paste_data_transfer(&data_transfer)?;
+                return Ok(true);
+            }
+        }
+        let data = utils::get_string_property(event, "data").or_else(|| {
+            utils::get_data_transfer_property(event, "dataTransfer")?
+                .get_data("text/plain")
+                .ok()
+        });
+        let mut edit = match Edit::from_input_type(&input_type, data) {
+            Some(edit) => edit,
+            None => return Ok(false),
+        };
+        let target = if uses_target_range(&input_type) {
+            self.target_offsets(event)
+        } else {
+            None
+        };
+        if target.is_some() {
+            edit.operation = edit.operation.on_target_range();
+        } else if input_type == "insertReplacementText" {
+            return Ok(false);
+        }
+        event.prevent_default();
+
+        match edit.operation {
+            EditOperation::Undo => {
+                self.undo()?;
+            }
+            EditOperation::Redo => {
+                self.redo()?;
+            }
+            EditOperation::Ignore => {}
+            ref operation => {
+                self.store_selection_range()?;
+                let before = self.begin_change();
+                let selection = target.or(self.model_selection).unwrap_or_else(|| {
+                    let end = self.model.len();
+                    (end, end)
+                });
+                let operation = self.limit_operation(operation, selection);
+                let caret = input::apply(&mut self.model, selection, &operation);
+                if let EditOperation::InsertText(_) = operation {
+                    let start = selection.0.min(selection.1);
+                    let inserted = self.replace_emoji(&self.model.slice(start, caret));
+                    self.model.replace_range(start, caret, &inserted);
+                }
+                let result = self.render_model(Some((caret, caret)));
+                self.finish_change(before, edit.kind);
+                result?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Return the logical offsets of the target range of a `beforeinput`
+    /// event, or `None` if the event has no target range within the wrapper.
+    fn target_offsets(&self, event: &InputEvent) -> Option<(u32, u32)> {
+        let ((start_node, start), (end_node, end)) = utils::get_target_range(event)?;
+        let extraction = self.extract();
+        Some((
+            position_to_offset(&extraction, &Position::Offset(&start_node, start))?,
+            position_to_offset(&extraction, &Position::Offset(&end_node, end))?,
+        ))
+    }
+
+    fn update_markup(&mut self) -> Result<(), JsValue> {
+        trace!("[compose_area] update_markup");
+        if !self.options.highlight_markup {
+            return Ok(());
+        }
+        self.store_selection_range()?;
+        Ok(self.refresh_markup()?)
+    }
+
+    fn toggle_bold(&mut self) -> Result<bool, JsValue> {
+        debug!("[compose_area] toggle_bold");
+        self.toggle_markup('*')
+    }
+
+    fn toggle_italic(&mut self) -> Result<bool, JsValue> {
+        debug!("[compose_area] toggle_italic");
+        self.toggle_markup('_')
+    }
+
+    fn toggle_strikethrough(&mut self) -> Result<bool, JsValue> {
+        debug!("[compose_area] toggle_strikethrough");
+        self.toggle_markup('~')
+    }
+
+    fn toggle_monospace(&mut self) -> Result<bool, JsValue> {
+        debug!("[compose_area] toggle_monospace");
+        self.toggle_markup('`')
+    }
+
+    /// Wrap or unwrap the stored selection range with the markup character.
+    /// Whitespace at the edges of the selection is not wrapped, and the
+    /// selection is moved so that it covers the same text afterwards.
+    ///
+    /// The change is recorded in the undo history as a single step. Return
+    /// `false` if nothing was changed: If there is no stored selection range,
+    /// if the selection spans multiple lines or only contains whitespace, or
+    /// if the markup would exceed the maximum length.
+    fn toggle_markup(&mut self, marker: char) -> Result<bool, JsValue> {
+        let before = self.begin_change();
+        let selection = match self.model_selection {
+            Some(selection) => selection,
+            None => return Ok(false),
+        };
+        let mut model = self.model.clone();
+        let selection = match toggle_markup(&mut model, selection, marker) {
+            Some(selection) => selection,
+            None => return Ok(false),
+        };
+        if model.len() > self.model.len() {
+            let markers = Document::from_text(&marker.to_string().repeat(2));
+            if self.limit_insertion(&markers, None).as_ref() != Some(&markers) {
+                return Ok(false);
+            }
+        }
+        self.model = model;
+        let result = self.render_model(Some(selection));
+        self.finish_change(before, ChangeKind::Other);
+        result?;
+        Ok(true)
+    }
+
+    fn handle_keydown(&mut self, event: &KeyboardEvent) -> Result<bool, JsValue> {
+        trace!("[compose_area] handle_keydown ({})", event.key());
+        if !(event.ctrl_key() || event.meta_key()) || event.alt_key() || event.shift_key() {
+            return Ok(false);
+        }
+        let marker = match &*event.key().to_lowercase() {
+            "b" => '*',
+            "i" => '_',
+            _ => return Ok(false),
+        };
+        self.store_selection_range()?;
+        // If nothing was changed (e.g. because the selection spans multiple
+        // lines), the shortcut is left to the browser
+        let changed = self.toggle_markup(marker)?;
+        if changed {
+            event.prevent_default();
+        }
+        Ok(changed)
+    }
+
+    fn handle_paste(&mut self, event: &web_sys::Event) -> Result<bool, JsValue> {
+        trace!("[compose_area] handle_paste");
+        // Note: `ClipboardEvent` is still an unstable API in web-sys
+        match utils::get_data_transfer_property(event, "clipboardData") {
+            Some(data_transfer) => {
+                event.prevent_default();
+                self.paste_data_transfer(&data_transfer)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Paste the contents of a data transfer. Contents copied from a compose
+    /// area (see `handle_copy`) are restored exactly, otherwise the
+    /// `text/html` and `text/plain` contents are pasted.
+    fn paste_data_transfer(&mut self, data_transfer: &DataTransfer) -> Result<bool, JsValue> {
+        let copied = data_transfer
+            .get_data(state::CLIPBOARD_TYPE)
+            .ok()
+            .filter(|data| !data.is_empty())
+            .and_then(|data| match state::deserialize(&data) {
+                Ok(snapshot) => Some(snapshot.document),
+                Err(e) => {
+                    warn!("[compose_area] Ignoring invalid clipboard data: {}", e);
+                    None
+                }
+            });
+        if let Some(document) = copied {
+            trace!("[compose_area]   Pasting copied contents");
+            return self.insert_pasted(&sanitize_document(&document));
+        }
+        let html = data_transfer.get_data("text/html").unwrap_or_default();
+        let text = data_transfer.get_data("text/plain").unwrap_or_default();
+        self.paste(&html, &text)
+    }
+
+    fn paste(&mut self, html: &str, text: &str) -> Result<bool, JsValue> {
+        debug!("[compose_area] paste");
+        let mut pasted = if html.trim().is_empty() {
+            Document::new()
+        } else {
+            sanitize(&parse_html(html)?, &self.options.paste_image_classes)
+        };
+        if pasted.is_empty() {
+            pasted = Document::from_text(&normalize_newlines(text));
+        }
+        let pasted = self.replace_emoji(&pasted);
+        self.insert_pasted(&pasted)
+    }
+
+    /// Insert a pasted document in place of the current selection, truncated
+    /// to the maximum length. Return whether anything was inserted.
+    fn insert_pasted(&mut self, pasted: &Document) -> Result<bool, JsValue> {
+        if pasted.is_empty() {
+            trace!("[compose_area]   Nothing to paste");
+            return Ok(false);
+        }
+        self.store_selection_range()?;
+        self.insert_document(pasted)
+    }
+
+    /// Insert a document in place of the stored selection range (or at the
+    /// end, if there is none), truncated to the maximum length. The caret is
+    /// placed after the inserted contents.
+    ///
+    /// The insertion is a single step in the undo history. Return whether
+    /// anything was inserted.
+    fn insert_document(&mut self, inserted: &Document) -> Result<bool, JsValue> {
+        let before = self.begin_change();
+        let (start, end) = self.model_selection.map_or_else(
+            || {
+                let end = self.model.len();
+                (end, end)
+            },
+            |(start, end)| (start.min(end), start.max(end)),
+        );
+        let inserted = match self.limit_insertion(inserted, Some((start, end))) {
+            Some(inserted) => inserted,
+            None => return Ok(false),
+        };
+        let caret = self.model.replace_range(start, end, &inserted);
+        let result = self.render_model(Some((caret, caret)));
+        self.finish_change(before, ChangeKind::Other);
+        result?;
+        Ok(true)
+    }
+
+    fn handle_copy(&mut self, event: &web_sys::Event) -> Result<bool, JsValue> {
+        trace!("[compose_area] handle_copy");
+        Ok(self.copy_selection(event)?.is_some())
+    }
+
+    fn handle_cut(&mut self, event: &web_sys::Event) -> Result<bool, JsValue> {
+        trace!("[compose_area] handle_cut");
+        let (start, end) = match self.copy_selection(event)? {
+            Some(selection) => selection,
+            None => return Ok(false),
+        };
+        let before = self.begin_change();
+        let caret = self.model.delete_range(start, end);
+        let result = self.render_model(Some((caret, caret)));
+        self.finish_change(before, ChangeKind::Other);
+        result?;
+        Ok(true)
+    }
+
+    /// Write the selected contents to the clipboard data of a `copy` or `cut`
+    /// event. Return the (ordered) selection offsets, or `None` if nothing
+    /// was copied.
+    fn copy_selection(&mut self, event: &web_sys::Event) -> Result<Option<(u32, u32)>, JsValue> {
+        let data_transfer = match utils::get_data_transfer_property(event, "clipboardData") {
+            Some(data_transfer) => data_transfer,
+            None => return Ok(None),
+        };
+        self.store_selection_range()?;
+        let (start, end) = match self.model_selection {
+            Some((start, end)) if start != end => (start.min(end), start.max(end)),
+            _ => return Ok(None),
+        };
+        let copied = self.model.slice(start, end);
+        data_transfer
+            .set_data("text/plain", &copied.to_text())
+            .dom_err("set clipboard text")?;
+        let snapshot = Snapshot {
+            document: copied,
+            selection: None,
+        };
+        data_transfer
+            .set_data(state::CLIPBOARD_TYPE, &state::serialize(&snapshot))
+            .dom_err("set clipboard data")?;
+        event.prevent_default();
+        Ok(Some((start, end)))
+    }
+
+    /// Apply the maximum length to contents that are inserted in place of the
+    /// selection (or appended, if there is no selection).
+    ///
+    /// Return the contents to insert (truncated if necessary), or `None` if
+    /// the insertion is rejected or nothing fits. If the maximum length is
+    /// exceeded, the `on_over_limit` callback is called.
+    fn limit_insertion(
+        &self,
+        inserted: &Document,
+        selection: Option<(u32, u32)>,
+    ) -> Option<Document> {
+        let max_length = match self.options.max_length {
+            Some(max_length) => max_length,
+            None => return Some(inserted.clone()),
+        };
+        let unit = self.options.max_length_unit;
+        let (start, end) = selection.unwrap_or_else(|| {
+            let end = self.model.len();
+            (end, end)
+        });
+        // Measure the resulting contents, lengths are not additive
+        let length_with = |inserted: &Document| {
+            let mut result = self.model.clone();
+            result.replace_range(start, end, inserted);
+            measure_document(&result, unit)
+        };
+        let length = length_with(inserted);
+        if length <= max_length {
+            return Some(inserted.clone());
+        }
+
+        let rejected = self.options.reject_over_limit;
+        debug!(
+            "[compose_area] Maximum length exceeded ({} > {})",
+            length, max_length
+        );
+        self.notifier
+            .over_limit(LimitExceeded::new(length, max_length, rejected));
+        if rejected {
+            return None;
+        }
+        Some(truncate_document(inserted, max_length, unit, length_with))
+            .filter(|doc| !doc.is_empty())
+    }
+
+    /// Apply the maximum length to a text insertion or line break (see
+    /// `limit_insertion`). Operations that are rejected are ignored.
+    fn limit_operation(&self, operation: &EditOperation, selection: (u32, u32)) -> EditOperation {
+        let inserted = match operation {
+            EditOperation::InsertText(text) => Document::from_text(text),
+            EditOperation::InsertLineBreak => Document::from_segments(vec![Segment::Newline]),
+            other => return other.clone(),
+        };
+        match self.limit_insertion(&inserted, Some(selection)) {
+            Some(ref limited) if limited == &inserted => operation.clone(),
+            Some(limited) => EditOperation::InsertText(limited.to_text()),
+            None => EditOperation::Ignore,
+        }
+    }
+
+    fn get_length(&mut self) -> ContentLength {
+        debug!("[compose_area] get_length");
+        self.sync_model();
+        ContentLength::of(&self.model.to_text())
+    }
+
+    fn on_over_limit(&self, callback: Option<js_sys::Function>) {
+        self.notifier.set_callback(Event::OverLimit, callback);
+    }
+
+    fn set_emoji_resolver(&mut self, resolver: Option<js_sys::Function>) {
+        debug!("[compose_area] set_emoji_resolver");
+        self.options.emoji_resolver = resolver;
+    }
+
+    /// Replace the emoji in the text segments of a document with images,
+    /// using the emoji resolver. If no resolver is set, the document is
+    /// returned unchanged.
+    fn replace_emoji(&self, document: &Document) -> Document {
+        match &self.options.emoji_resolver {
+            Some(resolver) => resolve_emoji(resolver, document),
+            None => document.clone(),
+        }
+    }
+
+    /// Replace the mention tokens in the text segments of a document with
+    /// mentions. The display names are looked up with the mention resolver.
+    fn parse_mentions(&self, document: &Document) -> Document {
+        let resolver = self.options.mention_resolver.as_ref();
+        replace_mentions(document, &self.options.mention_format, |id| {
+            resolver
+                .and_then(|resolver| resolver.call1(&JsValue::NULL, &id.into()).ok())
+                .and_then(|name| name.as_string())
+                .unwrap_or_else(|| id.to_string())
+        })
+    }
+
+    fn on_change(&self, callback: Option<js_sys::Function>) {
+        self.notifier.set_callback(Event::Change, callback);
+    }
+
+    fn on_selection_change(&self, callback: Option<js_sys::Function>) {
+        self.notifier.set_callback(Event::SelectionChange, callback);
+    }
+
+    fn on_focus(&self, callback: Option<js_sys::Function>) {
+        self.notifier.set_callback(Event::Focus, callback);
+    }
+
+    fn on_blur(&self, callback: Option<js_sys::Function>) {
+        self.notifier.set_callback(Event::Blur, callback);
+    }
+
+    fn on_empty_state_change(&self, callback: Option<js_sys::Function>) {
+        self.notifier
+            .set_callback(Event::EmptyStateChange, callback);
+    }
+
+    fn set_tag_handler(&mut self, tag: &str, handler: Option<js_sys::Function>) {
+        debug!("[compose_area] set_tag_handler ({})", tag);
+        match handler {
+            Some(handler) => self
+                .element_handlers
+                .set_tag_handler(tag, js_element_handler(handler)),
+            None => {
+                self.element_handlers.remove_tag_handler(tag);
+            }
+        }
+        self.element_handlers_changed();
+    }
+
+    fn set_class_handler(&mut self, cls: &str, handler: Option<js_sys::Function>) {
+        debug!("[compose_area] set_class_handler ({})", cls);
+        match handler {
+            Some(handler) => self
+                .element_handlers
+                .set_class_handler(cls, js_element_handler(handler)),
+            None => {
+                self.element_handlers.remove_class_handler(cls);
+            }
+        }
+        self.element_handlers_changed();
+    }
+
+    /// Return and increment the counter variable.
+    fn get_counter(&mut self) -> u32 {
+        let val = self.counter;
+        self.counter += 1;
+        val
+    }
+
+    fn insert_image(
+        &mut self,
+        src: &str,
+        alt: &str,
+        cls: &str,
+    ) -> Result<Option<HtmlElement>, JsValue> {
+        debug!("[compose_area] insert_image ({})", &alt);
+        self.sync_model();
+        let image = Document::from_segments(vec![Segment::image(src, alt, cls)]);
+        if self.limit_insertion(&image, self.model_selection).is_none() {
+            return Ok(None);
+        }
+        let img = create_image(&self.document, src, alt, cls)?;
+        if let Some(id) = self.next_image_id() {
+            img.set_id(&id);
+        }
+        self.insert_node(&img)?;
+        Ok(Some(img.unchecked_into()))
+    }
+
+    fn set_strip_image_ids(&mut self, strip: bool) {
+        debug!("[compose_area] set_strip_image_ids ({})", strip);
+        self.options.strip_image_ids = strip;
+    }
+
+    /// Return the ID for the next inserted image (or `None` if image IDs are
+    /// stripped).
+    fn next_image_id(&mut self) -> Option<String> {
+        if self.options.strip_image_ids {
+            return None;
+        }
+        let prefix = match &self.options.image_id_prefix {
+            Some(prefix) => prefix.clone(),
+            None => format!("__$$compose_area_{}_img_", self.instance_id),
+        };
+        Some(format!("{}{}", prefix, self.get_counter()))
+    }
+
+    fn insert_mention(&mut self, id: &str, display_name: &str) -> Result<bool, JsValue> {
+        debug!("[compose_area] insert_mention ({})", id);
+        if !mention::is_valid_id(id) {
+            warn!("[compose_area] insert_mention: Invalid ID");
+            return Ok(false);
+        }
+        let token = self.options.mention_format.token(id);
+        let mention = Document::from_segments(vec![Segment::mention(id, display_name, &token)]);
+        self.insert_document(&mention)
+    }
+
+    fn insert_token(
+        &mut self,
+        kind: &str,
+        text_repr: &str,
+        html_attrs: &JsValue,
+    ) -> Result<bool, JsValue> {
+        debug!("[compose_area] insert_token ({}, {})", kind, text_repr);
+        if text_repr.is_empty() {
+            warn!("[compose_area] insert_token: Empty text representation");
+            return Ok(false);
+        }
+        let attrs = match html_attrs.dyn_ref::<js_sys::Object>() {
+            Some(object) => js_sys::Object::entries(object)
+                .iter()
+                .filter_map(|entry| {
+                    let entry: js_sys::Array = entry.unchecked_into();
+                    Some((entry.get(0).as_string()?, entry.get(1).as_string()?))
+                })
+                .collect(),
+            None => vec![],
+        };
+        let token = Document::from_segments(vec![Segment::token(
+            kind,
+            text_repr,
+            token_attributes(attrs),
+        )]);
+        self.insert_document(&token)
+    }
+
+    fn insert_text(&mut self, text: &str) -> Result<(), JsValue> {
+        debug!("[compose_area] insert_text ({})", text);
+        self.sync_model();
+        let inserted = self.replace_emoji(&Document::from_text(text));
+        let inserted = match self.limit_insertion(&inserted, self.model_selection) {
+            Some(inserted) => inserted,
+            None => return Ok(()),
+        };
+        let fragment = render_fragment(&self.document, &inserted)?;
+        Ok(self.insert_fragment(&fragment)?)
+    }
+
+    fn insert_html(&mut self, html: &str) -> Result<(), JsValue> {
+        debug!("[compose_area] insert_html ({})", html);
+        self.sync_model();
+        let fragment = self
+            .document
+            .create_range()
+            .dom_err("create range")?
+            .create_contextual_fragment(html)
+            .dom_err("parse HTML")?;
+        if self.options.max_length.is_some() {
+            // Measure the contents of the fragment like the contents of the
+            // wrapper. If they are truncated, the truncated contents are
+            // inserted instead of the HTML.
+            let container = self
+                .document
+                .create_element("div")
+                .dom_err("create element")?;
+            container
+                .append_child(
+                    &fragment
+                        .clone_node_with_deep(true)
+                        .dom_err("clone fragment")?,
+                )
+                .dom_err("append child")?;
+            let inserted = Extraction::with_handlers(&container, &self.element_handlers).document;
+            match self.limit_insertion(&inserted, self.model_selection) {
+                Some(limited) if limited == inserted => {}
+                Some(limited) => {
+                    let fragment = render_fragment(&self.document, &limited)?;
+                    return Ok(self.insert_fragment(&fragment)?);
+                }
+                None => return Ok(()),
+            }
+        }
+        Ok(self.insert_fragment(&fragment)?)
+    }
+
+    /// Insert the contents of a document fragment. Set the caret position to
+    /// right after the last inserted node.
+    fn insert_fragment(&mut self, fragment: &DocumentFragment) -> Result<(), ComposeAreaError> {
+        match fragment.last_child() {
+            Some(last_node) => self.insert_nodes(fragment, &last_node),
+            None => {
+                trace!("[compose_area]   Nothing to insert");
+                Ok(())
+            }
+        }
+    }
+
+    fn insert_node(&mut self, node_ref: &Node) -> Result<(), JsValue> {
+        debug!("[compose_area] insert_node");
+        Ok(self.insert_nodes(node_ref, node_ref)?)
+    }
+
+    /// Insert the specified node (which may be a document fragment) at the
+    /// previously stored selection range. Set the caret position to right
+    /// after `last_node`.
+    fn insert_nodes(&mut self, node_ref: &Node, last_node: &Node) -> Result<(), ComposeAreaError> {
+        let before = self.begin_change();
+
+        // Insert the node
+        if let Some(range) = self.stored_range() {
+            range
+                .delete_contents()
+                .dom_err("remove selection contents")?;
+            range.insert_node(node_ref).dom_err("insert node")?;
+        } else {
+            // No current selection. Append at end, unless the last element in
+            // the area is a `<br>` node. This is needed because Firefox always
+            // adds a trailing newline that isn't rendered.
+            let last_child_node = utils::get_last_child(&self.wrapper);
+            match last_child_node.and_then(|n| n.dyn_into::<Element>().ok()) {
+                Some(ref element)
+                    if self.options.trailing_br_workaround && element.tag_name() == "BR" =>
+                {
+                    self.wrapper
+                        .insert_before(node_ref, Some(element))
+                        .dom_err("insert child")?;
+                }
+                Some(_) | None => {
+                    self.wrapper
+                        .append_child(node_ref)
+                        .dom_err("append child")?;
+                }
+            };
+        }
+        // Make sure that a trailing newline is rendered
+        if last_node.node_name() == "BR" {
+            update_filler(&self.document, &self.wrapper)?;
+        }
+
+        // Update selection
+        let result = self.select_positions(&Position::After(last_node), None);
+
+        // Normalize elements
+        self.normalize();
+        self.update_model();
+        self.finish_change(before, ChangeKind::Other);
+        result?;
+        self.refresh_markup()
+    }
+
+    /// Re-render the document model if markup highlighting is enabled, so
+    /// that contents inserted into the DOM are highlighted. The selection is
+    /// kept at the same logical offsets.
+    fn refresh_markup(&mut self) -> Result<(), ComposeAreaError> {
+        if !self.options.highlight_markup {
+            return Ok(());
+        }
+        self.render_model(self.model_selection)
+    }
+
+    /// Normalize the contents of the wrapper element.
+    ///
+    /// See <https://developer.mozilla.org/en-US/docs/Web/API/Node/normalize>
+    fn normalize(&self) {
+        trace!("[compose_area]   normalize");
+        self.wrapper.normalize();
+    }
+
+    /// Return the DOM selection.
+    fn fetch_selection(&self) -> Result<Selection, ComposeAreaError> {
+        trace!("[compose_area]   fetch_selection");
+        self.window
+            .get_selection()
+            .dom_err("get selection from window")?
+            .ok_or(ComposeAreaError::NoSelection)
+    }
+
+    fn fetch_range(&self) -> Result<RangeResult, JsValue> {
+        trace!("[compose_area] fetch_range");
+        let selection = self.fetch_selection()?;
+        Ok(match find_range(&selection, &self.wrapper)? {
+            Some((range, true)) => RangeResult::contained(range),
+            Some((range, false)) => RangeResult::outside(range),
+            None => RangeResult::none(),
+        })
+    }
+
+    fn get_text(&self, no_trim: Option<bool>) -> String {
+        debug!("[compose_area] get_text");
+        let text = self.extract().document.to_text();
+        if no_trim.unwrap_or(!self.options.trim_text) {
+            text
+        } else {
+            text.trim().to_string()
+        }
+    }
+
+    fn extract_segments(&self) -> js_sys::Array {
+        debug!("[compose_area] extract_segments");
+        self.extract()
+            .segments
+            .into_iter()
+            .map(JsValue::from)
+            .collect()
+    }
+
+    fn focus(&self) -> Result<(), JsValue> {
+        debug!("[compose_area] focus");
+        self.restore_selection_range()?;
+        if let Some(e) = self.wrapper.dyn_ref::<HtmlElement>() {
+            e.focus().dom_err("focus compose area")?;
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::needless_pass_by_value)] // Optional arguments must be owned
+    fn set_text(
+        &mut self,
+        text: &str,
+        emoji_resolver: Option<js_sys::Function>,
+        caret: &JsValue,
+    ) -> Result<(), JsValue> {
+        debug!("[compose_area] set_text ({})", text);
+        let before = self.begin_change();
+        let document = self.parse_mentions(&Document::from_text(text));
+        let default_resolver = self.options.emoji_resolver.as_ref();
+        let document = match emoji_resolver.as_ref().or(default_resolver) {
+            Some(resolver) => resolve_emoji(resolver, &document),
+            None => document,
+        };
+        self.model = match self.limit_insertion(&document, Some((0, self.model.len()))) {
+            Some(document) => document,
+            None => return Ok(()),
+        };
+        let end = self.model.len();
+        let caret = match (caret.as_f64(), caret.as_string().as_deref()) {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            (Some(offset), _) => (offset.max(0.0) as u32).min(end),
+            (None, Some("start")) => 0,
+            (None, Some("end")) | (None, None) => end,
+            (None, Some(other)) => {
+                warn!("[compose_area] set_text: Invalid caret position: {}", other);
+                end
+            }
+        };
+        let result = self.render_model(Some((caret, caret)));
+        self.finish_change(before, ChangeKind::Other);
+        Ok(result?)
+    }
+
+    fn save_state(&mut self) -> String {
+        debug!("[compose_area] save_state");
+        self.sync_model();
+        state::serialize(&self.snapshot())
+    }
+
+    fn restore_state(&mut self, state: &str) -> Result<(), JsValue> {
+        debug!("[compose_area] restore_state");
+        let mut snapshot =
+            state::deserialize(state).map_err(|e| ComposeAreaError::InvalidState(e.to_string()))?;
+        // The state may have been modified (e.g. if it is stored in local
+        // storage), so it is sanitized like pasted data. Unsafe images are
+        // replaced by their alt text, so the offsets stay the same.
+        snapshot.document = sanitize_document(&snapshot.document);
+        self.history = History::new();
+        Ok(self.restore_snapshot(snapshot)?)
+    }
+
+    fn clear(&mut self) -> Result<(), JsValue> {
+        debug!("[compose_area] clear");
+        let before = self.begin_change();
+        self.model = Document::new();
+        let result = self.render_model(None);
+        self.finish_change(before, ChangeKind::Other);
+        Ok(result?)
+    }
+
+    fn get_word_at_caret(&mut self) -> Option<WordAtCaret> {
+        debug!("[compose_area] get_word_at_caret");
+
+        // Only a caret (not a selection) has a word
+        let range = self.stored_range()?;
+        if !range.collapsed() {
+            return None;
+        }
+
+        let extraction = self.extract();
+        let (caret, _) = range_to_offsets(&extraction, &range)?;
+        let rules = &self.options.word_rules;
+        let (start, end) = rules.word_in_document(&extraction.document, caret);
+
+        // Create a range spanning the word
+        let word_range = self.document.create_range().ok()?;
+        let (start_node, start_offset) = offset_to_position(&extraction, start)?;
+        let (end_node, end_offset) = offset_to_position(&extraction, end)?;
+        set_range_positions(
+            &word_range,
+            &Position::Offset(&start_node, start_offset),
+            Some(&Position::Offset(&end_node, end_offset)),
+        )
+        .ok()?;
+
+        Some(WordAtCaret {
+            range: word_range,
+            before: extraction.document.slice(start, caret).to_text(),
+            after: extraction.document.slice(caret, end).to_text(),
+            offsets: (start, end),
+            mode: rules.mode,
+        })
+    }
+
+    fn select_word_at_caret(&mut self) -> Result<bool, JsValue> {
+        debug!("[compose_area] select_word_at_caret");
+
+        if let Some(wac) = self.get_word_at_caret() {
+            self.select_positions(
+                &Position::Offset(&wac.range.start_container()?, wac.range.start_offset()?),
+                Some(&Position::Offset(
+                    &wac.range.end_container()?,
+                    wac.range.end_offset()?,
+                )),
+            )?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn caret_rect(&self) -> Result<Option<CaretRect>, JsValue> {
+        debug!("[compose_area] caret_rect");
+        let range = match self.stored_range() {
+            Some(range) => range.clone_range(),
+            None => return Ok(None),
+        };
+        range.collapse_with_to_start(false);
+        Ok(Some(range_rect(&self.document, &range)?))
+    }
+
+    fn word_at_caret_rect(&mut self) -> Result<Option<CaretRect>, JsValue> {
+        debug!("[compose_area] word_at_caret_rect");
+        match self.get_word_at_caret() {
+            Some(wac) => Ok(Some(range_rect(&self.document, &wac.range)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn replace_word_at_caret_with_text(&mut self, text: &str) -> Result<bool, JsValue> {
+        debug!("[compose_area] replace_word_at_caret_with_text ({})", text);
+        let replacement = self.replace_emoji(&Document::from_text(text));
+        self.replace_word_at_caret(&replacement)
+    }
+
+    fn replace_word_at_caret_with_image(
+        &mut self,
+        src: &str,
+        alt: &str,
+        cls: &str,
+    ) -> Result<bool, JsValue> {
+        debug!("[compose_area] replace_word_at_caret_with_image ({})", alt);
+        let replacement = Document::from_segments(vec![Segment::image(src, alt, cls)]);
+        self.replace_word_at_caret(&replacement)
+    }
+
+    /// Replace the word at the caret with a document as a single change.
+    fn replace_word_at_caret(&mut self, replacement: &Document) -> Result<bool, JsValue> {
+        let before = self.begin_change();
+        let (start, end) = match self.get_word_at_caret() {
+            Some(wac) => wac.offsets,
+            None => return Ok(false),
+        };
+        let replacement = match self.limit_insertion(replacement, Some((start, end))) {
+            Some(replacement) => replacement,
+            None => return Ok(false),
+        };
+        let caret = self.model.replace_range(start, end, &replacement);
+        let result = self.render_model(Some((caret, caret)));
+        self.finish_change(before, ChangeKind::Other);
+        result?;
+        Ok(true)
+    }
+}
+
+impl Drop for Editor {
+    fn drop(&mut self) {
+        // Pending notifications must not be reported for a freed compose area
+        self.notifier.destroy();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn init() -> Editor {
+        init_with_options(&ComposeAreaOptions::new().log_level("trace"))
+    }
+
+    fn init_with_options(options: &ComposeAreaOptions) -> Editor {
+        Editor::new(create_wrapper(), options).unwrap()
+    }
+
+    /// Initialize a compose area, including the event listeners that are
+    /// attached with a reference to the editor.
+    fn init_compose_area(options: &ComposeAreaOptions) -> ComposeArea {
+        ComposeArea::bind_with_options(create_wrapper(), options).unwrap()
+    }
+
+    fn create_wrapper() -> Element {
+        // Get references
+        let window = web_sys::window().expect("No global `window` exists");
+        let document = window.document().expect("Should have a document on window");
+
+        // Create wrapper element
+        let wrapper = document
+            .create_element("div")
+            .expect("Could not create wrapper div");
+        wrapper
+            .set_attribute("style", "white-space: pre-wrap;")
+            .expect("Could not set style on wrapper div");
+        document.body().unwrap().append_child(&wrapper).unwrap();
+        wrapper
+    }
+
+    /// Create and return a text node.
+    fn text_node(ca: &Editor, text: &str) -> Node {
+        ca.document.create_text_node(text).unchecked_into()
+    }
+
+    /// Create and return a newline node.
+    fn element_node(ca: &Editor, name: &str) -> Node {
+        ca.document.create_element(name).unwrap().unchecked_into()
+    }
+
+    /// Return a JS function that records its arguments.
+    fn recorder<T: wasm_bindgen::convert::FromWasmAbi + 'static>(
+    ) -> (Rc<RefCell<Vec<T>>>, js_sys::Function) {
+        let calls = Rc::new(RefCell::new(vec![]));
+        let calls_clone = Rc::clone(&calls);
+        let closure = Closure::wrap(
+            Box::new(move |value: T| calls_clone.borrow_mut().push(value)) as Box<dyn FnMut(T)>,
+        );
+        let function = closure.as_ref().unchecked_ref::<js_sys::Function>().clone();
+        closure.forget();
+        (calls, function)
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    struct Img {
+        src: &'static str,
+        alt: &'static str,
+        cls: &'static str,
+    }
+
+    impl Img {
+        fn html(&self, ca: &Editor, counter: u32) -> String {
+            format!(
+                r#"<img id="__$$compose_area_{}_img_{}" src="{}" alt="{}" class="{}">"#,
+                ca.instance_id, counter, self.src, self.alt, self.cls,
+            )
+        }
+
+        fn as_node(&self, ca: &Editor) -> Node {
+            let img = ca.document.create_element("img").unwrap();
+            img.set_attribute("src", self.src).unwrap();
+            img.set_attribute("alt", self.alt).unwrap();
+            img.set_attribute("class", self.cls).unwrap();
+            img.unchecked_into()
+        }
+    }
+
+    mod insert_node {
+        use super::*;
+
+        use std::convert::TryFrom;
+
+        struct PositionByIndex {
+            /// The index of the child nodes.
+            ///
+            /// For example, `[1]` means "the second child node". `[1, 0]`
+            /// means the first child node of the first child node.
+            node_index: Vec<usize>,
+            offset: Option<u32>,
+        }
+
+        impl PositionByIndex {
+            fn offset(node_index: usize, offset: u32) -> Self {
+                Self {
+                    node_index: vec![node_index],
+                    offset: Some(offset),
+                }
+            }
+
+            fn after(node_index: usize) -> Self {
+                Self {
+                    node_index: vec![node_index],
+                    offset: None,
+                }
+            }
+
+            fn after_nested(node_index: Vec<usize>) -> Self {
+                Self {
+                    node_index,
+                    offset: None,
+                }
+            }
+        }
+
+        struct InsertNodeTest<N> {
+            children: Vec<Node>,
+            selection_start: PositionByIndex,
+            selection_end: Option<PositionByIndex>,
+            node: N,
+            final_html: String,
+        }
+
+        impl<N> InsertNodeTest<N> {
+            fn get_node(&self, indices: &[usize]) -> Node {
+                assert!(!indices.is_empty());
+                let mut node: Node = self.children.as_slice().get(indices[0]).unwrap().clone();
+                for i in indices.iter().skip(1) {
+                    node = node
+                        .unchecked_ref::<Element>()
+                        .child_nodes()
+                        .item(u32::try_from(*i).unwrap())
+                        .expect("Child node not found");
+                }
+                node
+            }
+
+            fn do_test<F>(&self, mut ca: &mut Editor, insert_func: F)
+            where
+                F: FnOnce(&mut Editor, &N),
+            {
+                // Add child nodes
+                for child in &self.children {
+                    ca.wrapper.append_child(child).unwrap();
+                }
+
+                // Add selection
+                let node_start = self.get_node(&self.selection_start.node_index);
+                let pos_start = {
+                    match self.selection_start.offset {
+                        Some(offset) => Position::Offset(&node_start, offset),
+                        None => Position::After(&node_start),
+                    }
+                };
+                match self.selection_end {
+                    Some(ref sel) => {
+                        let node_end = self.get_node(&sel.node_index);
+                        set_selection_range(
+                            &pos_start,
+                            Some(&match sel.offset {
+                                Some(offset) => Position::Offset(&node_end, offset),
+                                None => Position::After(&node_end),
+                            }),
+                        )
+                        .unwrap()
+                    }
+                    None => set_selection_range(&pos_start, None).unwrap(),
+                };
+
+                // Insert node and verify
+                ca.store_selection_range().unwrap();
+                insert_func(&mut ca, &self.node);
+                assert_eq!(ca.wrapper.inner_html(), self.final_html);
+            }
+        }
+
+        impl InsertNodeTest<&'static str> {
+            fn test(&self, ca: &mut Editor) {
+                self.do_test(ca, |ca, node| {
+                    ca.insert_text(node).unwrap();
+                });
+            }
+        }
+
+        impl InsertNodeTest<Img> {
+            fn test(&self, ca: &mut Editor) {
+                self.do_test(ca, |ca, node| {
+                    ca.insert_image(node.src, node.alt, node.cls).unwrap();
+                });
+            }
+        }
+
+        mod text {
+            use super::*;
+
+            #[wasm_bindgen_test]
+            fn at_end() {
+                let mut ca = init();
+                InsertNodeTest {
+                    children: vec![text_node(&ca, "hello ")],
+                    selection_start: PositionByIndex::after(0),
+                    selection_end: None,
+                    node: "world",
+                    final_html: "hello world".into(),
+                }
+                .test(&mut ca);
+            }
+
+            #[wasm_bindgen_test]
+            fn in_the_middle() {
+                let mut ca = init();
+                InsertNodeTest {
+                    children: vec![text_node(&ca, "ab")],
+                    selection_start: PositionByIndex::offset(0, 1),
+                    selection_end: None,
+                    node: "XY",
+                    final_html: "aXYb".into(),
+                }
+                .test(&mut ca);
+            }
+
+            #[wasm_bindgen_test]
+            fn replace_text() {
+                let mut ca = init();
+                InsertNodeTest {
+                    children: vec![text_node(&ca, "abcd")],
+                    selection_start: PositionByIndex::offset(0, 1),
+                    selection_end: Some(PositionByIndex::offset(0, 3)),
+                    node: "X",
+                    final_html: "aXd".into(),
+                }
+                .test(&mut ca);
+            }
+
+            #[wasm_bindgen_test]
+            fn replace_nodes() {
+                let mut ca = init();
+                let img = Img {
+                    src: "img.jpg",
+                    alt: "😀",
+                    cls: "em",
+                };
+                InsertNodeTest {
+                    children: vec![text_node(&ca, "ab"), img.as_node(&ca)],
+                    selection_start: PositionByIndex::offset(0, 1),
+                    selection_end: Some(PositionByIndex::after(1)),
+                    node: "z",
+                    final_html: "az".into(),
+                }
+                .test(&mut ca);
+            }
+
+            #[wasm_bindgen_test]
+            fn trailing_newline() {
+                let mut ca = init();
+                ca.insert_text("a\n").unwrap();
+                assert_eq!(ca.wrapper.inner_html(), r#"a<br><br data-ca-filler="">"#);
+                ca.insert_text("b").unwrap();
+                assert_eq!(ca.get_text(None), "a\nb");
+            }
+        }
+
+        mod image {
+            use super::*;
+
+            #[wasm_bindgen_test]
+            fn at_end() {
+                let mut ca = init();
+                let img = Img {
+                    src: "img.jpg",
+                    alt: "😀",
+                    cls: "em",
+                };
+                InsertNodeTest {
+                    children: vec![text_node(&ca, "hi ")],
+                    selection_start: PositionByIndex::after(0),
+                    selection_end: None,
+                    node: img,
+                    final_html: format!("hi {}", img.html(&ca, 0)),
+                }
+                .test(&mut ca);
+            }
+
+            /// If there is no selection but a trailing newline, the element
+            /// will be inserted before that trailing newline.
+            #[wasm_bindgen_test]
+            fn at_end_with_br() {
+                let mut ca = init();
+                let img = Img {
+                    src: "img.jpg",
+                    alt: "😀",
+                    cls: "em",
+                };
+
+                // Prepare wrapper
+                ca.wrapper.set_inner_html("<br>");
+
+                // Ensure that there's no selection left in the DOM
+                selection::unset_selection_range();
+
+                // Insert node and verify
+                ca.insert_image(&img.src, &img.alt, &img.cls).unwrap();
+                assert_eq!(ca.wrapper.inner_html(), format!("{}<br>", img.html(&ca, 0)));
+            }
+
+            #[wasm_bindgen_test]
+            fn split_text() {
+                let mut ca = init();
+                let img = Img {
+                    src: "img.jpg",
+                    alt: "😀",
+                    cls: "em",
+                };
+                InsertNodeTest {
+                    children: vec![text_node(&ca, "bonjour")],
+                    selection_start: PositionByIndex::offset(0, 3),
+                    selection_end: None,
+                    node: img,
+                    final_html: format!("bon{}jour", img.html(&ca, 0)),
+                }
+                .test(&mut ca);
+            }
+
+            #[wasm_bindgen_test]
+            fn between_nodes_br() {
+                let mut ca = init();
+                let img = Img {
+                    src: "img.jpg",
+                    alt: "😀",
+                    cls: "em",
+                };
+                InsertNodeTest {
+                    children: vec![
+                        text_node(&ca, "a"),
+                        element_node(&ca, "br"),
+                        text_node(&ca, "b"),
+                    ],
+                    selection_start: PositionByIndex::after(0),
+                    selection_end: None,
+                    node: img,
+                    final_html: format!("a{}<br>b", img.html(&ca, 0)),
+                }
+                .test(&mut ca);
+            }
+
+            #[wasm_bindgen_test]
+            fn between_nodes_div() {
+                let mut ca = init();
+                let img = Img {
+                    src: "img.jpg",
+                    alt: "😀",
+                    cls: "em",
+                };
+                let div_a = {
+                    let div = element_node(&ca, "div");
+                    div.append_child(&text_node(&ca, "a")).unwrap();
+                    div
+                };
+                let div_b = {
+                    let div = element_node(&ca, "div");
+                    div.append_child(&text_node(&ca, "b")).unwrap();
+                    div.append_child(&element_node(&ca, "br")).unwrap();
+                    div
+                };
+                InsertNodeTest {
+                    children: vec![div_a, div_b],
+                    selection_start: PositionByIndex::after_nested(vec![0, 0]),
+                    selection_end: None,
+                    node: img,
+                    final_html: format!("<div>a{}</div><div>b<br></div>", img.html(&ca, 0)),
+                }
+                .test(&mut ca);
+            }
+        }
+    }
+
+    mod image_ids {
+        use super::*;
+
+        #[wasm_bindgen_test]
+        fn multiple_instances() {
+            let mut ca1 = init();
+            let mut ca2 = init();
+            let img1 = ca1.insert_image("a.png", "a", "em").unwrap().unwrap();
+            let img2 = ca2.insert_image("b.png", "b", "em").unwrap().unwrap();
+            assert_ne!(img1.id(), img2.id());
+            assert_eq!(img1.get_attribute("src").as_deref(), Some("a.png"));
+            assert_eq!(img2.get_attribute("src").as_deref(), Some("b.png"));
+            assert!(ca1.wrapper.contains(Some(&img1)));
+            assert!(ca2.wrapper.contains(Some(&img2)));
+        }
+
+        #[wasm_bindgen_test]
+        fn strip_ids() {
+            let mut ca = init();
+            ca.set_strip_image_ids(true);
+            let img = ca
+                .insert_image("a.png", "\"quoted\"", "em")
+                .unwrap()
+                .unwrap();
+            assert!(!img.has_attribute("id"));
+            assert_eq!(img.get_attribute("alt").as_deref(), Some("\"quoted\""));
+            assert!(ca.wrapper.contains(Some(&img)));
+        }
+    }
+
+    mod selection_range {
+        use super::*;
+
+        #[wasm_bindgen_test]
+        fn restore_selection_range() {
+            let mut ca = init();
+            let node = text_node(&ca, "abc");
+            ca.wrapper.append_child(&node).unwrap();
+
+            // Highlight "b"
+            set_selection_range(
+                &Position::Offset(&node, 1),
+                Some(&Position::Offset(&node, 2)),
+            )
+            .unwrap();
+            let range_result = ca.fetch_range().unwrap();
+            assert!(!range_result.outside);
+            let range = range_result.range.expect("Could not get range");
+            assert_eq!(range.start_offset().unwrap(), 1);
+            assert_eq!(range.end_offset().unwrap(), 2);
+
+            // Store range
+            ca.store_selection_range().unwrap();
+
+            // Change range, highlight "a"
+            set_selection_range(
+                &Position::Offset(&node, 0),
+                Some(&Position::Offset(&node, 1)),
+            )
+            .unwrap();
+            let range_result = ca.fetch_range().unwrap();
+            assert!(!range_result.outside);
+            let range = range_result.range.expect("Could not get range");
+            assert_eq!(range.start_offset().unwrap(), 0);
+            assert_eq!(range.end_offset().unwrap(), 1);
+
+            // Retore range
+            ca.restore_selection_range().unwrap();
+            let range_result = ca.fetch_range().unwrap();
+            assert!(!range_result.outside);
+            let range = range_result.range.expect("Could not get range");
+            assert_eq!(range.start_offset().unwrap(), 1);
+            assert_eq!(range.end_offset().unwrap(), 2);
+        }
+
+        #[wasm_bindgen_test]
+        fn get_range_result() {
+            let ca = init();
+            let inner_text_node = text_node(&ca, "abc");
+            ca.wrapper.append_child(&inner_text_node).unwrap();
+
+            // No range set
+            selection::unset_selection_range();
+            let range_result = ca.fetch_range().unwrap();
+            assert!(range_result.range.is_none());
+            assert!(!range_result.outside);
+
+            // Range is outside
+            let outer_text_node = ca.document.create_text_node("hello");
+            ca.document
+                .body()
+                .unwrap()
+                .append_child(&outer_text_node)
+                .unwrap();
+            set_selection_range(&Position::Offset(&outer_text_node, 0), None).unwrap();
+            let range_result = ca.fetch_range().unwrap();
+            assert!(range_result.range.is_some());
+            assert!(range_result.outside);
+
+            // Inside wrapper
+            set_selection_range(&Position::Offset(&inner_text_node, 0), None).unwrap();
+            let range_result = ca.fetch_range().unwrap();
+            assert!(range_result.range.is_some());
+            assert!(!range_result.outside);
+        }
+    }
+
+    mod selection_offsets {
+        use super::*;
+
+        #[wasm_bindgen_test]
+        fn get_offsets() {
+            let mut ca = init();
+            assert_eq!(ca.get_selection_offsets(), None);
+
+            ca.insert_text("ab").unwrap();
+            ca.insert_node(
+                &Img {
+                    src: "img.jpg",
+                    alt: "😀",
+                    cls: "em",
+                }
+                .as_node(&ca),
+            )
+            .unwrap();
+            ca.insert_text("c\nd").unwrap();
+            assert_eq!(ca.get_text(Some(true)), "ab😀c\nd");
+            assert_eq!(
+                ca.get_selection_offsets(),
+                Some(SelectionOffsets { start: 7, end: 7 })
+            );
+
+            let text = ca.wrapper.first_child().unwrap();
+            set_selection_range(&Position::Offset(&text, 1), Some(&Position::After(&text)))
+                .unwrap();
+            ca.store_selection_range().unwrap();
+            assert_eq!(
+                ca.get_selection_offsets(),
+                Some(SelectionOffsets { start: 1, end: 2 })
+            );
+        }
+
+        #[wasm_bindgen_test]
+        fn set_offsets() {
+            let mut ca = init();
+            ca.insert_text("ab").unwrap();
+            ca.insert_node(
+                &Img {
+                    src: "img.jpg",
+                    alt: "😀",
+                    cls: "em",
+                }
+                .as_node(&ca),
+            )
+            .unwrap();
+            ca.insert_text("cd").unwrap();
+
+            ca.set_selection_offsets(5, Some(6)).unwrap();
+            let range = ca.fetch_range().unwrap().range.unwrap();
+            assert_eq!(range.to_string(), "d");
+
+            // Offset inside of the image
+            ca.set_selection_offsets(3, None).unwrap();
+            assert_eq!(
+                ca.get_selection_offsets(),
+                Some(SelectionOffsets { start: 2, end: 2 })
+            );
+
+            // Insert at offset
+            ca.insert_text("X").unwrap();
+            assert_eq!(ca.get_text(None), "abX😀cd");
+        }
+    }
+
+    mod emoji_replacement {
+        use super::*;
+
+        fn resolver() -> js_sys::Function {
+            js_sys::Function::new_with_args(
+                "emoji",
+                "if (emoji === '🍻') { return undefined; } \
+                 return { src: 'emoji/' + emoji.codePointAt(0).toString(16) + '.png', cls: 'em' };",
+            )
+        }
+
+        #[wasm_bindgen_test]
+        fn insert_text() {
+            let mut ca = init();
+            ca.set_emoji_resolver(Some(resolver()));
+            ca.insert_text("hi 😀🍻!").unwrap();
+            assert_eq!(
+                ca.wrapper.inner_html(),
+                r#"hi <img src="emoji/1f600.png" alt="😀" class="em">🍻!"#
+            );
+            assert_eq!(ca.get_text(None), "hi 😀🍻!");
+        }
+
+        #[wasm_bindgen_test]
+        fn disabled() {
+            let mut ca = init();
+            ca.set_emoji_resolver(Some(resolver()));
+            ca.set_emoji_resolver(None);
+            ca.insert_text("😀").unwrap();
+            assert_eq!(ca.wrapper.inner_html(), "😀");
+        }
+    }
+
+    mod set_text {
+        use super::*;
+
+        #[wasm_bindgen_test]
+        fn round_trip() {
+            let mut ca = init();
+            ca.insert_text("old").unwrap();
+            for text in &["hello\nworld", "a\n\nb\n", "\n", "", "😀 \u{200d}x"] {
+                ca.set_text(text, None, &JsValue::UNDEFINED).unwrap();
+                assert_eq!(extract_text(&ca.wrapper, true), *text);
+                assert_eq!(ca.get_text(Some(true)), *text);
+            }
+            ca.set_text("a\nb", None, &JsValue::UNDEFINED).unwrap();
+            assert_eq!(ca.wrapper.inner_html(), "a<br>b");
+        }
+
+        #[wasm_bindgen_test]
+        fn emoji() {
+            let mut ca = init();
+            let resolver = js_sys::Function::new_with_args("emoji", "return { src: 'e.png' };");
+            ca.set_text("hi 😀", Some(resolver), &JsValue::UNDEFINED)
+                .unwrap();
+            assert_eq!(
+                ca.wrapper.inner_html(),
+                r#"hi <img src="e.png" alt="😀" class="">"#
+            );
+            assert_eq!(extract_text(&ca.wrapper, true), "hi 😀");
+        }
+
+        #[wasm_bindgen_test]
+        fn caret() {
+            let mut ca = init();
+            ca.set_text("abc", None, &JsValue::UNDEFINED).unwrap();
+            assert_eq!(ca.model_selection, Some((3, 3)));
+            ca.set_text("abc", None, &"start".into()).unwrap();
+            assert_eq!(ca.model_selection, Some((0, 0)));
+            ca.set_text("abc", None, &JsValue::from(1)).unwrap();
+            assert_eq!(ca.model_selection, Some((1, 1)));
+            ca.insert_text("X").unwrap();
+            assert_eq!(ca.get_text(None), "aXbc");
+        }
+
+        #[wasm_bindgen_test]
+        fn undo() {
+            let mut ca = init();
+            ca.insert_text("draft").unwrap();
+            ca.set_text("message", None, &JsValue::UNDEFINED).unwrap();
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.get_text(None), "draft");
+        }
+    }
+
+    mod state {
+        use super::*;
+
+        #[wasm_bindgen_test]
+        fn save_restore() {
+            let mut ca = init();
+            ca.insert_text("hi ").unwrap();
+            ca.insert_node(
+                &Img {
+                    src: "img.jpg",
+                    alt: "😀",
+                    cls: "em",
+                }
+                .as_node(&ca),
+            )
+            .unwrap();
+            ca.insert_text("\nthere").unwrap();
+            ca.set_selection_offsets(1, Some(2)).unwrap();
+            let state = ca.save_state();
+            let html = ca.wrapper.inner_html();
+
+            let mut other = init();
+            other.insert_text("something else").unwrap();
+            other.restore_state(&state).unwrap();
+            assert_eq!(other.wrapper.inner_html(), html);
+            assert_eq!(other.get_text(None), "hi 😀\nthere");
+            assert_eq!(
+                other.get_selection_offsets(),
+                Some(SelectionOffsets { start: 1, end: 2 })
+            );
+            assert!(!other.can_undo());
+
+            other.insert_text("X").unwrap();
+            assert_eq!(other.get_text(None), "hX 😀\nthere");
+        }
+
+        #[wasm_bindgen_test]
+        fn invalid_state() {
+            let mut ca = init();
+            ca.insert_text("abc").unwrap();
+            let error = ca.restore_state("99||").unwrap_err();
+            assert_eq!(
+                js_sys::Reflect::get(&error, &"kind".into()).unwrap(),
+                "InvalidState"
+            );
+            assert!(ca.restore_state("abc").is_err());
+            assert_eq!(ca.get_text(None), "abc");
+        }
+
+        #[wasm_bindgen_test]
+        fn untrusted_state() {
+            let mut ca = init();
+            ca.restore_state(
+                "1|2,2|i19:javascript:alert(1)4:😀2:em\
+                 k3:tag2:#a1:27:onclick8:alert(1)5:title1:x",
+            )
+            .unwrap();
+            assert_eq!(
+                ca.model.segments(),
+                &[
+                    Segment::Text("😀".into()),
+                    Segment::token("tag", "#a", vec![("title".to_string(), "x".to_string())]),
+                ][..]
+            );
+            assert!(!ca.wrapper.inner_html().contains("onclick"));
+            assert_eq!(
+                ca.get_selection_offsets(),
+                Some(SelectionOffsets { start: 2, end: 2 })
+            );
+        }
+    }
+
+    mod element_handlers {
+        use super::*;
+
+        #[wasm_bindgen_test]
+        fn default_behavior() {
+            let ca = init();
+            ca.wrapper
+                .set_inner_html("a<p>b</p><a href=\"x\">c</a><span>d</span>");
+            assert_eq!(ca.get_text(None), "ad");
+        }
+
+        #[wasm_bindgen_test]
+        fn js_handlers() {
+            let mut ca = init();
+            ca.set_tag_handler(
+                "P",
+                Some(js_sys::Function::new_with_args(
+                    "el",
+                    "return { text: '\\n', recurse: true };",
+                )),
+            );
+            ca.set_tag_handler(
+                "a",
+                Some(js_sys::Function::new_with_args(
+                    "el",
+                    "return { text: el.getAttribute('href') };",
+                )),
+            );
+            ca.set_class_handler(
+                "mention",
+                Some(js_sys::Function::new_with_args(
+                    "el",
+                    "return { text: '@' + el.dataset.user, recurse: false };",
+                )),
+            );
+            ca.wrapper.set_inner_html(concat!(
+                r#"a<p>b</p><a href="https://x.ch">c</a> "#,
+                r#"<span class="mention" data-user="bob">Bob</span>"#,
+            ));
+            assert_eq!(ca.get_text(None), "a\nbhttps://x.ch @bob");
+
+            ca.set_tag_handler("p", None);
+            assert_eq!(ca.get_text(None), "ahttps://x.ch @bob");
+        }
+
+        #[wasm_bindgen_test]
+        fn fallback_to_default() {
+            let mut ca = init();
+            ca.set_tag_handler(
+                "img",
+                Some(js_sys::Function::new_with_args(
+                    "el",
+                    "return el.classList.contains('custom') ? { text: '[img]' } : undefined;",
+                )),
+            );
+            ca.wrapper.set_inner_html(
+                r#"<img src="a.png" alt="😀"><img src="b.png" alt="x" class="custom">"#,
+            );
+            assert_eq!(ca.get_text(None), "😀[img]");
+        }
+
+        #[wasm_bindgen_test]
+        fn rust_handlers() {
+            let mut ca = init_compose_area(&ComposeAreaOptions::new());
+            let mut handlers = ElementHandlers::new();
+            handlers.set_tag_handler(
+                "li",
+                Rc::new(|_| {
+                    Some(ElementOutput {
+                        text: "- ".into(),
+                        recurse: true,
+                    })
+                }),
+            );
+            ca.set_element_handlers(handlers).unwrap();
+            ca.editor
+                .borrow()
+                .wrapper
+                .set_inner_html("<ul><li>a</li></ul>");
+            assert_eq!(ca.get_text(None).unwrap(), "");
+            let mut handlers = ca.element_handlers().unwrap();
+            handlers.set_tag_handler(
+                "ul",
+                Rc::new(|_| {
+                    Some(ElementOutput {
+                        text: "".into(),
+                        recurse: true,
+                    })
+                }),
+            );
+            ca.set_element_handlers(handlers).unwrap();
+            assert_eq!(ca.get_text(None).unwrap(), "- a");
+        }
+    }
+
+    mod clear {
+        use super::*;
+
+        #[wasm_bindgen_test]
+        fn clear_contents() {
+            // Init, no child nodes
+            let mut ca = init();
+            assert_eq!(ca.wrapper.child_nodes().length(), 0);
+
+            // Append some child nodes
+            ca.wrapper.append_child(&text_node(&ca, "abc")).unwrap();
+            ca.wrapper.append_child(&element_node(&ca, "br")).unwrap();
+            assert_eq!(ca.wrapper.child_nodes().length(), 2);
+
+            // Clear
+            ca.clear().unwrap();
+            assert_eq!(ca.wrapper.child_nodes().length(), 0);
+        }
+    }
+
+    mod undo {
+        use super::*;
+
+        #[wasm_bindgen_test]
+        fn undo_redo_insertions() {
+            let mut ca = init();
+            assert!(!ca.can_undo());
+
+            ca.insert_text("hello").unwrap();
+            ca.insert_node(&element_node(&ca, "br")).unwrap();
+            ca.insert_text("world").unwrap();
+            assert_eq!(ca.wrapper.inner_html(), "hello<br>world");
+            assert!(ca.can_undo());
+            assert!(!ca.can_redo());
+
+            assert!(ca.undo().unwrap());
+            assert_eq!(
+                ca.wrapper.inner_html(),
+                r#"hello<br><br data-ca-filler="">"#
+            );
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.wrapper.inner_html(), "hello");
+            assert!(ca.can_redo());
+
+            assert!(ca.redo().unwrap());
+            assert_eq!(
+                ca.wrapper.inner_html(),
+                r#"hello<br><br data-ca-filler="">"#
+            );
+            assert_eq!(ca.model_selection, Some((6, 6)));
+        }
+
+        #[wasm_bindgen_test]
+        fn undo_drops_unknown_elements() {
+            let mut ca = init();
+            ca.insert_text("a").unwrap();
+            ca.insert_node(&element_node(&ca, "hr")).unwrap();
+            ca.insert_text("b").unwrap();
+            assert_eq!(ca.wrapper.inner_html(), "a<hr>b");
+
+            // The model has no segment for the unknown element
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.wrapper.inner_html(), "a");
+            assert!(ca.redo().unwrap());
+            assert_eq!(ca.wrapper.inner_html(), "ab");
+        }
+
+        #[wasm_bindgen_test]
+        fn undo_clear() {
+            let mut ca = init();
+            ca.insert_text("abc").unwrap();
+            ca.clear().unwrap();
+            assert_eq!(ca.get_text(None), "");
+
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.get_text(None), "abc");
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.get_text(None), "");
+            assert!(!ca.undo().unwrap());
+        }
+
+        #[wasm_bindgen_test]
+        fn new_change_clears_redo() {
+            let mut ca = init();
+            ca.insert_text("a").unwrap();
+            assert!(ca.undo().unwrap());
+            assert!(ca.can_redo());
+            ca.insert_text("b").unwrap();
+            assert!(!ca.can_redo());
+            assert!(!ca.redo().unwrap());
+        }
+    }
+
+    mod beforeinput {
+        use super::*;
+
+        use web_sys::InputEventInit;
+
+        /// Dispatch a `beforeinput` event to the compose area.
+        pub(super) fn input(ca: &mut Editor, input_type: &str, data: Option<&str>) -> bool {
+            let mut init = InputEventInit::new();
+            init.cancelable(true);
+            // Note: `data` and `inputType` are not yet supported by web-sys
+            js_sys::Reflect::set(&init, &"inputType".into(), &input_type.into()).unwrap();
+            if let Some(data) = data {
+                js_sys::Reflect::set(&init, &"data".into(), &data.into()).unwrap();
+            }
+            let event = InputEvent::new_with_event_init_dict("beforeinput", &init).unwrap();
+            ca.handle_beforeinput(&event).unwrap()
+        }
+
+        #[wasm_bindgen_test]
+        fn typing() {
+            let mut ca = init();
+            assert!(input(&mut ca, "insertText", Some("a")));
+            assert!(input(&mut ca, "insertText", Some("b")));
+            assert!(input(&mut ca, "insertParagraph", None));
+            assert!(input(&mut ca, "insertText", Some("c")));
+            assert_eq!(ca.wrapper.inner_html(), "ab<br>c");
+            assert_eq!(ca.model_selection, Some((4, 4)));
+
+            assert!(input(&mut ca, "deleteContentBackward", None));
+            assert_eq!(ca.wrapper.inner_html(), r#"ab<br><br data-ca-filler="">"#);
+            assert_eq!(ca.get_text(Some(true)), "ab\n");
+        }
+
+        #[wasm_bindgen_test]
+        fn max_length() {
+            let mut ca = init_with_options(&ComposeAreaOptions::new().max_length(Some(4)));
+            assert!(input(&mut ca, "insertText", Some("a😀bc")));
+            assert_eq!(ca.get_text(None), "a😀b");
+            assert!(input(&mut ca, "insertText", Some("d")));
+            assert!(input(&mut ca, "insertParagraph", None));
+            assert_eq!(ca.get_text(None), "a😀b");
+
+            // Replacing the selection
+            ca.set_selection_offsets(0, Some(1)).unwrap();
+            assert!(input(&mut ca, "insertText", Some("xyz")));
+            assert_eq!(ca.get_text(None), "x😀b");
+        }
+
+        #[wasm_bindgen_test]
+        fn delete_image() {
+            let mut ca = init();
+            ca.insert_text("a").unwrap();
+            ca.insert_node(
+                &Img {
+                    src: "img.jpg",
+                    alt: "😀",
+                    cls: "em",
+                }
+                .as_node(&ca),
+            )
+            .unwrap();
+            assert!(input(&mut ca, "deleteContentBackward", None));
+            assert_eq!(ca.wrapper.inner_html(), "a");
+        }
+
+        #[wasm_bindgen_test]
+        fn delete_before_image() {
+            let mut ca = init();
+            ca.insert_text("a").unwrap();
+            let img = ca.insert_image("img.jpg", "😀", "em").unwrap().unwrap();
+            ca.set_selection_offsets(1, None).unwrap();
+            assert!(input(&mut ca, "deleteContentBackward", None));
+
+            // The inserted image element is kept, including its ID
+            assert_eq!(ca.get_text(None), "😀");
+            assert!(img.is_connected());
+            assert_eq!(ca.wrapper.first_child().unwrap(), **img);
+            assert!(!img.id().is_empty());
+        }
+
+        #[wasm_bindgen_test]
+        fn typing_emoji() {
+            let mut ca = init();
+            ca.set_emoji_resolver(Some(js_sys::Function::new_with_args(
+                "emoji",
+                "return { src: 'emoji.png' };",
+            )));
+            assert!(input(&mut ca, "insertText", Some("a")));
+            assert!(input(&mut ca, "insertText", Some("👍🏽")));
+            assert!(input(&mut ca, "insertText", Some("b")));
+            assert_eq!(
+                ca.wrapper.inner_html(),
+                r#"a<img src="emoji.png" alt="👍🏽" class="">b"#
+            );
+            assert_eq!(ca.get_text(None), "a👍🏽b");
+            assert_eq!(ca.model_selection, Some((6, 6)));
+        }
+
+        #[wasm_bindgen_test]
+        fn undo() {
+            let mut ca = init();
+            assert!(input(&mut ca, "insertText", Some("a")));
+            assert!(input(&mut ca, "insertText", Some("b")));
+            assert!(input(&mut ca, "historyUndo", None));
+            assert_eq!(ca.get_text(None), "");
+            assert!(input(&mut ca, "historyRedo", None));
+            assert_eq!(ca.get_text(None), "ab");
+        }
+
+        #[wasm_bindgen_test]
+        fn replacement_without_target_range() {
+            let mut ca = init();
+            ca.insert_text("wrold").unwrap();
+            assert!(!input(&mut ca, "insertReplacementText", Some("world")));
+            assert_eq!(ca.get_text(None), "wrold");
+        }
+
+        #[wasm_bindgen_test]
+        fn ignored() {
+            let mut ca = init();
+            ca.insert_text("a").unwrap();
+            assert!(input(&mut ca, "formatBold", None));
+            assert!(!input(&mut ca, "insertCompositionText", Some("b")));
+            assert_eq!(ca.wrapper.inner_html(), "a");
+        }
+    }
+
+    mod paste {
+        use super::*;
+
+        fn init_paste() -> Editor {
+            init_with_options(&ComposeAreaOptions::new().paste_image_classes("em"))
+        }
+
+        #[wasm_bindgen_test]
+        fn html() {
+            let mut ca = init_paste();
+            ca.insert_text("ab").unwrap();
+            ca.set_selection_offsets(1, None).unwrap();
+            assert!(ca
+                .paste(
+                    r#"<p>x <b>y</b><script>alert(1)</script></p><p onclick="alert(1)">z</p>"#,
+                    "x y\nz",
+                )
+                .unwrap());
+            assert_eq!(ca.wrapper.inner_html(), "ax y<br>zb");
+            assert_eq!(ca.model_selection, Some((6, 6)));
+        }
+
+        #[wasm_bindgen_test]
+        fn images() {
+            let mut ca = init_paste();
+            assert!(ca
+                .paste(
+                    r#"<img src="e.png" alt="😀" class="em big" onerror="alert(1)"><img src="javascript:alert(1)" alt="😎" class="em"><img src="a.png" alt="!">"#,
+                    "",
+                )
+                .unwrap());
+            assert_eq!(
+                ca.wrapper.inner_html(),
+                r#"<img src="e.png" alt="😀" class="em">😎!"#
+            );
+        }
+
+        #[wasm_bindgen_test]
+        fn plain_text() {
+            let mut ca = init_paste();
+            assert!(ca.paste("", "a\r\nb").unwrap());
+            assert_eq!(ca.get_text(None), "a\nb");
+            assert!(!ca.paste("<script>alert(1)</script>", "").unwrap());
+            assert_eq!(ca.get_text(None), "a\nb");
+        }
+
+        #[wasm_bindgen_test]
+        fn undo() {
+            let mut ca = init_paste();
+            ca.insert_text("a").unwrap();
+            ca.paste("<div>b</div><div>c</div>", "").unwrap();
+            assert_eq!(ca.get_text(None), "ab\nc");
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.get_text(None), "a");
+        }
+
+        #[wasm_bindgen_test]
+        fn max_length() {
+            let options = ComposeAreaOptions::new()
+                .paste_image_classes("em")
+                .max_length(Some(3));
+            let mut ca = init_with_options(&options);
+            ca.paste(r#"a<img src="e.png" alt="😀" class="em">b"#, "")
+                .unwrap();
+            assert_eq!(ca.get_text(None), "a😀");
+        }
+    }
+
+    mod clipboard {
+        use super::*;
+
+        /// Create a clipboard event with a writable data transfer.
+        fn clipboard_event(name: &str) -> (web_sys::Event, DataTransfer) {
+            let event = web_sys::Event::new(name).unwrap();
+            let data_transfer = DataTransfer::new().unwrap();
+            // Note: `ClipboardEvent` is still an unstable API in web-sys
+            js_sys::Reflect::set(&event, &"clipboardData".into(), &data_transfer).unwrap();
+            (event, data_transfer)
+        }
+
+        fn init_with_image() -> Editor {
+            let mut ca = init();
+            let resolver =
+                js_sys::Function::new_with_args("emoji", "return { src: 'e.png', cls: 'em' };");
+            ca.set_text("a😀b\nc", Some(resolver), &JsValue::UNDEFINED)
+                .unwrap();
+            ca
+        }
+
+        #[wasm_bindgen_test]
+        fn copy_and_paste() {
+            let mut ca = init_with_image();
+            ca.set_selection_offsets(0, Some(5)).unwrap();
+            let (event, data_transfer) = clipboard_event("copy");
+            assert!(ca.handle_copy(&event).unwrap());
+            assert_eq!(data_transfer.get_data("text/plain").unwrap(), "a😀b\n");
+            assert_eq!(ca.get_text(None), "a😀b\nc");
+
+            ca.set_selection_offsets(6, None).unwrap();
+            let (event, _) = clipboard_event("paste");
+            js_sys::Reflect::set(&event, &"clipboardData".into(), &data_transfer).unwrap();
+            assert!(ca.handle_paste(&event).unwrap());
+            assert_eq!(
+                ca.wrapper.inner_html(),
+                r#"a<img src="e.png" alt="😀" class="em">b<br>ca<img src="e.png" alt="😀" class="em">b<br><br data-ca-filler="">"#
+            );
+        }
+
+        #[wasm_bindgen_test]
+        fn cut() {
+            let mut ca = init_with_image();
+            ca.set_selection_offsets(1, Some(3)).unwrap();
+            let (event, data_transfer) = clipboard_event("cut");
+            assert!(ca.handle_cut(&event).unwrap());
+            assert_eq!(data_transfer.get_data("text/plain").unwrap(), "😀");
+            assert_eq!(ca.get_text(None), "ab\nc");
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.get_text(None), "a😀b\nc");
+        }
+
+        #[wasm_bindgen_test]
+        fn empty_selection() {
+            let mut ca = init_with_image();
+            ca.set_selection_offsets(1, None).unwrap();
+            let (event, data_transfer) = clipboard_event("cut");
+            assert!(!ca.handle_cut(&event).unwrap());
+            assert_eq!(data_transfer.get_data("text/plain").unwrap(), "");
+            assert_eq!(ca.get_text(None), "a😀b\nc");
+        }
+
+        #[wasm_bindgen_test]
+        fn untrusted_data() {
+            let mut ca = init();
+            let (event, data_transfer) = clipboard_event("paste");
+            data_transfer
+                .set_data(
+                    crate::state::CLIPBOARD_TYPE,
+                    "1||t1:ai19:javascript:alert(1)4:😀2:em",
+                )
+                .unwrap();
+            assert!(ca.handle_paste(&event).unwrap());
+            assert_eq!(ca.wrapper.inner_html(), "a😀");
+        }
+    }
+
+    mod word_at_caret {
+        use super::*;
+
+        #[wasm_bindgen_test]
+        fn empty() {
+            let mut ca = init();
+            let wac = ca.get_word_at_caret();
+            assert!(wac.is_none());
+        }
+
+        #[wasm_bindgen_test]
+        fn in_text() {
+            let mut ca = init();
+
+            let text = ca.document.create_text_node("hello world!\tgoodbye.");
+            ca.wrapper.append_child(&text).unwrap();
+            set_selection_range(&Position::Offset(&text, 9), None).unwrap();
+            ca.store_selection_range().unwrap();
+
+            let wac = ca
+                .get_word_at_caret()
+                .expect("get_word_at_caret returned None");
+            assert_eq!(&wac.before(), "wor");
+            assert_eq!(&wac.after(), "ld!");
+            assert_eq!(wac.start_offset(), 6);
+            assert_eq!(wac.end_offset(), 12);
+        }
+
+        #[wasm_bindgen_test]
+        fn after_text() {
+            let mut ca = init();
+
+            let text = ca.document.create_text_node("hello world");
+            ca.wrapper.append_child(&text).unwrap();
+            set_selection_range(&Position::After(&text), None).unwrap();
+            ca.store_selection_range().unwrap();
+
+            let wac = ca
+                .get_word_at_caret()
+                .expect("get_word_at_caret returned None");
+            assert_eq!(&wac.before(), "world");
+            assert_eq!(&wac.after(), "");
+            assert_eq!(wac.start_offset(), 6);
+            assert_eq!(wac.end_offset(), 11);
+        }
+
+        #[wasm_bindgen_test]
+        fn before_word() {
+            let mut ca = init();
+
+            let text = ca.document.create_text_node("hello world");
+            ca.wrapper.append_child(&text).unwrap();
+            set_selection_range(&Position::Offset(&text, 0), None).unwrap();
+            ca.store_selection_range().unwrap();
+
+            let wac = ca
+                .get_word_at_caret()
+                .expect("get_word_at_caret returned None");
+            assert_eq!(&wac.before(), "");
+            assert_eq!(&wac.after(), "hello");
+            assert_eq!(wac.start_offset(), 0);
+            assert_eq!(wac.end_offset(), 5);
+        }
+
+        #[wasm_bindgen_test]
+        fn single_word() {
+            let mut ca = init();
+
+            let text = ca.document.create_text_node(":ok:");
+            ca.wrapper.append_child(&text).unwrap();
+            set_selection_range(&Position::Offset(&text, 4), None).unwrap();
+            ca.store_selection_range().unwrap();
+
+            let wac = ca
+                .get_word_at_caret()
+                .expect("get_word_at_caret returned None");
+            assert_eq!(&wac.before(), ":ok:");
+            assert_eq!(&wac.after(), "");
+            assert_eq!(wac.start_offset(), 0);
+            assert_eq!(wac.end_offset(), 4);
+        }
+
+        /// Return the word at the caret offset in a single text node.
+        fn word_at(options: &ComposeAreaOptions, text: &str, offset: u32) -> WordAtCaret {
+            let mut ca = init_with_options(options);
+            let text = ca.document.create_text_node(text);
+            ca.wrapper.append_child(&text).unwrap();
+            set_selection_range(&Position::Offset(&text, offset), None).unwrap();
+            ca.store_selection_range().unwrap();
+            ca.get_word_at_caret()
+                .expect("get_word_at_caret returned None")
+        }
+
+        #[wasm_bindgen_test]
+        fn boundaries() {
+            let wac = word_at(&ComposeAreaOptions::new(), "hi,\u{a0}wor😀ld", 9);
+            assert_eq!(&wac.before(), "wor😀");
+            assert_eq!(&wac.after(), "ld");
+            assert_eq!(wac.start_offset(), 4);
+            assert_eq!(wac.end_offset(), 11);
+            assert_eq!(&wac.mode(), "boundaries");
+        }
+
+        #[wasm_bindgen_test]
+        fn unicode() {
+            let options = ComposeAreaOptions::new().word_mode("unicode");
+            let wac = word_at(&options, "(hello, wor", 11);
+            assert_eq!(&wac.before(), "wor");
+            assert_eq!(wac.start_offset(), 8);
+            assert_eq!(&wac.mode(), "unicode");
+
+            let wac = word_at(&options, "@ali", 4);
+            assert_eq!(&wac.before(), "ali");
+        }
+
+        #[wasm_bindgen_test]
+        fn token() {
+            let options = ComposeAreaOptions::new()
+                .word_mode("token")
+                .token_prefixes("@#");
+            let wac = word_at(&options, "hi @ali, #x", 6);
+            assert_eq!(&wac.before(), "@al");
+            assert_eq!(&wac.after(), "i");
+            assert_eq!(wac.start_offset(), 3);
+            assert_eq!(&wac.mode(), "token");
+
+            let wac = word_at(&options, "hi @ali, #", 10);
+            assert_eq!(&wac.before(), "#");
+            assert_eq!(wac.start_offset(), 9);
+            assert_eq!(wac.end_offset(), 10);
+        }
+
+        /// <span>"hel"</span>"lo"|<b>"wo"</b> -> "hel" to "wo"
+        #[wasm_bindgen_test]
+        fn across_nodes() {
+            let mut ca = init();
+            ca.wrapper
+                .set_inner_html("a <span>hel</span>lo<b>wo</b>rld!<br>next");
+            let text = ca.wrapper.child_nodes().get(2).unwrap();
+            set_selection_range(&Position::Offset(&text, 2), None).unwrap();
+            ca.store_selection_range().unwrap();
+
+            let wac = ca
+                .get_word_at_caret()
+                .expect("get_word_at_caret returned None");
+            assert_eq!(&wac.before(), "hello");
+            assert_eq!(&wac.after(), "world!");
+            assert_eq!(wac.start_offset(), 2);
+            assert_eq!(wac.end_offset(), 13);
+            assert_eq!(&String::from(wac.range().to_string()), "helloworld!");
+
+            assert!(ca.select_word_at_caret().unwrap());
+            let offsets = ca.get_selection_offsets().unwrap();
+            assert_eq!((offsets.start(), offsets.end()), (2, 13));
+        }
+
+        #[wasm_bindgen_test]
+        fn images() {
+            let html = r#"ab<img src="e.png" alt="😀">cd"#;
+            let mut ca = init();
+            ca.wrapper.set_inner_html(html);
+            let text = ca.wrapper.child_nodes().get(2).unwrap();
+            set_selection_range(&Position::Offset(&text, 1), None).unwrap();
+            ca.store_selection_range().unwrap();
+            let wac = ca.get_word_at_caret().unwrap();
+            assert_eq!(&wac.before(), "c");
+            assert_eq!(wac.start_offset(), 4);
+
+            let mut ca = init_with_options(&ComposeAreaOptions::new().images_in_words(true));
+            ca.wrapper.set_inner_html(html);
+            let text = ca.wrapper.child_nodes().get(2).unwrap();
+            set_selection_range(&Position::Offset(&text, 1), None).unwrap();
+            ca.store_selection_range().unwrap();
+            let wac = ca.get_word_at_caret().unwrap();
+            assert_eq!(&wac.before(), "ab😀c");
+            assert_eq!(&wac.after(), "d");
+            assert_eq!((wac.start_offset(), wac.end_offset()), (0, 6));
+        }
+
+        /// Set up a compose area with a single text node and the caret at
+        /// the specified offset.
+        fn with_caret(text: &str, offset: u32) -> Editor {
+            let mut ca = init();
+            let text = ca.document.create_text_node(text);
+            ca.wrapper.append_child(&text).unwrap();
+            set_selection_range(&Position::Offset(&text, offset), None).unwrap();
+            ca.store_selection_range().unwrap();
+            ca
+        }
+
+        #[wasm_bindgen_test]
+        fn replace_with_text() {
+            let mut ca = with_caret("hi @al there", 6);
+            assert!(ca.replace_word_at_caret_with_text("@alice ").unwrap());
+            assert_eq!(ca.get_text(Some(true)), "hi @alice  there");
+            let offsets = ca.get_selection_offsets().unwrap();
+            assert_eq!((offsets.start(), offsets.end()), (10, 10));
+
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.get_text(Some(true)), "hi @al there");
+            assert!(!ca.undo().unwrap());
+        }
+
+        #[wasm_bindgen_test]
+        fn replace_with_image() {
+            let mut ca = with_caret("a :smi", 6);
+            assert!(ca
+                .replace_word_at_caret_with_image("smile.png", "😄", "emoji")
+                .unwrap());
+            assert_eq!(
+                ca.wrapper.inner_html(),
+                r#"a <img src="smile.png" alt="😄" class="emoji">"#
+            );
+            assert_eq!(ca.get_text(Some(true)), "a 😄");
+            let offsets = ca.get_selection_offsets().unwrap();
+            assert_eq!((offsets.start(), offsets.end()), (4, 4));
+
+            // Typing continues after the image
+            ca.insert_text("!").unwrap();
+            assert_eq!(ca.get_text(Some(true)), "a 😄!");
+
+            assert!(ca.undo().unwrap());
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.get_text(Some(true)), "a :smi");
+        }
+
+        #[wasm_bindgen_test]
+        fn replace_without_word() {
+            // An empty word: The text is inserted at the caret
+            let mut ca = with_caret("a ", 2);
+            assert!(ca.replace_word_at_caret_with_text("b").unwrap());
+            assert_eq!(ca.get_text(Some(true)), "a b");
+            let offsets = ca.get_selection_offsets().unwrap();
+            assert_eq!((offsets.start(), offsets.end()), (3, 3));
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.get_text(Some(true)), "a ");
+
+            // No stored caret: Nothing is changed
+
+            let mut ca = init();
+            assert!(!ca.replace_word_at_caret_with_text("b").unwrap());
+            assert_eq!(ca.get_text(Some(true)), "");
+        }
+
+        #[wasm_bindgen_test]
+        fn not_collapsed() {
+            let mut ca = init();
+            let text = ca.document.create_text_node("hello");
+            ca.wrapper.append_child(&text).unwrap();
+            set_selection_range(
+                &Position::Offset(&text, 1),
+                Some(&Position::Offset(&text, 3)),
+            )
+            .unwrap();
+            ca.store_selection_range().unwrap();
+            assert!(ca.get_word_at_caret().is_none());
+        }
+    }
+
+    mod caret_rect {
+        use super::*;
+
+        #[wasm_bindgen_test]
+        fn no_range() {
+            let mut ca = init();
+            assert!(ca.caret_rect().unwrap().is_none());
+            assert!(ca.word_at_caret_rect().unwrap().is_none());
+        }
+
+        #[wasm_bindgen_test]
+        fn in_text() {
+            let mut ca = init();
+            ca.wrapper.set_inner_html("hello world");
+            let text = ca.wrapper.first_child().unwrap();
+            set_selection_range(&Position::Offset(&text, 8), None).unwrap();
+            ca.store_selection_range().unwrap();
+
+            let caret = ca.caret_rect().unwrap().unwrap();
+            assert!(caret.width() < f64::EPSILON);
+            assert!(caret.height() > 0.0);
+            assert!(caret.line_height() > 0.0);
+
+            let word = ca.word_at_caret_rect().unwrap().unwrap();
+            assert!(word.width() > 0.0);
+            assert!(word.x() < caret.x());
+            assert!(word.x() + word.width() > caret.x());
+        }
+
+        /// On empty lines and after images, browsers return no client rects
+        /// for the caret.
+        #[wasm_bindgen_test]
+        fn marker_fallback() {
+            let mut ca = init();
+            let html = r#"a<br><br><img src="e.png" alt="😀">"#;
+            ca.wrapper.set_inner_html(html);
+            let text = ca.wrapper.first_child().unwrap();
+            set_selection_range(&Position::Offset(&text, 1), None).unwrap();
+            ca.store_selection_range().unwrap();
+            let first_line = ca.caret_rect().unwrap().unwrap();
 
-        if let Some(wac) = self.get_word_at_caret() {
-            let node = wac.node();
-            set_selection_range(
-                &Position::Offset(&node, wac.start_offset()),
-                Some(&Position::Offset(&node, wac.end_offset())),
-            )
-            .is_some()
-        } else {
-            false
+            for index in &[2, 4] {
+                set_selection_range(&Position::Offset(&ca.wrapper, *index), None).unwrap();
+                ca.store_selection_range().unwrap();
+                let caret = ca.caret_rect().unwrap().unwrap();
+                assert!(caret.height() > 0.0);
+                assert!(caret.y() > first_line.y());
+
+                // The marker was removed again
+                assert_eq!(ca.wrapper.inner_html(), html);
+                let range = ca.stored_range().unwrap();
+                assert_eq!(range.start_offset().unwrap(), *index);
+            }
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    mod mentions {
+        use super::*;
 
-    use wasm_bindgen_test::*;
+        use super::beforeinput::input;
 
-    wasm_bindgen_test_configure!(run_in_browser);
+        #[wasm_bindgen_test]
+        fn insert() {
+            let mut ca = init();
+            ca.insert_text("hi ").unwrap();
+            assert!(ca.insert_mention("ABCD1234", "Alice").unwrap());
+            assert_eq!(
+                ca.wrapper.inner_html(),
+                "hi <span class=\"ca-mention\" contenteditable=\"false\" \
+                 data-ca-mention=\"ABCD1234\" data-ca-token=\"@[ABCD1234]\">Alice</span>"
+            );
+            assert_eq!(ca.get_text(Some(true)), "hi @[ABCD1234]");
+            assert_eq!(extract_text(&ca.wrapper, true), "hi @[ABCD1234]");
+            let offsets = ca.get_selection_offsets().unwrap();
+            assert_eq!((offsets.start(), offsets.end()), (14, 14));
 
-    fn init() -> ComposeArea {
-        // Get references
-        let window = web_sys::window().expect("No global `window` exists");
-        let document = window.document().expect("Should have a document on window");
+            // Deleted as a whole
+            assert!(input(&mut ca, "deleteContentBackward", None));
+            assert_eq!(ca.get_text(Some(true)), "hi ");
 
-        // Create wrapper element
-        let wrapper = document
-            .create_element("div")
-            .expect("Could not create wrapper div");
-        wrapper
-            .set_attribute("style", "white-space: pre-wrap;")
-            .expect("Could not set style on wrapper div");
-        document.body().unwrap().append_child(&wrapper).unwrap();
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.get_text(Some(true)), "hi @[ABCD1234]");
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.get_text(Some(true)), "hi ");
+        }
 
-        // Bind to wrapper
-        ComposeArea::bind_to(wrapper, Some("trace".into()))
-    }
+        #[wasm_bindgen_test]
+        fn invalid_id() {
+            let mut ca = init();
+            for id in &["", "a b", "X1]", "Zoë"] {
+                assert!(!ca.insert_mention(id, "Alice").unwrap());
+            }
+            assert_eq!(ca.wrapper.inner_html(), "");
+            assert!(!ca.undo().unwrap());
+        }
 
-    /// Create and return a text node.
-    fn text_node(ca: &ComposeArea, text: &str) -> Node {
-        ca.document.create_text_node(text).unchecked_into()
+        #[wasm_bindgen_test]
+        fn set_text() {
+            let resolver =
+                js_sys::Function::new_with_args("id", "return id === 'X1' ? 'Alice' : undefined;");
+            let options = ComposeAreaOptions::new()
+                .mention_format("<@{id}>")
+                .mention_resolver(Some(resolver));
+            let mut ca = init_with_options(&options);
+            ca.set_text("<@X1> and <@X2>: hi", None, &JsValue::UNDEFINED)
+                .unwrap();
+            assert_eq!(
+                ca.model.segments(),
+                &[
+                    Segment::mention("X1", "Alice", "<@X1>"),
+                    Segment::Text(" and ".into()),
+                    Segment::mention("X2", "X2", "<@X2>"),
+                    Segment::Text(": hi".into()),
+                ][..]
+            );
+            assert_eq!(ca.get_text(Some(true)), "<@X1> and <@X2>: hi");
+        }
     }
 
-    /// Create and return a newline node.
-    fn element_node(ca: &ComposeArea, name: &str) -> Node {
-        ca.document.create_element(name).unwrap().unchecked_into()
-    }
+    mod tokens {
+        use super::*;
 
-    #[derive(Copy, Clone, Debug)]
-    struct Img {
-        src: &'static str,
-        alt: &'static str,
-        cls: &'static str,
-    }
+        use super::beforeinput::input;
 
-    impl Img {
-        fn html(&self, counter: u32) -> String {
-            format!(
-                r#"<img id="__$$compose_area_img_{}" src="{}" alt="{}" class="{}">"#,
-                counter, self.src, self.alt, self.cls,
-            )
+        #[wasm_bindgen_test]
+        fn insert() {
+            let mut ca = init();
+            ca.insert_text("see ").unwrap();
+            let attrs = js_sys::Object::new();
+            for (name, value) in &[("class", "file"), ("onclick", "alert(1)"), ("data-id", "7")] {
+                js_sys::Reflect::set(&attrs, &(*name).into(), &(*value).into()).unwrap();
+            }
+            assert!(ca.insert_token("file", "[report.pdf]", &attrs).unwrap());
+            assert_eq!(
+                ca.wrapper.inner_html(),
+                "see <span class=\"ca-token file\" contenteditable=\"false\" \
+                 data-ca-kind=\"file\" data-ca-token=\"[report.pdf]\" data-id=\"7\">\
+                 [report.pdf]</span>"
+            );
+            assert_eq!(ca.get_text(Some(true)), "see [report.pdf]");
+            let segments = ca.extract().segments;
+            let token = &segments[1];
+            assert_eq!(token.kind(), SegmentKind::Token);
+            assert_eq!(token.token_kind().as_deref(), Some("file"));
+            assert_eq!((token.start(), token.end()), (4, 16));
+
+            // Deleted as a whole
+            assert!(input(&mut ca, "deleteContentBackward", None));
+            assert_eq!(ca.get_text(Some(true)), "see ");
+            assert!(!ca.insert_token("file", "", &JsValue::UNDEFINED).unwrap());
         }
 
-        fn as_node(&self, ca: &ComposeArea) -> Node {
-            let img = ca.document.create_element("img").unwrap();
-            img.set_attribute("src", self.src).unwrap();
-            img.set_attribute("alt", self.alt).unwrap();
-            img.set_attribute("class", self.cls).unwrap();
-            img.unchecked_into()
+        #[wasm_bindgen_test]
+        fn word_boundary() {
+            let mut ca = init();
+            ca.insert_text("ab").unwrap();
+            ca.insert_token("hashtag", "#cd", &JsValue::UNDEFINED)
+                .unwrap();
+            ca.insert_text("ef").unwrap();
+            let wac = ca.get_word_at_caret().unwrap();
+            assert_eq!(&wac.before(), "ef");
+            assert_eq!(wac.start_offset(), 5);
+        }
+
+        #[wasm_bindgen_test]
+        fn element_handlers() {
+            let mut ca = init();
+            let handler = || {
+                Some(js_sys::Function::new_with_args(
+                    "el",
+                    "return { text: 'handled' };",
+                ))
+            };
+            ca.set_class_handler("ca-token", handler());
+            ca.set_class_handler("ca-mention", handler());
+            ca.set_tag_handler("span", handler());
+            ca.insert_token("hashtag", "#a", &JsValue::UNDEFINED)
+                .unwrap();
+            ca.insert_mention("X1", "Alice").unwrap();
+            assert_eq!(ca.get_text(Some(true)), "#a@[X1]");
+
+            // The elements are kept when the contents are rendered again
+            ca.insert_text("!").unwrap();
+            assert!(ca.undo().unwrap());
+            assert_eq!(
+                ca.model.segments(),
+                &[
+                    Segment::token("hashtag", "#a", vec![]),
+                    Segment::mention("X1", "Alice", "@[X1]"),
+                ][..]
+            );
+            assert_eq!(
+                ca.wrapper
+                    .query_selector_all(".ca-token, .ca-mention")
+                    .unwrap()
+                    .length(),
+                2
+            );
+        }
+
+        #[wasm_bindgen_test]
+        fn untrusted_data() {
+            let mut ca = init();
+            let event = web_sys::Event::new("paste").unwrap();
+            let data_transfer = DataTransfer::new().unwrap();
+            js_sys::Reflect::set(&event, &"clipboardData".into(), &data_transfer).unwrap();
+            data_transfer
+                .set_data(
+                    crate::state::CLIPBOARD_TYPE,
+                    "1||k3:tag2:#a1:27:onclick8:alert(1)5:title1:x",
+                )
+                .unwrap();
+            assert!(ca.handle_paste(&event).unwrap());
+            assert_eq!(
+                ca.model.segments(),
+                &[Segment::token(
+                    "tag",
+                    "#a",
+                    vec![("title".to_string(), "x".to_string())]
+                )][..]
+            );
         }
     }
 
-    mod insert_node {
+    mod markup {
         use super::*;
 
-        use std::convert::TryFrom;
+        use super::beforeinput::input;
 
-        struct PositionByIndex {
-            /// The index of the child nodes.
-            ///
-            /// For example, `[1]` means "the second child node". `[1, 0]`
-            /// means the first child node of the first child node.
-            node_index: Vec<usize>,
-            offset: Option<u32>,
+        fn init_markup() -> Editor {
+            init_with_options(&ComposeAreaOptions::new().highlight_markup(true))
         }
 
-        impl PositionByIndex {
-            fn offset(node_index: usize, offset: u32) -> Self {
-                Self {
-                    node_index: vec![node_index],
-                    offset: Some(offset),
-                }
+        #[wasm_bindgen_test]
+        fn typing() {
+            let mut ca = init_markup();
+            for c in &["a", " ", "*", "b", "*"] {
+                assert!(input(&mut ca, "insertText", Some(c)));
             }
+            assert_eq!(
+                ca.wrapper.inner_html(),
+                "a <span class=\"ca-markup ca-bold ca-marker\">*</span>\
+                 <span class=\"ca-markup ca-bold\">b</span>\
+                 <span class=\"ca-markup ca-bold ca-marker\">*</span>"
+            );
+            assert_eq!(ca.get_text(Some(true)), "a *b*");
+            assert_eq!(ca.model_selection, Some((5, 5)));
 
-            fn after(node_index: usize) -> Self {
-                Self {
-                    node_index: vec![node_index],
-                    offset: None,
-                }
-            }
+            // Typing inside the markup keeps the caret
+            ca.set_selection_offsets(4, None).unwrap();
+            assert!(input(&mut ca, "insertText", Some("c")));
+            assert_eq!(ca.get_text(Some(true)), "a *bc*");
+            assert_eq!(ca.model_selection, Some((5, 5)));
+            assert_eq!(ca.get_selection_offsets().unwrap().start(), 5);
 
-            fn after_nested(node_index: Vec<usize>) -> Self {
-                Self {
-                    node_index,
-                    offset: None,
-                }
-            }
+            // Breaking the markup removes the highlighting
+            assert!(input(&mut ca, "insertText", Some(" ")));
+            assert_eq!(ca.wrapper.inner_html(), "a *bc *");
         }
 
-        struct InsertNodeTest<N> {
-            children: Vec<Node>,
-            selection_start: PositionByIndex,
-            selection_end: Option<PositionByIndex>,
-            node: N,
-            final_html: String,
-        }
+        #[wasm_bindgen_test]
+        fn inserted_contents() {
+            let mut ca = init_markup();
+            ca.insert_text("_hi_").unwrap();
+            assert_eq!(
+                ca.wrapper.inner_html(),
+                "<span class=\"ca-markup ca-italic ca-marker\">_</span>\
+                 <span class=\"ca-markup ca-italic\">hi</span>\
+                 <span class=\"ca-markup ca-italic ca-marker\">_</span>"
+            );
+            assert_eq!(ca.model_selection, Some((4, 4)));
+            ca.set_text("~x~ y", None, &JsValue::UNDEFINED).unwrap();
+            assert_eq!(ca.wrapper.child_nodes().length(), 4);
+            assert_eq!(ca.get_text(Some(true)), "~x~ y");
 
-        impl<N> InsertNodeTest<N> {
-            fn get_node(&self, indices: &[usize]) -> Node {
-                assert!(!indices.is_empty());
-                let mut node: Node = self.children.as_slice().get(indices[0]).unwrap().clone();
-                for i in indices.iter().skip(1) {
-                    node = node
-                        .unchecked_ref::<Element>()
-                        .child_nodes()
-                        .item(u32::try_from(*i).unwrap())
-                        .expect("Child node not found");
-                }
-                node
-            }
+            // Unknown elements are dropped when the markup is highlighted
+            ca.insert_node(&element_node(&ca, "hr")).unwrap();
+            assert_eq!(ca.wrapper.inner_html().matches("<hr>").count(), 0);
+            assert_eq!(ca.get_text(Some(true)), "~x~ y");
+        }
 
-            fn do_test<F>(&self, mut ca: &mut ComposeArea, insert_func: F)
-            where
-                F: FnOnce(&mut ComposeArea, &N),
-            {
-                // Add child nodes
-                for child in &self.children {
-                    ca.wrapper.append_child(child).unwrap();
-                }
+        #[wasm_bindgen_test]
+        fn update_markup() {
+            let mut ca = init_markup();
+            ca.insert_text("*a").unwrap();
 
-                // Add selection
-                let node_start = self.get_node(&self.selection_start.node_index);
-                let pos_start = {
-                    match self.selection_start.offset {
-                        Some(offset) => Position::Offset(&node_start, offset),
-                        None => Position::After(&node_start),
-                    }
-                };
-                match self.selection_end {
-                    Some(ref sel) => {
-                        let node_end = self.get_node(&sel.node_index);
-                        set_selection_range(
-                            &pos_start,
-                            Some(&match sel.offset {
-                                Some(offset) => Position::Offset(&node_end, offset),
-                                None => Position::After(&node_end),
-                            }),
-                        )
-                    }
-                    None => set_selection_range(&pos_start, None),
-                };
+            // Modified by the browser, e.g. by an IME composition
+            let text = ca.wrapper.first_child().unwrap();
+            text.set_node_value(Some("*ab*"));
+            ca.set_selection_offsets(3, None).unwrap();
+            ca.update_markup().unwrap();
+            assert_eq!(ca.wrapper.child_nodes().length(), 3);
+            assert_eq!(ca.get_text(Some(true)), "*ab*");
+            assert_eq!(ca.model_selection, Some((3, 3)));
 
-                // Insert node and verify
-                ca.store_selection_range();
-                insert_func(&mut ca, &self.node);
-                assert_eq!(ca.wrapper.inner_html(), self.final_html);
-            }
+            // Without highlighting, nothing is rendered
+            let mut ca = init();
+            ca.insert_text("*a*").unwrap();
+            ca.update_markup().unwrap();
+            assert_eq!(ca.wrapper.inner_html(), "*a*");
         }
 
-        impl InsertNodeTest<&'static str> {
-            fn test(&self, ca: &mut ComposeArea) {
-                self.do_test(ca, |ca, node| {
-                    ca.insert_text(node);
-                });
-            }
-        }
+        #[wasm_bindgen_test]
+        fn toggle() {
+            let mut ca = init();
+            ca.insert_text("a bc d").unwrap();
+            ca.set_selection_offsets(2, Some(4)).unwrap();
+            assert!(ca.toggle_bold().unwrap());
+            assert_eq!(ca.get_text(Some(true)), "a *bc* d");
+            assert_eq!(ca.model_selection, Some((3, 5)));
+            assert!(ca.toggle_italic().unwrap());
+            assert!(ca.toggle_monospace().unwrap());
+            assert_eq!(ca.get_text(Some(true)), "a *_`bc`_* d");
 
-        impl InsertNodeTest<Img> {
-            fn test(&self, ca: &mut ComposeArea) {
-                self.do_test(ca, |ca, node| {
-                    ca.insert_image(node.src, node.alt, node.cls);
-                });
-            }
+            // Unwrap
+            assert!(ca.toggle_monospace().unwrap());
+            assert_eq!(ca.get_text(Some(true)), "a *_bc_* d");
+            assert_eq!(ca.model_selection, Some((4, 6)));
+
+            // Every toggle is a single undo step
+            assert!(ca.undo().unwrap());
+            assert_eq!(ca.get_text(Some(true)), "a *_`bc`_* d");
+            assert_eq!(ca.model_selection, Some((5, 7)));
+
+            // Collapsed selection
+            ca.set_selection_offsets(12, None).unwrap();
+            assert!(ca.toggle_strikethrough().unwrap());
+            assert_eq!(ca.get_text(Some(true)), "a *_`bc`_* d~~");
+            assert_eq!(ca.model_selection, Some((13, 13)));
         }
 
-        mod text {
-            use super::*;
+        #[wasm_bindgen_test]
+        fn toggle_not_possible() {
+            let mut ca = init_with_options(&ComposeAreaOptions::new().max_length(Some(4)));
+            assert!(!ca.toggle_bold().unwrap());
 
-            #[wasm_bindgen_test]
-            fn at_end() {
-                let mut ca = init();
-                InsertNodeTest {
-                    children: vec![text_node(&ca, "hello ")],
-                    selection_start: PositionByIndex::after(0),
-                    selection_end: None,
-                    node: "world",
-                    final_html: "hello world".into(),
-                }
-                .test(&mut ca);
-            }
+            ca.insert_text("a\nb").unwrap();
+            ca.set_selection_offsets(0, Some(3)).unwrap();
+            assert!(!ca.toggle_bold().unwrap());
 
-            #[wasm_bindgen_test]
-            fn in_the_middle() {
-                let mut ca = init();
-                InsertNodeTest {
-                    children: vec![text_node(&ca, "ab")],
-                    selection_start: PositionByIndex::offset(0, 1),
-                    selection_end: None,
-                    node: "XY",
-                    final_html: "aXYb".into(),
-                }
-                .test(&mut ca);
-            }
+            // Exceeds the maximum length
+            ca.set_selection_offsets(0, Some(1)).unwrap();
+            assert!(!ca.toggle_bold().unwrap());
+            assert_eq!(ca.get_text(Some(true)), "a\nb");
+        }
 
-            #[wasm_bindgen_test]
-            fn replace_text() {
-                let mut ca = init();
-                InsertNodeTest {
-                    children: vec![text_node(&ca, "abcd")],
-                    selection_start: PositionByIndex::offset(0, 1),
-                    selection_end: Some(PositionByIndex::offset(0, 3)),
-                    node: "X",
-                    final_html: "aXd".into(),
-                }
-                .test(&mut ca);
-            }
+        #[wasm_bindgen_test]
+        fn keydown() {
+            let mut ca = init_markup();
+            ca.insert_text("ab").unwrap();
+            ca.set_selection_offsets(0, Some(2)).unwrap();
+            let keydown = |ca: &mut Editor, key: &str| {
+                let mut init = web_sys::KeyboardEventInit::new();
+                init.key(key).ctrl_key(true).cancelable(true);
+                let event =
+                    KeyboardEvent::new_with_keyboard_event_init_dict("keydown", &init).unwrap();
+                let handled = ca.handle_keydown(&event).unwrap();
+                assert_eq!(event.default_prevented(), handled);
+                handled
+            };
+            assert!(keydown(&mut ca, "b"));
+            assert!(keydown(&mut ca, "I"));
+            assert_eq!(ca.get_text(Some(true)), "*_ab_*");
+            assert_eq!(ca.wrapper.child_nodes().length(), 5);
+            assert!(!keydown(&mut ca, "x"));
 
-            #[wasm_bindgen_test]
-            fn replace_nodes() {
-                let mut ca = init();
-                let img = Img {
-                    src: "img.jpg",
-                    alt: "😀",
-                    cls: "em",
-                };
-                InsertNodeTest {
-                    children: vec![text_node(&ca, "ab"), img.as_node(&ca)],
-                    selection_start: PositionByIndex::offset(0, 1),
-                    selection_end: Some(PositionByIndex::after(1)),
-                    node: "z",
-                    final_html: "az".into(),
-                }
-                .test(&mut ca);
-            }
+            // Nothing can be toggled
+            ca.set_selection_offsets(6, None).unwrap();
+            ca.insert_text("\nc").unwrap();
+            ca.set_selection_offsets(0, Some(8)).unwrap();
+            assert!(!keydown(&mut ca, "b"));
+            assert_eq!(ca.get_text(Some(true)), "*_ab_*\nc");
         }
+    }
 
-        mod image {
-            use super::*;
+    mod errors {
+        use super::*;
 
-            #[wasm_bindgen_test]
-            fn at_end() {
-                let mut ca = init();
-                let img = Img {
-                    src: "img.jpg",
-                    alt: "😀",
-                    cls: "em",
-                };
-                InsertNodeTest {
-                    children: vec![text_node(&ca, "hi ")],
-                    selection_start: PositionByIndex::after(0),
-                    selection_end: None,
-                    node: img,
-                    final_html: format!("hi {}", img.html(0)),
-                }
-                .test(&mut ca);
-            }
+        fn kind(error: &JsValue) -> JsValue {
+            js_sys::Reflect::get(error, &"kind".into()).unwrap()
+        }
 
-            /// If there is no selection but a trailing newline, element
-            /// will replace that trailing newline due to the way how the
-            /// `insertHTML` command works.
-            #[wasm_bindgen_test]
-            fn at_end_with_br() {
-                let mut ca = init();
-                let img = Img {
-                    src: "img.jpg",
-                    alt: "😀",
-                    cls: "em",
-                };
+        #[wasm_bindgen_test]
+        fn detached_wrapper() {
+            let document = web_sys::window().unwrap().document().unwrap();
+            let wrapper = document.create_element("div").unwrap();
+            let mut ca = Editor::new(wrapper, &ComposeAreaOptions::new()).unwrap();
 
-                // Prepare wrapper
-                ca.wrapper.set_inner_html("<br>");
+            // Editing works without a DOM selection
+            ca.insert_text("abc").unwrap();
+            ca.set_selection_offsets(1, None).unwrap();
+            assert_eq!(ca.get_text(None), "abc");
+            assert_eq!(
+                ca.get_selection_offsets(),
+                Some(SelectionOffsets { start: 1, end: 1 })
+            );
 
-                // Ensure that there's no selection left in the DOM
-                selection::unset_selection_range();
+            // The selection cannot be restored until the wrapper is attached
+            let error = ca.restore_selection_range().unwrap_err();
+            assert_eq!(kind(&error), "DetachedWrapper");
+            assert!(ca.focus().is_err());
 
-                // Insert node and verify
-                ca.insert_image(&img.src, &img.alt, &img.cls);
-                assert_eq!(ca.wrapper.inner_html(), img.html(0));
-            }
+            document.body().unwrap().append_child(&ca.wrapper).unwrap();
+            assert!(ca.restore_selection_range().unwrap());
+            ca.insert_text("X").unwrap();
+            assert_eq!(ca.get_text(None), "aXbc");
+        }
 
-            #[wasm_bindgen_test]
-            fn split_text() {
-                let mut ca = init();
-                let img = Img {
-                    src: "img.jpg",
-                    alt: "😀",
-                    cls: "em",
-                };
-                InsertNodeTest {
-                    children: vec![text_node(&ca, "bonjour")],
-                    selection_start: PositionByIndex::offset(0, 3),
-                    selection_end: None,
-                    node: img,
-                    final_html: format!("bon{}jour", img.html(0)),
+        /// Return a JS function that calls a method of the compose area and
+        /// records the kind of the error it returns.
+        fn reentrant_function(
+            ca: &ComposeArea,
+            call: fn(&mut ComposeArea) -> Result<(), JsValue>,
+        ) -> (Rc<RefCell<Vec<JsValue>>>, js_sys::Function) {
+            let mut other = ComposeArea {
+                editor: Rc::clone(&ca.editor),
+            };
+            let errors = Rc::new(RefCell::new(vec![]));
+            let errors_clone = Rc::clone(&errors);
+            let closure = Closure::wrap(Box::new(move |_argument: JsValue| {
+                if let Err(error) = call(&mut other) {
+                    errors_clone.borrow_mut().push(kind(&error));
                 }
-                .test(&mut ca);
-            }
+                JsValue::UNDEFINED
+            }) as Box<dyn FnMut(JsValue) -> JsValue>);
+            let function = closure.as_ref().unchecked_ref::<js_sys::Function>().clone();
+            closure.forget();
+            (errors, function)
+        }
 
-            #[wasm_bindgen_test]
-            fn between_nodes_br() {
-                let mut ca = init();
-                let img = Img {
-                    src: "img.jpg",
-                    alt: "😀",
-                    cls: "em",
-                };
-                InsertNodeTest {
-                    children: vec![
-                        text_node(&ca, "a"),
-                        element_node(&ca, "br"),
-                        text_node(&ca, "b"),
-                    ],
-                    selection_start: PositionByIndex::after(0),
-                    selection_end: None,
-                    node: img,
-                    final_html: format!("a{}<br>b", img.html(0)),
-                }
-                .test(&mut ca);
-            }
+        #[wasm_bindgen_test]
+        fn reentrant_calls() {
+            let mut ca = init_compose_area(&ComposeAreaOptions::new());
 
-            #[wasm_bindgen_test]
-            fn between_nodes_div() {
-                let mut ca = init();
-                let img = Img {
-                    src: "img.jpg",
-                    alt: "😀",
-                    cls: "em",
-                };
-                let div_a = {
-                    let div = element_node(&ca, "div");
-                    div.append_child(&text_node(&ca, "a")).unwrap();
-                    div
-                };
-                let div_b = {
-                    let div = element_node(&ca, "div");
-                    div.append_child(&text_node(&ca, "b")).unwrap();
-                    div.append_child(&element_node(&ca, "br")).unwrap();
-                    div
-                };
-                InsertNodeTest {
-                    children: vec![div_a, div_b],
-                    selection_start: PositionByIndex::after_nested(vec![0, 0]),
-                    selection_end: None,
-                    node: img,
-                    final_html: format!("<div>a{}</div><div>b<br></div>", img.html(0)),
-                }
-                .test(&mut ca);
-            }
+            // An emoji resolver calls a getter while the area is modified
+            let (errors, resolver) = reentrant_function(&ca, |ca| ca.get_text(None).map(drop));
+            ca.set_emoji_resolver(Some(resolver)).unwrap();
+            ca.insert_text("a😀").unwrap();
+            assert_eq!(*errors.borrow(), vec![JsValue::from("Busy")]);
+            assert_eq!(ca.get_text(None).unwrap(), "a😀");
+            ca.set_emoji_resolver(None).unwrap();
+
+            // An element handler modifies the area while it is read
+            let (errors, handler) = reentrant_function(&ca, |ca| ca.insert_text("b"));
+            ca.set_tag_handler("p", Some(handler)).unwrap();
+            ca.editor
+                .borrow()
+                .wrapper
+                .append_child(&ca.editor.borrow().document.create_element("p").unwrap())
+                .unwrap();
+            assert_eq!(ca.get_text(None).unwrap(), "a😀");
+            assert_eq!(*errors.borrow(), vec![JsValue::from("Busy")]);
         }
     }
 
-    mod selection_range {
+    mod options {
         use super::*;
 
         #[wasm_bindgen_test]
-        fn restore_selection_range() {
-            let mut ca = init();
-            let node = text_node(&ca, "abc");
-            ca.wrapper.append_child(&node).unwrap();
+        fn wrapper_classes() {
+            let ca = init_with_options(&ComposeAreaOptions::new().wrapper_classes("editor  big"));
+            assert_eq!(ca.wrapper.class_name(), "editor big");
+            assert_eq!(init().wrapper.class_name(), "cawrapper initialized");
+        }
 
-            // Highlight "b"
-            set_selection_range(
-                &Position::Offset(&node, 1),
-                Some(&Position::Offset(&node, 2)),
-            );
-            let range_result = ca.fetch_range();
-            assert!(!range_result.outside);
-            let range = range_result.range.expect("Could not get range");
-            assert_eq!(range.start_offset().unwrap(), 1);
-            assert_eq!(range.end_offset().unwrap(), 2);
+        #[wasm_bindgen_test]
+        fn image_id_prefix() {
+            let mut ca = init_with_options(&ComposeAreaOptions::new().image_id_prefix("emoji-"));
+            let img1 = ca.insert_image("a.png", "a", "em").unwrap().unwrap();
+            let img2 = ca.insert_image("b.png", "b", "em").unwrap().unwrap();
+            assert_eq!(img1.id(), "emoji-0");
+            assert_eq!(img2.id(), "emoji-1");
 
-            // Store range
-            ca.store_selection_range();
+            let mut ca = init_with_options(&ComposeAreaOptions::new().strip_image_ids(true));
+            let img = ca.insert_image("a.png", "a", "em").unwrap().unwrap();
+            assert!(!img.has_attribute("id"));
 
-            // Change range, highlight "a"
-            set_selection_range(
-                &Position::Offset(&node, 0),
-                Some(&Position::Offset(&node, 1)),
+            // The prefix is kept when stripping is disabled
+            let options = ComposeAreaOptions::new()
+                .image_id_prefix("x-")
+                .strip_image_ids(false);
+            let mut ca = init_with_options(&options);
+            let img = ca.insert_image("a.png", "a", "em").unwrap().unwrap();
+            assert_eq!(img.id(), "x-0");
+            ca.set_strip_image_ids(true);
+            let img = ca.insert_image("b.png", "b", "em").unwrap().unwrap();
+            assert!(!img.has_attribute("id"));
+            ca.set_strip_image_ids(false);
+            let img = ca.insert_image("c.png", "c", "em").unwrap().unwrap();
+            assert_eq!(img.id(), "x-1");
+        }
+
+        #[wasm_bindgen_test]
+        fn text_options() {
+            let mut ca = init_with_options(
+                &ComposeAreaOptions::new()
+                    .trim_text(false)
+                    .word_boundaries(" ,")
+                    .max_length(Some(10)),
             );
-            let range_result = ca.fetch_range();
-            assert!(!range_result.outside);
-            let range = range_result.range.expect("Could not get range");
-            assert_eq!(range.start_offset().unwrap(), 0);
-            assert_eq!(range.end_offset().unwrap(), 1);
+            ca.set_text(" foo,bar\tbaz qux", None, &JsValue::from(6))
+                .unwrap();
+            assert_eq!(ca.get_text(None), " foo,bar\tb");
+            assert_eq!(ca.get_text(Some(false)), "foo,bar\tb");
+            let wac = ca.get_word_at_caret().unwrap();
+            assert_eq!(wac.before(), "b");
+            assert_eq!(wac.after(), "ar\tb");
 
-            // Retore range
-            ca.restore_selection_range();
-            let range_result = ca.fetch_range();
-            assert!(!range_result.outside);
-            let range = range_result.range.expect("Could not get range");
-            assert_eq!(range.start_offset().unwrap(), 1);
-            assert_eq!(range.end_offset().unwrap(), 2);
+            ca.insert_text("abc").unwrap();
+            assert_eq!(ca.get_text(None), " foo,bar\tb");
         }
 
         #[wasm_bindgen_test]
-        fn get_range_result() {
-            let ca = init();
-            let inner_text_node = text_node(&ca, "abc");
-            ca.wrapper.append_child(&inner_text_node).unwrap();
+        fn undo_history() {
+            let mut ca = init_with_options(&ComposeAreaOptions::new().undo_history(false));
+            ca.insert_text("abc").unwrap();
+            assert!(!ca.can_undo());
+            assert!(!ca.undo().unwrap());
 
-            // No range set
+            // Changes made by the browser
+            ca.wrapper.append_child(&text_node(&ca, "d")).unwrap();
+            ca.store_selection_range().unwrap();
+            assert_eq!(ca.get_text(None), "abcd");
+            assert!(!ca.can_undo());
+        }
+    }
+
+    mod listeners {
+        use super::*;
+
+        fn dispatch(ca: &Editor, event: &str) {
+            let event = web_sys::Event::new(event).unwrap();
+            ca.wrapper.dispatch_event(&event).unwrap();
+        }
+
+        fn select(ca: &Editor, offset: u32) {
+            let text = ca.wrapper.first_child().unwrap();
+            set_selection_range(&Position::Offset(&text, offset), None).unwrap();
+        }
+
+        #[wasm_bindgen_test]
+        fn track_selection() {
+            let mut ca = init();
+            ca.insert_text("hello").unwrap();
+            select(&ca, 2);
+            dispatch(&ca, "keyup");
+            assert_eq!(
+                ca.get_selection_offsets(),
+                Some(SelectionOffsets { start: 2, end: 2 })
+            );
+
+            // Selections outside the wrapper are ignored
             selection::unset_selection_range();
-            let range_result = ca.fetch_range();
-            assert!(range_result.range.is_none());
-            assert!(!range_result.outside);
+            dispatch(&ca, "mouseup");
+            assert_eq!(
+                ca.get_selection_offsets(),
+                Some(SelectionOffsets { start: 2, end: 2 })
+            );
+        }
 
-            // Range is outside
-            let outer_text_node = ca.document.create_text_node("hello");
-            ca.document
-                .body()
+        #[wasm_bindgen_test]
+        fn disabled() {
+            let mut ca = init_with_options(&ComposeAreaOptions::new().track_selection(false));
+            ca.insert_text("hello").unwrap();
+            select(&ca, 2);
+            dispatch(&ca, "keyup");
+            assert_eq!(
+                ca.get_selection_offsets(),
+                Some(SelectionOffsets { start: 5, end: 5 })
+            );
+        }
+
+        #[wasm_bindgen_test]
+        fn destroy() {
+            let mut ca = init();
+            ca.insert_text("hello").unwrap();
+            ca.destroy().unwrap();
+            assert_eq!(ca.wrapper.class_name(), "");
+            assert!(!ca.wrapper.has_attribute("contenteditable"));
+            assert_eq!(ca.get_selection_offsets(), None);
+
+            select(&ca, 2);
+            dispatch(&ca, "keyup");
+            assert_eq!(ca.get_selection_offsets(), None);
+
+            // The wrapper can be bound again
+            let ca = Editor::new(ca.wrapper.clone(), &ComposeAreaOptions::new()).unwrap();
+            assert_eq!(ca.get_text(None), "hello");
+            assert_eq!(ca.wrapper.class_name(), "cawrapper initialized");
+        }
+
+        /// Dispatch a cancelable event. Return whether it was cancelled.
+        fn dispatch_cancelable(ca: &ComposeArea, event: &web_sys::Event) -> bool {
+            let wrapper = ca.editor.borrow().wrapper.clone();
+            !wrapper.dispatch_event(event).unwrap()
+        }
+
+        fn beforeinput(data: &str) -> web_sys::Event {
+            let mut init = web_sys::InputEventInit::new();
+            init.cancelable(true);
+            js_sys::Reflect::set(&init, &"inputType".into(), &"insertText".into()).unwrap();
+            js_sys::Reflect::set(&init, &"data".into(), &data.into()).unwrap();
+            InputEvent::new_with_event_init_dict("beforeinput", &init)
                 .unwrap()
-                .append_child(&outer_text_node)
-                .unwrap();
-            set_selection_range(&Position::Offset(&outer_text_node, 0), None);
-            let range_result = ca.fetch_range();
-            assert!(range_result.range.is_some());
-            assert!(range_result.outside);
+                .unchecked_into()
+        }
 
-            // Inside wrapper
-            set_selection_range(&Position::Offset(&inner_text_node, 0), None);
-            let range_result = ca.fetch_range();
-            assert!(range_result.range.is_some());
-            assert!(!range_result.outside);
+        fn paste(text: &str) -> web_sys::Event {
+            let mut init = web_sys::EventInit::new();
+            init.cancelable(true);
+            let event = web_sys::Event::new_with_event_init_dict("paste", &init).unwrap();
+            let data_transfer = DataTransfer::new().unwrap();
+            data_transfer.set_data("text/plain", text).unwrap();
+            js_sys::Reflect::set(&event, &"clipboardData".into(), &data_transfer).unwrap();
+            event
         }
-    }
 
-    mod clear {
-        use super::*;
+        fn keydown(key: &str) -> web_sys::Event {
+            let mut init = web_sys::KeyboardEventInit::new();
+            init.key(key).ctrl_key(true).cancelable(true);
+            KeyboardEvent::new_with_keyboard_event_init_dict("keydown", &init)
+                .unwrap()
+                .unchecked_into()
+        }
 
         #[wasm_bindgen_test]
-        fn clear_contents() {
-            // Init, no child nodes
-            let mut ca = init();
-            assert_eq!(ca.wrapper.child_nodes().length(), 0);
+        fn input_handlers() {
+            let options = ComposeAreaOptions::new()
+                .handle_beforeinput(true)
+                .handle_paste(true)
+                .handle_clipboard(true)
+                .formatting_shortcuts(true);
+            let mut ca = init_compose_area(&options);
+            assert!(dispatch_cancelable(&ca, &beforeinput("a")));
+            assert!(dispatch_cancelable(&ca, &paste("b")));
+            assert_eq!(ca.get_text(None).unwrap(), "ab");
 
-            // Append some child nodes
-            ca.wrapper.append_child(&text_node(&ca, "abc")).unwrap();
-            ca.wrapper.append_child(&element_node(&ca, "br")).unwrap();
-            assert_eq!(ca.wrapper.child_nodes().length(), 2);
+            ca.set_selection_offsets(0, Some(2)).unwrap();
+            assert!(dispatch_cancelable(&ca, &keydown("b")));
+            assert_eq!(ca.get_text(None).unwrap(), "*ab*");
 
-            // Clear
-            ca.clear();
-            assert_eq!(ca.wrapper.child_nodes().length(), 0);
+            // The listeners are removed by `destroy`
+            ca.destroy().unwrap();
+            assert!(!dispatch_cancelable(&ca, &beforeinput("c")));
+            assert!(!dispatch_cancelable(&ca, &keydown("i")));
+            assert_eq!(ca.get_text(None).unwrap(), "*ab*");
+        }
+
+        #[wasm_bindgen_test]
+        fn input_handlers_disabled() {
+            let ca = init_compose_area(&ComposeAreaOptions::new());
+            assert!(!dispatch_cancelable(&ca, &beforeinput("a")));
+            assert!(!dispatch_cancelable(&ca, &paste("b")));
+            assert_eq!(ca.get_text(None).unwrap(), "");
         }
     }
 
-    mod word_at_caret {
+    mod notifications {
         use super::*;
 
         #[wasm_bindgen_test]
-        fn empty() {
+        fn change() {
             let mut ca = init();
-            let wac = ca.get_word_at_caret();
-            assert!(wac.is_none());
+            let (changes, on_change) = recorder::<ContentSummary>();
+            let (empty_states, on_empty_state_change) = recorder::<bool>();
+            ca.on_change(Some(on_change));
+            ca.on_empty_state_change(Some(on_empty_state_change));
+
+            // Changes are debounced
+            ca.insert_text("ab").unwrap();
+            ca.insert_image("a.png", "😀", "em").unwrap();
+            ca.notifier.flush();
+            assert_eq!(changes.borrow().len(), 1);
+            assert_eq!(changes.borrow()[0].length(), 4);
+            assert_eq!(changes.borrow()[0].image_count(), 1);
+            assert_eq!(*empty_states.borrow(), vec![false]);
+
+            ca.clear().unwrap();
+            ca.notifier.flush();
+            assert_eq!(changes.borrow().len(), 2);
+            assert!(changes.borrow()[1].is_empty());
+            assert_eq!(*empty_states.borrow(), vec![false, true]);
+
+            // Undo
+            ca.undo().unwrap();
+            ca.notifier.flush();
+            assert_eq!(changes.borrow().len(), 3);
+            assert_eq!(*empty_states.borrow(), vec![false, true, false]);
+
+            // No further changes
+            ca.notifier.flush();
+            assert_eq!(changes.borrow().len(), 3);
         }
 
         #[wasm_bindgen_test]
-        fn in_text() {
+        fn selection_change() {
             let mut ca = init();
+            let (selections, on_selection_change) = recorder::<JsValue>();
+            ca.on_selection_change(Some(on_selection_change));
+            ca.insert_text("abc").unwrap();
+            ca.set_selection_offsets(1, Some(2)).unwrap();
+            ca.notifier.flush();
+            assert_eq!(selections.borrow().len(), 1);
 
-            let text = ca.document.create_text_node("hello world!\tgoodbye.");
-            ca.wrapper.append_child(&text).unwrap();
-            set_selection_range(&Position::Offset(&text, 9), None);
-            ca.store_selection_range();
+            ca.set_selection_offsets(2, Some(1)).unwrap();
+            ca.set_selection_offsets(1, Some(2)).unwrap();
+            ca.notifier.flush();
+            assert_eq!(selections.borrow().len(), 1);
 
-            let wac = ca
-                .get_word_at_caret()
-                .expect("get_word_at_caret returned None");
-            assert_eq!(&wac.before(), "wor");
-            assert_eq!(&wac.after(), "ld!");
-            assert_eq!(wac.start_offset(), 6);
-            assert_eq!(wac.end_offset(), 12);
+            ca.clear().unwrap();
+            ca.notifier.flush();
+            assert_eq!(selections.borrow().len(), 2);
+            assert!(selections.borrow()[1].is_undefined());
         }
 
         #[wasm_bindgen_test]
-        fn after_text() {
+        fn focus() {
+            let ca = init();
+            let (focus_calls, on_focus) = recorder::<JsValue>();
+            let (blur_calls, on_blur) = recorder::<JsValue>();
+            ca.on_focus(Some(on_focus));
+            ca.on_blur(Some(on_blur));
+            ca.wrapper
+                .dispatch_event(&web_sys::Event::new("focus").unwrap())
+                .unwrap();
+            // Callbacks are called asynchronously
+            assert_eq!(focus_calls.borrow().len(), 0);
+            ca.notifier.flush();
+            assert_eq!(focus_calls.borrow().len(), 1);
+            assert_eq!(blur_calls.borrow().len(), 0);
+            ca.wrapper
+                .dispatch_event(&web_sys::Event::new("blur").unwrap())
+                .unwrap();
+            ca.notifier.flush();
+            assert_eq!(blur_calls.borrow().len(), 1);
+        }
+
+        #[wasm_bindgen_test]
+        fn destroy() {
             let mut ca = init();
+            let (changes, on_change) = recorder::<ContentSummary>();
+            let (focus_calls, on_focus) = recorder::<JsValue>();
+            ca.on_change(Some(on_change));
+            ca.on_focus(Some(on_focus));
+            ca.insert_text("ab").unwrap();
+            ca.wrapper
+                .dispatch_event(&web_sys::Event::new("focus").unwrap())
+                .unwrap();
+            ca.destroy().unwrap();
+            ca.notifier.flush();
+            assert!(changes.borrow().is_empty());
+            assert!(focus_calls.borrow().is_empty());
+        }
+    }
 
-            let text = ca.document.create_text_node("hello world");
-            ca.wrapper.append_child(&text).unwrap();
-            set_selection_range(&Position::After(&text), None);
-            ca.store_selection_range();
+    mod length {
+        use super::*;
 
-            let wac = ca
-                .get_word_at_caret()
-                .expect("get_word_at_caret returned None");
-            assert_eq!(&wac.before(), "world");
-            assert_eq!(&wac.after(), "");
-            assert_eq!(wac.start_offset(), 6);
-            assert_eq!(wac.end_offset(), 11);
+        #[wasm_bindgen_test]
+        fn get_length() {
+            let mut ca = init();
+            ca.insert_text("ae\u{301}").unwrap();
+            ca.insert_image("a.png", "👍🏽", "em").unwrap();
+            let length = ca.get_length();
+            assert_eq!(length.utf8_bytes(), 12);
+            assert_eq!(length.utf16(), 7);
+            assert_eq!(length.scalars(), 5);
+            assert_eq!(length.graphemes(), 3);
         }
 
         #[wasm_bindgen_test]
-        fn before_word() {
-            let mut ca = init();
+        fn truncate() {
+            let options = ComposeAreaOptions::new()
+                .max_length(Some(6))
+                .max_length_unit("utf8");
+            let mut ca = init_with_options(&options);
+            let (calls, on_over_limit) = recorder::<LimitExceeded>();
+            ca.on_over_limit(Some(on_over_limit));
 
-            let text = ca.document.create_text_node("hello world");
-            ca.wrapper.append_child(&text).unwrap();
-            set_selection_range(&Position::Offset(&text, 0), None);
-            ca.store_selection_range();
+            ca.insert_text("abcäö").unwrap();
+            assert_eq!(ca.get_text(None), "abcä");
+            assert!(calls.borrow().is_empty());
+            ca.notifier.flush();
+            assert_eq!(calls.borrow().len(), 1);
+            assert_eq!(calls.borrow()[0].length(), 7);
+            assert_eq!(calls.borrow()[0].max_length(), 6);
+            assert!(!calls.borrow()[0].rejected());
 
-            let wac = ca
-                .get_word_at_caret()
-                .expect("get_word_at_caret returned None");
-            assert_eq!(&wac.before(), "");
-            assert_eq!(&wac.after(), "hello");
-            assert_eq!(wac.start_offset(), 0);
-            assert_eq!(wac.end_offset(), 5);
+            // Images are never truncated
+            assert!(ca.insert_image("a.png", "😀", "em").unwrap().is_none());
+            assert_eq!(ca.get_text(None), "abcä");
+            ca.notifier.flush();
+            assert_eq!(calls.borrow().len(), 2);
+
+            // Pasting
+            assert!(ca.paste("", "xyz").unwrap());
+            assert_eq!(ca.get_text(None), "abcäx");
         }
 
         #[wasm_bindgen_test]
-        fn single_word() {
-            let mut ca = init();
+        fn reject() {
+            let options = ComposeAreaOptions::new()
+                .max_length(Some(3))
+                .max_length_unit("graphemes")
+                .reject_over_limit(true);
+            let mut ca = init_with_options(&options);
+            let (calls, on_over_limit) = recorder::<LimitExceeded>();
+            ca.on_over_limit(Some(on_over_limit));
 
-            let text = ca.document.create_text_node(":ok:");
-            ca.wrapper.append_child(&text).unwrap();
-            set_selection_range(&Position::Offset(&text, 4), None);
-            ca.store_selection_range();
+            ca.insert_text("a👍🏽").unwrap();
+            ca.insert_text("bc").unwrap();
+            assert_eq!(ca.get_text(None), "a👍🏽");
+            assert!(!ca.paste("", "bc").unwrap());
+            assert!(ca.insert_image("a.png", "😀", "em").unwrap().is_some());
+            assert_eq!(ca.get_text(None), "a👍🏽😀");
+            ca.notifier.flush();
+            assert_eq!(calls.borrow().len(), 2);
+            assert_eq!(calls.borrow()[0].length(), 4);
+            assert!(calls.borrow()[0].rejected());
 
-            let wac = ca
-                .get_word_at_caret()
-                .expect("get_word_at_caret returned None");
-            assert_eq!(&wac.before(), ":ok:");
-            assert_eq!(&wac.after(), "");
-            assert_eq!(wac.start_offset(), 0);
-            assert_eq!(wac.end_offset(), 4);
+            // Replacing the selection
+            ca.set_selection_offsets(0, Some(1)).unwrap();
+            ca.insert_text("x").unwrap();
+            assert_eq!(ca.get_text(None), "x👍🏽😀");
+        }
+
+        #[wasm_bindgen_test]
+        fn graphemes() {
+            let options = ComposeAreaOptions::new()
+                .max_length(Some(2))
+                .max_length_unit("graphemes");
+            let mut ca = init_with_options(&options);
+            ca.insert_text("ae").unwrap();
+
+            // The combining mark joins the preceding grapheme cluster
+            ca.insert_text("\u{301}b").unwrap();
+            assert_eq!(ca.get_text(None), "ae\u{301}");
+        }
+
+        #[wasm_bindgen_test]
+        fn set_text_and_html() {
+            let options = ComposeAreaOptions::new().max_length(Some(3));
+            let mut ca = init_with_options(&options);
+            let (calls, on_over_limit) = recorder::<LimitExceeded>();
+            ca.on_over_limit(Some(on_over_limit));
+
+            // The previous contents are replaced
+            ca.insert_text("abc").unwrap();
+            ca.set_text("wxyz", None, &JsValue::UNDEFINED).unwrap();
+            assert_eq!(ca.get_text(None), "wxy");
+            ca.notifier.flush();
+            assert_eq!(calls.borrow().len(), 1);
+            assert_eq!(calls.borrow()[0].length(), 4);
+
+            ca.clear().unwrap();
+            ca.insert_html("<b>a</b>b").unwrap();
+            assert_eq!(ca.wrapper.inner_html(), "<b>a</b>b");
+            ca.insert_html("<b>cd</b>").unwrap();
+            assert_eq!(ca.get_text(None), "abc");
+            ca.notifier.flush();
+            assert_eq!(calls.borrow().len(), 2);
         }
     }
 }
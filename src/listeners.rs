@@ -0,0 +1,177 @@
+//! Event listeners owned by a compose area.
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{Element, Event, EventTarget, Range, Window};
+
+use crate::error::{ComposeAreaError, DomResultExt};
+use crate::notify::Notifier;
+use crate::selection::find_range;
+use crate::Editor;
+
+/// The events on the wrapper element after which the selection is stored.
+const WRAPPER_EVENTS: &[&str] = &["keyup", "mouseup", "paste"];
+
+/// An event listener. It is removed from its target when dropped.
+struct Listener {
+    target: EventTarget,
+    event: &'static str,
+    closure: Closure<dyn FnMut(Event)>,
+}
+
+impl Listener {
+    fn add(
+        target: &EventTarget,
+        event: &'static str,
+        closure: Closure<dyn FnMut(Event)>,
+    ) -> Result<Self, ComposeAreaError> {
+        target
+            .add_event_listener_with_callback(event, closure.as_ref().unchecked_ref())
+            .dom_err("add event listener")?;
+        Ok(Self {
+            target: target.clone(),
+            event,
+            closure,
+        })
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        trace!("[compose_area] Removing {} listener", self.event);
+        let removed = self
+            .target
+            .remove_event_listener_with_callback(self.event, self.closure.as_ref().unchecked_ref());
+        if removed.is_err() {
+            warn!("[compose_area] Could not remove {} listener", self.event);
+        }
+    }
+}
+
+/// The event listeners of a compose area. All listeners are removed when
+/// this is dropped.
+#[derive(Default)]
+pub(crate) struct Listeners(Vec<Listener>);
+
+impl Listeners {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach listeners that store the selection range in `selection_range`
+    /// whenever the selection changes. Selections outside the wrapper are
+    /// ignored.
+    pub(crate) fn track_selection(
+        &mut self,
+        window: &Window,
+        wrapper: &Element,
+        selection_range: &Rc<RefCell<Option<Range>>>,
+        notifier: &Notifier,
+    ) -> Result<(), ComposeAreaError> {
+        let document = window.document().ok_or(ComposeAreaError::NoDocument)?;
+        let closure = || store_range_closure(window, wrapper, selection_range, notifier);
+        for event in WRAPPER_EVENTS {
+            self.0.push(Listener::add(wrapper, event, closure())?);
+        }
+        // Note: Unfortunately the selectionchange listener can only be set on
+        // document level, not on the wrapper itself.
+        self.0
+            .push(Listener::add(&document, "selectionchange", closure())?);
+        Ok(())
+    }
+
+    /// Attach listeners that report input (that was not handled by the
+    /// compose area itself) and focus changes to the notifier.
+    pub(crate) fn notify(
+        &mut self,
+        wrapper: &Element,
+        notifier: &Notifier,
+    ) -> Result<(), ComposeAreaError> {
+        let input_notifier = notifier.clone();
+        self.0.push(Listener::add(
+            wrapper,
+            "input",
+            Closure::wrap(
+                Box::new(move |_event: Event| input_notifier.content_changed())
+                    as Box<dyn FnMut(Event)>,
+            ),
+        )?);
+        for (event, focused) in &[("focus", true), ("blur", false)] {
+            let notifier = notifier.clone();
+            let focused = *focused;
+            self.0.push(Listener::add(
+                wrapper,
+                event,
+                Closure::wrap(
+                    Box::new(move |_event: Event| notifier.focus_changed(focused))
+                        as Box<dyn FnMut(Event)>,
+                ),
+            )?);
+        }
+        Ok(())
+    }
+
+    /// Attach a listener that passes events of type `E` to a method of the
+    /// editor (e.g. `Editor::handle_paste`).
+    ///
+    /// Events are ignored if the editor was dropped, or if it is in use
+    /// because the event was dispatched by one of its own methods.
+    pub(crate) fn handle<E: JsCast + 'static>(
+        &mut self,
+        wrapper: &Element,
+        event: &'static str,
+        editor: &Weak<RefCell<Editor>>,
+        handler: fn(&mut Editor, &E) -> Result<bool, JsValue>,
+    ) -> Result<(), ComposeAreaError> {
+        let editor = Weak::clone(editor);
+        let closure = Closure::wrap(Box::new(move |js_event: Event| {
+            let editor = match editor.upgrade() {
+                Some(editor) => editor,
+                None => return,
+            };
+            let mut editor = match editor.try_borrow_mut() {
+                Ok(editor) => editor,
+                Err(_) => return,
+            };
+            if let Some(js_event) = js_event.dyn_ref::<E>() {
+                if handler(&mut editor, js_event).is_err() {
+                    warn!("[compose_area] Could not handle {} event", event);
+                }
+            }
+        }) as Box<dyn FnMut(Event)>);
+        self.0.push(Listener::add(wrapper, event, closure)?);
+        Ok(())
+    }
+}
+
+/// Return a closure that stores the current selection range, if it is within
+/// the wrapper element.
+fn store_range_closure(
+    window: &Window,
+    wrapper: &Element,
+    selection_range: &Rc<RefCell<Option<Range>>>,
+    notifier: &Notifier,
+) -> Closure<dyn FnMut(Event)> {
+    let window = window.clone();
+    let wrapper = wrapper.clone();
+    let selection_range = Rc::clone(selection_range);
+    let notifier = notifier.clone();
+    Closure::wrap(Box::new(move |_event: Event| {
+        let range = match window.get_selection() {
+            Ok(Some(selection)) => find_range(&selection, &wrapper),
+            Ok(None) | Err(_) => return,
+        };
+        match range {
+            Ok(Some((range, true))) => {
+                trace!("[compose_area] Selection changed");
+                // Note: The range must be cloned, so that changes to the range
+                // in the DOM are not reflected in the stored reference.
+                selection_range.replace(Some(range.clone_range()));
+                notifier.selection_changed();
+            }
+            Ok(_) => {}
+            Err(e) => warn!("[compose_area] Could not store selection range: {}", e),
+        }
+    }) as Box<dyn FnMut(Event)>)
+}
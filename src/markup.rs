@@ -0,0 +1,380 @@
+//! Text markup (`*bold*`, `_italic_`, `~strikethrough~` and `` `monospace` ``):
+//! Detection for highlighting, and toggling around the selection.
+//!
+//! Markup never spans multiple lines. Images, mentions and tokens are not
+//! part of the parsed text, but markup may enclose them.
+use std::ops::Range;
+
+use crate::model::{utf16_len, Document, Segment};
+
+/// The character that replaces atomic segments in the parsed text. It is
+/// neither whitespace nor alphanumeric.
+const OBJECT_REPLACEMENT: char = '\u{fffc}';
+
+/// A set of markup styles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Styles(u8);
+
+impl Styles {
+    pub(crate) const BOLD: Styles = Styles(1);
+    pub(crate) const ITALIC: Styles = Styles(1 << 1);
+    pub(crate) const STRIKETHROUGH: Styles = Styles(1 << 2);
+    pub(crate) const MONOSPACE: Styles = Styles(1 << 3);
+    /// The text is a markup character (e.g. the `*` around bold text).
+    pub(crate) const MARKER: Styles = Styles(1 << 4);
+
+    /// Return whether all styles in `other` are set.
+    pub(crate) fn contains(self, other: Styles) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Return whether no style is set.
+    pub(crate) fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    fn insert(&mut self, other: Styles) {
+        self.0 |= other.0;
+    }
+}
+
+/// A part of a text segment with uniform styles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Run {
+    /// The byte range within the text segment.
+    pub(crate) range: Range<usize>,
+    pub(crate) styles: Styles,
+}
+
+/// Return the style of a markup character.
+fn marker_style(c: char) -> Option<Styles> {
+    match c {
+        '*' => Some(Styles::BOLD),
+        '_' => Some(Styles::ITALIC),
+        '~' => Some(Styles::STRIKETHROUGH),
+        '`' => Some(Styles::MONOSPACE),
+        _ => None,
+    }
+}
+
+/// Return the byte ranges (including the markup characters) and styles of
+/// all markup spans in a single line of text.
+///
+/// A span starts with a markup character that is not preceded by a letter or
+/// digit and not followed by whitespace. It ends with the next matching
+/// markup character that is not preceded by whitespace and not followed by
+/// a letter or digit. Spans of different styles may be nested, but not
+/// cross each other: Of two crossing spans, the one that starts later is
+/// dropped.
+pub(crate) fn find_markup(line: &str) -> Vec<(Range<usize>, Styles)> {
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let at = |i: Option<usize>| i.and_then(|i| chars.get(i)).map(|(_, c)| *c);
+    let can_open = |i: usize| {
+        !at(i.checked_sub(1)).map_or(false, char::is_alphanumeric)
+            && at(Some(i + 1)).map_or(false, |c| !c.is_whitespace())
+    };
+    let can_close = |i: usize| {
+        !at(i.checked_sub(1)).map_or(true, char::is_whitespace)
+            && !at(Some(i + 1)).map_or(false, char::is_alphanumeric)
+    };
+
+    let mut spans = vec![];
+    for marker in &['*', '_', '~', '`'] {
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i].1 != *marker || !can_open(i) {
+                i += 1;
+                continue;
+            }
+            // The span must not be empty
+            let end = (i + 2..chars.len()).find(|j| chars[*j].1 == *marker && can_close(*j));
+            match end {
+                Some(end) => {
+                    let style = marker_style(*marker).unwrap_or_default();
+                    // Markup characters are a single byte long
+                    let (start, end_byte) = (chars[i].0, chars[end].0 + 1);
+                    spans.push((start..end_byte, style));
+                    i = end + 1;
+                }
+                None => i += 1,
+            }
+        }
+    }
+    spans.sort_by_key(|(range, _)| range.start);
+
+    let mut result: Vec<(Range<usize>, Styles)> = vec![];
+    for (range, style) in spans {
+        let crosses = result.iter().any(|(other, _)| {
+            other.start < range.start && range.start < other.end && other.end < range.end
+        });
+        if !crosses {
+            result.push((range, style));
+        }
+    }
+    result
+}
+
+/// Split the text segments of a document into runs with uniform markup
+/// styles. The result contains one list of runs per segment (empty for
+/// segments that are not text).
+pub(crate) fn highlight(document: &Document) -> Vec<Vec<Run>> {
+    let mut result = vec![];
+    let mut line = String::new();
+    // The indices (into `result`) and byte offsets (into `line`) of the text
+    // segments of the current line
+    let mut texts: Vec<(usize, usize)> = vec![];
+    for (index, segment) in document.segments().iter().enumerate() {
+        result.push(vec![]);
+        match segment {
+            Segment::Text(text) => {
+                texts.push((index, line.len()));
+                line.push_str(text);
+            }
+            Segment::Newline => {
+                highlight_line(document, &line, &texts, &mut result);
+                line.clear();
+                texts.clear();
+            }
+            _ => line.push(OBJECT_REPLACEMENT),
+        }
+    }
+    highlight_line(document, &line, &texts, &mut result);
+    result
+}
+
+/// Compute the runs of the text segments of a single line.
+fn highlight_line(
+    document: &Document,
+    line: &str,
+    texts: &[(usize, usize)],
+    result: &mut [Vec<Run>],
+) {
+    let mut styles = vec![Styles::default(); line.len()];
+    for (range, style) in find_markup(line) {
+        for styles in &mut styles[range.clone()] {
+            styles.insert(style);
+        }
+        styles[range.start].insert(Styles::MARKER);
+        styles[range.end - 1].insert(Styles::MARKER);
+    }
+
+    for (index, offset) in texts {
+        let len = document.segments()[*index].as_text().len();
+        let runs = &mut result[*index];
+        for (i, style) in styles[*offset..offset + len].iter().enumerate() {
+            let end = i + 1;
+            match runs.last_mut() {
+                Some(run) if run.styles == *style => run.range.end = end,
+                _ => runs.push(Run {
+                    range: i..end,
+                    styles: *style,
+                }),
+            }
+        }
+    }
+}
+
+/// Wrap the selection in the markup character, or unwrap it if it is already
+/// wrapped (either directly around or at the edges of the selection).
+///
+/// Whitespace at the edges of the selection is not wrapped. A collapsed
+/// selection is wrapped into an empty pair of markup characters. Return the
+/// new selection around the same text, or `None` if the selection spans
+/// multiple lines or only contains whitespace.
+pub(crate) fn toggle_markup(
+    document: &mut Document,
+    selection: (u32, u32),
+    marker: char,
+) -> Option<(u32, u32)> {
+    let start = document.snap_offset(selection.0.min(selection.1), false);
+    let end = document.snap_offset(selection.0.max(selection.1), true);
+    let selected = document.slice(start, end);
+    if selected.segments().contains(&Segment::Newline) {
+        return None;
+    }
+    let is_marker = |document: &Document, offset: u32| {
+        offset < document.len()
+            && document.slice(offset, offset + 1).segments() == [Segment::Text(marker.to_string())]
+    };
+    let edges_are_text = {
+        let text = selected.to_text();
+        text.is_empty()
+            || !text.starts_with(char::is_whitespace) && !text.ends_with(char::is_whitespace)
+    };
+
+    // Unwrap
+    if start > 0 && is_marker(document, start - 1) && is_marker(document, end) && edges_are_text {
+        document.delete_range(end, end + 1);
+        document.delete_range(start - 1, start);
+        return Some((start - 1, end - 1));
+    }
+    if end - start >= 2 && is_marker(document, start) && is_marker(document, end - 1) {
+        document.delete_range(end - 1, end);
+        document.delete_range(start, start + 1);
+        return Some((start, end - 2));
+    }
+
+    // Wrap the selection without leading and trailing whitespace
+    let text = selected.to_text();
+    let trimmed = text.trim_start();
+    let start = start + utf16_len(&text[..text.len() - trimmed.len()]);
+    let end = end - utf16_len(&trimmed[trimmed.trim_end().len()..]);
+    if start > end || (start == end && !text.is_empty()) {
+        return None;
+    }
+    let marker = Document::from_text(&marker.to_string());
+    document.insert(end, &marker);
+    document.insert(start, &marker);
+    Some((start + 1, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::utf16_to_byte_index;
+
+    fn spans(line: &str) -> Vec<&str> {
+        find_markup(line)
+            .into_iter()
+            .map(|(range, _)| &line[range])
+            .collect()
+    }
+
+    #[test]
+    fn find() {
+        assert_eq!(spans("a *bold* b"), vec!["*bold*"]);
+        assert_eq!(spans("_it_ and ~st~!"), vec!["_it_", "~st~"]);
+        assert_eq!(spans("*a b* *c*"), vec!["*a b*", "*c*"]);
+        assert_eq!(spans("*_both_*"), vec!["*_both_*", "_both_"]);
+        assert_eq!(spans("(*x*)"), vec!["*x*"]);
+        assert_eq!(spans("*ä😀*"), vec!["*ä😀*"]);
+        assert_eq!(spans("`x = 1`"), vec!["`x = 1`"]);
+    }
+
+    #[test]
+    fn crossing() {
+        assert_eq!(spans("*a _b* c_"), vec!["*a _b*"]);
+        assert_eq!(spans("_a *b_ c* ~d~"), vec!["_a *b_", "~d~"]);
+        assert_eq!(spans("*a _b_ c*"), vec!["*a _b_ c*", "_b_"]);
+        assert_eq!(
+            highlight(&Document::from_text("*a _b* c_"))[0]
+                .iter()
+                .map(|run| run.styles)
+                .collect::<Vec<_>>(),
+            vec![
+                Styles(Styles::BOLD.0 | Styles::MARKER.0),
+                Styles::BOLD,
+                Styles(Styles::BOLD.0 | Styles::MARKER.0),
+                Styles::default(),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_markup() {
+        assert!(spans("snake_case_name").is_empty());
+        assert!(spans("2 * 3 * 4").is_empty());
+        assert!(spans("** __").is_empty());
+        assert!(spans("*a *b").is_empty());
+        assert!(spans("*a*b").is_empty());
+        assert!(spans("*").is_empty());
+    }
+
+    #[test]
+    fn highlight_document() {
+        let document = Document::from_segments(vec![
+            Segment::Text("a *b".into()),
+            Segment::image("e.png", "😀", "em"),
+            Segment::Text("c* _d".into()),
+            Segment::Newline,
+            Segment::Text("e_".into()),
+        ]);
+        let bold = Styles::BOLD;
+        let marker = Styles(Styles::BOLD.0 | Styles::MARKER.0);
+        let run = |range: Range<usize>, styles: Styles| Run { range, styles };
+        assert_eq!(
+            highlight(&document),
+            vec![
+                vec![
+                    run(0..2, Styles::default()),
+                    run(2..3, marker),
+                    run(3..4, bold)
+                ],
+                vec![],
+                vec![
+                    run(0..1, bold),
+                    run(1..2, marker),
+                    run(2..5, Styles::default())
+                ],
+                vec![],
+                vec![run(0..2, Styles::default())],
+            ]
+        );
+        assert!(marker.contains(Styles::MARKER));
+        assert!(!bold.contains(Styles::ITALIC));
+    }
+
+    /// Toggle the markup around the selection, marked by `[` and `]` (or by
+    /// `|` if it is collapsed) in the text.
+    fn toggle(text_with_selection: &str, marker: char) -> Option<String> {
+        let start = text_with_selection.find(|c| c == '[' || c == '|').unwrap();
+        let end = text_with_selection.find(|c| c == ']' || c == '|').unwrap();
+        let end = if start == end { start } else { end - 1 };
+        let text = text_with_selection.replace(|c| c == '[' || c == ']' || c == '|', "");
+        let mut document = Document::from_text(&text);
+        let (start, end) = toggle_markup(
+            &mut document,
+            (utf16_len(&text[..start]), utf16_len(&text[..end])),
+            marker,
+        )?;
+        let text = document.to_text();
+        let start = utf16_to_byte_index(&text, start);
+        let end = utf16_to_byte_index(&text, end);
+        Some(if start == end {
+            format!("{}|{}", &text[..start], &text[start..])
+        } else {
+            format!("{}[{}]{}", &text[..start], &text[start..end], &text[end..])
+        })
+    }
+
+    #[test]
+    fn toggle_wrap() {
+        assert_eq!(toggle("a [bc] d", '*').as_deref(), Some("a *[bc]* d"));
+        assert_eq!(toggle("a[ bc ]d", '_').as_deref(), Some("a _[bc]_ d"));
+        assert_eq!(toggle("a|", '~').as_deref(), Some("a~|~"));
+        assert_eq!(toggle("[ä😀]", '`').as_deref(), Some("`[ä😀]`"));
+        assert_eq!(toggle("a[  ]b", '*'), None);
+        assert_eq!(toggle("[a\nb]", '*'), None);
+    }
+
+    #[test]
+    fn toggle_unwrap() {
+        assert_eq!(toggle("a *[bc]* d", '*').as_deref(), Some("a [bc] d"));
+        assert_eq!(toggle("a [*bc*] d", '*').as_deref(), Some("a [bc] d"));
+        assert_eq!(toggle("a~|~", '~').as_deref(), Some("a|"));
+        assert_eq!(toggle("_[a]_", '*').as_deref(), Some("_*[a]*_"));
+
+        // Markers of different spans are not unwrapped
+        assert_eq!(toggle("*a*[ ]*b*", '*'), None);
+        assert_eq!(toggle("*a* [x] *b*", '*').as_deref(), Some("*a* *[x]* *b*"));
+    }
+
+    #[test]
+    fn toggle_images() {
+        let mut document = Document::from_segments(vec![
+            Segment::Text("*".into()),
+            Segment::image("e.png", "😀", "em"),
+            Segment::Text("*".into()),
+        ]);
+        assert_eq!(toggle_markup(&mut document, (1, 3), '*'), Some((0, 2)));
+        assert_eq!(
+            document.segments(),
+            &[Segment::image("e.png", "😀", "em")][..]
+        );
+
+        // Selections partially covering an image include it
+        assert_eq!(toggle_markup(&mut document, (1, 1), '_'), Some((1, 3)));
+        assert_eq!(document.to_text(), "_😀_");
+    }
+}
@@ -0,0 +1,189 @@
+//! Mention tokens, e.g. `@[ABCD1234]`.
+//!
+//! In the text representation of the compose area, a mention is represented
+//! by a token that contains the ID of the mentioned contact. The format of
+//! the token is configurable (see `ComposeAreaOptions.mention_format`).
+use std::ops::Range;
+
+use crate::model::{Document, Segment};
+
+/// The placeholder for the ID in a mention format pattern.
+const ID_PLACEHOLDER: &str = "{id}";
+
+/// The format of mention tokens: The ID, surrounded by a prefix and a suffix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MentionFormat {
+    prefix: String,
+    suffix: String,
+}
+
+impl Default for MentionFormat {
+    fn default() -> Self {
+        Self {
+            prefix: "@[".to_string(),
+            suffix: "]".to_string(),
+        }
+    }
+}
+
+impl MentionFormat {
+    /// Parse a pattern like `@[{id}]`. The pattern must contain the `{id}`
+    /// placeholder exactly once, and it must not be at the start.
+    pub(crate) fn from_pattern(pattern: &str) -> Option<Self> {
+        let index = pattern.find(ID_PLACEHOLDER)?;
+        let prefix = &pattern[..index];
+        let suffix = &pattern[index + ID_PLACEHOLDER.len()..];
+        if prefix.is_empty() || suffix.contains(ID_PLACEHOLDER) {
+            return None;
+        }
+        Some(Self {
+            prefix: prefix.to_string(),
+            suffix: suffix.to_string(),
+        })
+    }
+
+    /// Return the token for the specified ID.
+    pub(crate) fn token(&self, id: &str) -> String {
+        format!("{}{}{}", self.prefix, id, self.suffix)
+    }
+
+    /// Return the byte ranges and IDs of all mention tokens in the text.
+    ///
+    /// IDs consist of ASCII letters, digits, `_` and `-`.
+    pub(crate) fn find_tokens<'a>(&self, text: &'a str) -> Vec<(Range<usize>, &'a str)> {
+        let mut tokens = vec![];
+        let mut rest = 0;
+        while let Some(index) = text[rest..].find(&self.prefix) {
+            let start = rest + index;
+            let id_start = start + self.prefix.len();
+            let id_end = text[id_start..]
+                .find(|c: char| !is_id_char(c))
+                .map_or(text.len(), |index| id_start + index);
+            if id_end > id_start && text[id_end..].starts_with(&self.suffix) {
+                let end = id_end + self.suffix.len();
+                tokens.push((start..end, &text[id_start..id_end]));
+                rest = end;
+            } else {
+                rest = id_start;
+            }
+        }
+        tokens
+    }
+}
+
+/// Return whether the ID is valid, i.e. not empty and consists of ASCII
+/// letters, digits, `_` and `-` only. Only valid IDs can be parsed back from
+/// the text representation.
+pub(crate) fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(is_id_char)
+}
+
+fn is_id_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Replace all mention tokens in the text segments of the document with
+/// mention segments.
+///
+/// The `resolve` function is called with every ID and returns the display
+/// name of the mentioned contact. The text representation (and thus all
+/// logical offsets) of the document stays the same.
+pub(crate) fn replace_mentions<F>(
+    document: &Document,
+    format: &MentionFormat,
+    mut resolve: F,
+) -> Document
+where
+    F: FnMut(&str) -> String,
+{
+    let mut result = Document::new();
+    for segment in document.segments() {
+        let text = match segment {
+            Segment::Text(text) => text,
+            other => {
+                result.push(other.clone());
+                continue;
+            }
+        };
+        let mut last = 0;
+        for (range, id) in format.find_tokens(text) {
+            result.push(Segment::Text(text[last..range.start].to_string()));
+            result.push(Segment::mention(id, &resolve(id), &text[range.clone()]));
+            last = range.end;
+        }
+        result.push(Segment::Text(text[last..].to_string()));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids<'a>(format: &MentionFormat, text: &'a str) -> Vec<&'a str> {
+        format
+            .find_tokens(text)
+            .into_iter()
+            .map(|(_, id)| id)
+            .collect()
+    }
+
+    #[test]
+    fn find_tokens() {
+        let format = MentionFormat::default();
+        assert_eq!(format.token("ABCD1234"), "@[ABCD1234]");
+        assert_eq!(
+            format.find_tokens("hi @[ABCD1234]!"),
+            vec![(3..14, "ABCD1234")]
+        );
+        assert_eq!(ids(&format, "@[a]@[b-c_d]"), vec!["a", "b-c_d"]);
+        assert!(ids(&format, "@[] @[a b] @[a @a] [a]").is_empty());
+        assert_eq!(ids(&format, "@[@[a]"), vec!["a"]);
+        assert_eq!(ids(&format, "😀@[ä] @[x]"), vec!["x"]);
+    }
+
+    #[test]
+    fn valid_ids() {
+        assert!(is_valid_id("ABCD1234"));
+        assert!(is_valid_id("b-c_d"));
+        assert!(!is_valid_id(""));
+        assert!(!is_valid_id("a b"));
+        assert!(!is_valid_id("a]"));
+        assert!(!is_valid_id("ä"));
+    }
+
+    #[test]
+    fn patterns() {
+        let format = MentionFormat::from_pattern("<@{id}>").unwrap();
+        assert_eq!(format.token("x"), "<@x>");
+        assert_eq!(ids(&format, "<@a> <@b"), vec!["a"]);
+
+        let format = MentionFormat::from_pattern("@{id}").unwrap();
+        assert_eq!(ids(&format, "hi @alice, @bob"), vec!["alice", "bob"]);
+
+        assert_eq!(MentionFormat::from_pattern("{id}]"), None);
+        assert_eq!(MentionFormat::from_pattern("@[id]"), None);
+        assert_eq!(MentionFormat::from_pattern("@{id}{id}"), None);
+    }
+
+    #[test]
+    fn replace() {
+        let document = Document::from_segments(vec![
+            Segment::Text("hi @[a] and\n@[b]".into()),
+            Segment::image("e.png", "😀", "em"),
+        ]);
+        let replaced = replace_mentions(&document, &MentionFormat::default(), str::to_uppercase);
+        assert_eq!(
+            replaced.segments(),
+            &[
+                Segment::Text("hi ".into()),
+                Segment::mention("a", "A", "@[a]"),
+                Segment::Text(" and".into()),
+                Segment::Newline,
+                Segment::mention("b", "B", "@[b]"),
+                Segment::image("e.png", "😀", "em"),
+            ][..]
+        );
+        assert_eq!(replaced.to_text(), document.to_text());
+    }
+}
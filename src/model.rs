@@ -0,0 +1,511 @@
+//! A DOM independent model of the compose area contents.
+//!
+//! The compose area contents are represented as a flat list of segments. All
+//! offsets used in this module are logical offsets, measured in UTF-16 code
+//! units of the text representation (which is the same unit JS strings use).
+//! Non-text segments (e.g. images) are atomic: They can only be inserted or
+//! removed as a whole.
+
+/// A single piece of content in the compose area.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// A run of text. Never empty and never contains newlines.
+    Text(String),
+
+    /// An image (e.g. an emoji). It is represented by its alt text. The ID
+    /// attribute (e.g. of images inserted with `insert_image`) is kept when
+    /// the image is rendered again.
+    Image {
+        src: String,
+        alt: String,
+        cls: String,
+        id: Option<String>,
+    },
+
+    /// A mention of a contact, displayed with its name. It is represented
+    /// by a token that contains the ID (e.g. `@[ABCD1234]`).
+    Mention {
+        id: String,
+        name: String,
+        token: String,
+    },
+
+    /// An inline token of a custom kind (e.g. a hashtag or a file
+    /// placeholder). It is represented by its text and rendered with the
+    /// specified HTML attributes, which are kept sorted by name.
+    Token {
+        kind: String,
+        text: String,
+        attrs: Vec<(String, String)>,
+    },
+
+    /// A line break.
+    Newline,
+}
+
+impl Segment {
+    /// Create an image segment without an ID.
+    pub fn image(src: &str, alt: &str, cls: &str) -> Self {
+        Self::image_with_id(src, alt, cls, None)
+    }
+
+    /// Create an image segment with an optional ID attribute.
+    pub fn image_with_id(src: &str, alt: &str, cls: &str, id: Option<&str>) -> Self {
+        Segment::Image {
+            src: src.to_string(),
+            alt: alt.to_string(),
+            cls: cls.to_string(),
+            id: id.map(str::to_string),
+        }
+    }
+
+    /// Create a mention segment.
+    pub fn mention(id: &str, name: &str, token: &str) -> Self {
+        Segment::Mention {
+            id: id.to_string(),
+            name: name.to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    /// Create a token segment. The attributes are sorted by name.
+    pub fn token(kind: &str, text: &str, mut attrs: Vec<(String, String)>) -> Self {
+        attrs.sort();
+        Segment::Token {
+            kind: kind.to_string(),
+            text: text.to_string(),
+            attrs,
+        }
+    }
+
+    /// Return the text representation of this segment.
+    pub fn as_text(&self) -> &str {
+        match self {
+            Segment::Text(text) | Segment::Token { text, .. } => text,
+            Segment::Image { alt, .. } => alt,
+            Segment::Mention { token, .. } => token,
+            Segment::Newline => "\n",
+        }
+    }
+
+    /// Return the length of the text representation in UTF-16 code units.
+    pub fn len(&self) -> u32 {
+        utf16_len(self.as_text())
+    }
+
+    /// Return whether the text representation of this segment is empty.
+    pub fn is_empty(&self) -> bool {
+        self.as_text().is_empty()
+    }
+
+    /// Return whether this segment can only be handled as a whole.
+    pub fn is_atomic(&self) -> bool {
+        match self {
+            Segment::Text(_) => false,
+            Segment::Image { .. }
+            | Segment::Mention { .. }
+            | Segment::Token { .. }
+            | Segment::Newline => true,
+        }
+    }
+}
+
+/// The contents of a compose area.
+///
+/// The segment list is always kept normalized: There are no empty text
+/// segments and no two adjacent text segments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Document {
+    segments: Vec<Segment>,
+}
+
+impl Document {
+    /// Create an empty document.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a document from plain text. Newlines are converted to newline
+    /// segments.
+    pub fn from_text(text: &str) -> Self {
+        let mut document = Self::new();
+        document.push(Segment::Text(text.to_string()));
+        document
+    }
+
+    /// Create a document from a list of segments.
+    pub fn from_segments(segments: Vec<Segment>) -> Self {
+        let mut document = Self::new();
+        document.extend(segments);
+        document
+    }
+
+    /// Return the (normalized) segments.
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Return whether the document has no contents.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Return the length of the text representation in UTF-16 code units.
+    pub fn len(&self) -> u32 {
+        self.segments.iter().map(Segment::len).sum()
+    }
+
+    /// Return the text representation of the document.
+    pub fn to_text(&self) -> String {
+        self.segments.iter().map(Segment::as_text).collect()
+    }
+
+    /// Append a segment, keeping the segment list normalized.
+    pub fn push(&mut self, segment: Segment) {
+        match segment {
+            Segment::Text(text) => {
+                for (i, line) in text.split('\n').enumerate() {
+                    if i > 0 {
+                        self.segments.push(Segment::Newline);
+                    }
+                    if line.is_empty() {
+                        continue;
+                    }
+                    if let Some(Segment::Text(ref mut last)) = self.segments.last_mut() {
+                        last.push_str(line);
+                    } else {
+                        self.segments.push(Segment::Text(line.to_string()));
+                    }
+                }
+            }
+            other => self.segments.push(other),
+        }
+    }
+
+    /// Append multiple segments, keeping the segment list normalized.
+    pub fn extend<I: IntoIterator<Item = Segment>>(&mut self, segments: I) {
+        for segment in segments {
+            self.push(segment);
+        }
+    }
+
+    /// Move an offset that points into the middle of an atomic segment (or
+    /// into the middle of a surrogate pair) to the closest boundary in the
+    /// specified direction. Offsets past the end are clamped.
+    pub fn snap_offset(&self, offset: u32, forward: bool) -> u32 {
+        let mut start = 0;
+        for segment in &self.segments {
+            let end = start + segment.len();
+            if offset <= start {
+                return start;
+            }
+            if offset < end {
+                return match segment {
+                    Segment::Text(text) => start + snap_utf16(text, offset - start, forward),
+                    _ if forward => end,
+                    _ => start,
+                };
+            }
+            start = end;
+        }
+        start
+    }
+
+    /// Return the offset of the previous caret position, used when deleting
+    /// backwards. Atomic segments are skipped as a whole.
+    pub fn prev_offset(&self, offset: u32) -> u32 {
+        let offset = self.snap_offset(offset, false);
+        let mut start = 0;
+        for segment in &self.segments {
+            let end = start + segment.len();
+            if offset <= end {
+                return match segment {
+                    Segment::Text(text) if offset > start => {
+                        start + prev_char_boundary(text, offset - start)
+                    }
+                    Segment::Text(_) => start,
+                    _ if offset == end => start,
+                    _ => offset,
+                };
+            }
+            start = end;
+        }
+        offset
+    }
+
+    /// Return the offset of the next caret position, used when deleting
+    /// forwards. Atomic segments are skipped as a whole.
+    pub fn next_offset(&self, offset: u32) -> u32 {
+        let offset = self.snap_offset(offset, true);
+        let mut start = 0;
+        for segment in &self.segments {
+            let end = start + segment.len();
+            if offset < end {
+                return match segment {
+                    Segment::Text(text) => start + next_char_boundary(text, offset - start),
+                    _ => end,
+                };
+            }
+            start = end;
+        }
+        offset
+    }
+
+    /// Split the document at the specified offset. Atomic segments that
+    /// contain the offset are kept on the left side.
+    fn split_at(&self, offset: u32) -> (Vec<Segment>, Vec<Segment>) {
+        let mut left = vec![];
+        let mut right = vec![];
+        let mut start = 0;
+        for segment in &self.segments {
+            let end = start + segment.len();
+            if end <= offset || (segment.is_atomic() && start < offset) {
+                left.push(segment.clone());
+            } else if start >= offset {
+                right.push(segment.clone());
+            } else if let Segment::Text(text) = segment {
+                let index = utf16_to_byte_index(text, offset - start);
+                left.push(Segment::Text(text[..index].to_string()));
+                right.push(Segment::Text(text[index..].to_string()));
+            }
+            start = end;
+        }
+        (left, right)
+    }
+
+    /// Return a copy of the contents between `start` and `end`. Atomic
+    /// segments that are partially covered are included.
+    pub fn slice(&self, start: u32, end: u32) -> Document {
+        let start = self.snap_offset(start, false);
+        let end = self.snap_offset(end.max(start), true);
+        let (_, tail) = self.split_at(start);
+        let tail = Document::from_segments(tail);
+        let (middle, _) = tail.split_at(end - start);
+        Document::from_segments(middle)
+    }
+
+    /// Replace the contents between `start` and `end` with the contents of
+    /// `replacement`. Atomic segments that are partially covered by the range
+    /// are removed as well.
+    ///
+    /// Return the offset right after the inserted contents.
+    pub fn replace_range(&mut self, start: u32, end: u32, replacement: &Document) -> u32 {
+        let start = self.snap_offset(start.min(end), false);
+        let end = self.snap_offset(end.max(start), true);
+        let (head, _) = self.split_at(start);
+        let (_, tail) = self.split_at(end);
+        let mut document = Document::from_segments(head);
+        document.extend(replacement.segments.iter().cloned());
+        let caret = document.len();
+        document.extend(tail);
+        *self = document;
+        caret
+    }
+
+    /// Insert the contents of `insertion` at the specified offset.
+    ///
+    /// Return the offset right after the inserted contents.
+    pub fn insert(&mut self, offset: u32, insertion: &Document) -> u32 {
+        self.replace_range(offset, offset, insertion)
+    }
+
+    /// Remove the contents between `start` and `end`.
+    ///
+    /// Return the offset where the removed contents were.
+    pub fn delete_range(&mut self, start: u32, end: u32) -> u32 {
+        self.replace_range(start, end, &Document::new())
+    }
+}
+
+/// Return the length of a string in UTF-16 code units.
+pub fn utf16_len(text: &str) -> u32 {
+    #[allow(clippy::cast_possible_truncation)]
+    let len = text.encode_utf16().count() as u32;
+    len
+}
+
+/// Convert an UTF-16 offset into a byte index. If the offset points into the
+/// middle of a surrogate pair, the index of that character is returned. If the
+/// offset is too large, the length of the string is returned.
+pub fn utf16_to_byte_index(text: &str, offset: u32) -> usize {
+    let mut units = 0;
+    for (index, c) in text.char_indices() {
+        #[allow(clippy::cast_possible_truncation)]
+        let next = units + c.len_utf16() as u32;
+        if next > offset {
+            return index;
+        }
+        units = next;
+    }
+    text.len()
+}
+
+/// Move an UTF-16 offset within a string to a character boundary.
+fn snap_utf16(text: &str, offset: u32, forward: bool) -> u32 {
+    let index = utf16_to_byte_index(text, offset);
+    let snapped = utf16_len(&text[..index]);
+    if forward && snapped < offset {
+        next_char_boundary(text, snapped)
+    } else {
+        snapped
+    }
+}
+
+/// Return the UTF-16 offset of the character boundary before `offset`.
+fn prev_char_boundary(text: &str, offset: u32) -> u32 {
+    let index = utf16_to_byte_index(text, offset);
+    match text[..index].chars().next_back() {
+        #[allow(clippy::cast_possible_truncation)]
+        Some(c) => utf16_len(&text[..index]) - c.len_utf16() as u32,
+        None => 0,
+    }
+}
+
+/// Return the UTF-16 offset of the character boundary after `offset`.
+fn next_char_boundary(text: &str, offset: u32) -> u32 {
+    let index = utf16_to_byte_index(text, offset);
+    match text[index..].chars().next() {
+        #[allow(clippy::cast_possible_truncation)]
+        Some(c) => utf16_len(&text[..index]) + c.len_utf16() as u32,
+        None => utf16_len(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn img(alt: &str) -> Segment {
+        Segment::image("img.jpg", alt, "em")
+    }
+
+    fn text(text: &str) -> Segment {
+        Segment::Text(text.to_string())
+    }
+
+    mod normalize {
+        use super::*;
+
+        #[test]
+        fn from_text() {
+            let document = Document::from_text("ab\n\ncd");
+            assert_eq!(
+                document.segments(),
+                &[text("ab"), Segment::Newline, Segment::Newline, text("cd")]
+            );
+            assert_eq!(document.to_text(), "ab\n\ncd");
+        }
+
+        #[test]
+        fn merge_text() {
+            let document = Document::from_segments(vec![text("a"), text(""), text("b"), img("😀")]);
+            assert_eq!(document.segments(), &[text("ab"), img("😀")]);
+        }
+
+        #[test]
+        fn empty() {
+            assert!(Document::from_text("").is_empty());
+            assert_eq!(Document::new().len(), 0);
+        }
+    }
+
+    mod length {
+        use super::*;
+
+        #[test]
+        fn utf16() {
+            let document = Document::from_segments(vec![text("aä😀"), img("🍻"), Segment::Newline]);
+            assert_eq!(document.len(), 1 + 1 + 2 + 2 + 1);
+            assert_eq!(document.to_text(), "aä😀🍻\n");
+        }
+    }
+
+    mod offsets {
+        use super::*;
+
+        #[test]
+        fn snap_into_image() {
+            let document = Document::from_segments(vec![text("a"), img("🍻"), text("b")]);
+            assert_eq!(document.snap_offset(2, false), 1);
+            assert_eq!(document.snap_offset(2, true), 3);
+            assert_eq!(document.snap_offset(3, false), 3);
+            assert_eq!(document.snap_offset(99, false), 4);
+        }
+
+        #[test]
+        fn snap_into_surrogate_pair() {
+            let document = Document::from_text("a😀b");
+            assert_eq!(document.snap_offset(2, false), 1);
+            assert_eq!(document.snap_offset(2, true), 3);
+        }
+
+        #[test]
+        fn prev_and_next() {
+            let document = Document::from_segments(vec![text("a😀"), img("🍻"), Segment::Newline]);
+            assert_eq!(document.prev_offset(6), 5);
+            assert_eq!(document.prev_offset(5), 3);
+            assert_eq!(document.prev_offset(3), 1);
+            assert_eq!(document.prev_offset(1), 0);
+            assert_eq!(document.prev_offset(0), 0);
+            assert_eq!(document.next_offset(0), 1);
+            assert_eq!(document.next_offset(1), 3);
+            assert_eq!(document.next_offset(3), 5);
+            assert_eq!(document.next_offset(5), 6);
+            assert_eq!(document.next_offset(6), 6);
+        }
+
+        #[test]
+        fn mention_is_atomic() {
+            let mention = Segment::mention("ABCD1234", "Alice", "@[ABCD1234]");
+            let document = Document::from_segments(vec![text("a"), mention]);
+            assert_eq!(document.to_text(), "a@[ABCD1234]");
+            assert_eq!(document.prev_offset(12), 1);
+            assert_eq!(document.next_offset(1), 12);
+            assert_eq!(document.snap_offset(5, false), 1);
+        }
+    }
+
+    mod editing {
+        use super::*;
+
+        #[test]
+        fn insert_text() {
+            let mut document = Document::from_text("ad");
+            let caret = document.insert(1, &Document::from_text("bc"));
+            assert_eq!(caret, 3);
+            assert_eq!(document.segments(), &[text("abcd")]);
+        }
+
+        #[test]
+        fn insert_image_splits_text() {
+            let mut document = Document::from_text("bonjour");
+            let caret = document.insert(3, &Document::from_segments(vec![img("😀")]));
+            assert_eq!(caret, 5);
+            assert_eq!(document.segments(), &[text("bon"), img("😀"), text("jour")]);
+        }
+
+        #[test]
+        fn replace_partially_covered_image() {
+            let mut document = Document::from_segments(vec![text("ab"), img("😀"), text("c")]);
+            let caret = document.replace_range(1, 3, &Document::from_text("z"));
+            assert_eq!(caret, 2);
+            assert_eq!(document.segments(), &[text("azc")]);
+        }
+
+        #[test]
+        fn delete_newline() {
+            let mut document = Document::from_text("a\nb");
+            let caret = document.delete_range(1, 2);
+            assert_eq!(caret, 1);
+            assert_eq!(document.to_text(), "ab");
+        }
+
+        #[test]
+        fn slice() {
+            let document = Document::from_segments(vec![text("hello"), img("😀"), text("world")]);
+            assert_eq!(document.slice(3, 8).to_text(), "lo😀w");
+            assert_eq!(document.slice(6, 6).to_text(), "😀");
+            assert!(document.slice(2, 2).is_empty());
+        }
+    }
+}
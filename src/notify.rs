@@ -0,0 +1,322 @@
+//! Notification callbacks for changes of the contents, the selection and the
+//! focus.
+//!
+//! Content and selection changes are debounced: They are collected and
+//! reported once no further change happened for the configured delay. All
+//! other events are reported right after the current operation. Callbacks are
+//! never called synchronously, because they may call methods of the compose
+//! area while it is still in use.
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::rc::{Rc, Weak};
+
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{Element, Range, Window};
+
+use crate::extract::{ElementHandlers, Extraction};
+use crate::length::LimitExceeded;
+use crate::model::{Document, Segment};
+use crate::selection::range_to_offsets;
+use crate::SelectionOffsets;
+
+/// A summary of the contents, passed to the `on_change` callback.
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentSummary {
+    length: u32,
+    image_count: u32,
+}
+
+impl ContentSummary {
+    fn of(document: &Document) -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        let image_count = document
+            .segments()
+            .iter()
+            .filter(|segment| matches!(segment, Segment::Image { .. }))
+            .count() as u32;
+        Self {
+            length: document.len(),
+            image_count,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl ContentSummary {
+    /// The length of the text, measured like the logical offsets (see
+    /// `ComposeArea.get_selection_offsets`).
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// The number of images.
+    pub fn image_count(&self) -> u32 {
+        self.image_count
+    }
+
+    /// Whether the compose area is empty.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+}
+
+/// The events that can be subscribed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Event {
+    Change,
+    SelectionChange,
+    Focus,
+    Blur,
+    EmptyStateChange,
+    OverLimit,
+}
+
+#[derive(Default)]
+struct Callbacks {
+    change: Option<js_sys::Function>,
+    selection_change: Option<js_sys::Function>,
+    focus: Option<js_sys::Function>,
+    blur: Option<js_sys::Function>,
+    empty_state_change: Option<js_sys::Function>,
+    over_limit: Option<js_sys::Function>,
+}
+
+impl Callbacks {
+    fn get_mut(&mut self, event: Event) -> &mut Option<js_sys::Function> {
+        match event {
+            Event::Change => &mut self.change,
+            Event::SelectionChange => &mut self.selection_change,
+            Event::Focus => &mut self.focus,
+            Event::Blur => &mut self.blur,
+            Event::EmptyStateChange => &mut self.empty_state_change,
+            Event::OverLimit => &mut self.over_limit,
+        }
+    }
+}
+
+/// Dispatches notifications to the registered callbacks.
+///
+/// The notifier is shared between the compose area and its event listeners.
+#[derive(Clone)]
+pub(crate) struct Notifier(Rc<Inner>);
+
+struct Inner {
+    window: Window,
+    wrapper: Element,
+    selection_range: Rc<RefCell<Option<Range>>>,
+    /// A copy of the element handlers of the compose area, used to extract
+    /// the contents.
+    element_handlers: RefCell<ElementHandlers>,
+    callbacks: RefCell<Callbacks>,
+    delay: i32,
+    /// Handle of the pending timeout (if any).
+    timeout: Cell<Option<i32>>,
+    /// The closure called by the timeout.
+    flush: RefCell<Option<Closure<dyn FnMut()>>>,
+    /// Events that are not debounced, with the callback argument.
+    events: RefCell<Vec<(Event, JsValue)>>,
+    /// Handle of the pending timeout for `events` (if any).
+    events_timeout: Cell<Option<i32>>,
+    /// The closure called by the events timeout.
+    dispatch: RefCell<Option<Closure<dyn FnMut()>>>,
+    /// Whether the contents were changed since the last notification.
+    changed: Cell<bool>,
+    /// The last reported empty state.
+    empty: Cell<bool>,
+    /// The last reported selection.
+    selection: Cell<Option<(u32, u32)>>,
+}
+
+impl Notifier {
+    /// Create a notifier that debounces content and selection changes by
+    /// `delay` milliseconds.
+    pub(crate) fn new(
+        window: &Window,
+        wrapper: &Element,
+        selection_range: &Rc<RefCell<Option<Range>>>,
+        delay: u32,
+    ) -> Self {
+        let inner = Rc::new(Inner {
+            window: window.clone(),
+            wrapper: wrapper.clone(),
+            selection_range: Rc::clone(selection_range),
+            element_handlers: RefCell::new(ElementHandlers::new()),
+            callbacks: RefCell::new(Callbacks::default()),
+            delay: i32::try_from(delay).unwrap_or(i32::MAX),
+            timeout: Cell::new(None),
+            flush: RefCell::new(None),
+            events: RefCell::new(vec![]),
+            events_timeout: Cell::new(None),
+            dispatch: RefCell::new(None),
+            changed: Cell::new(false),
+            empty: Cell::new(Extraction::new(wrapper).document.is_empty()),
+            selection: Cell::new(None),
+        });
+        let weak: Weak<Inner> = Rc::downgrade(&inner);
+        inner.flush.replace(Some(Closure::wrap(Box::new(move || {
+            if let Some(inner) = weak.upgrade() {
+                inner.flush();
+            }
+        }) as Box<dyn FnMut()>)));
+        let weak: Weak<Inner> = Rc::downgrade(&inner);
+        inner.dispatch.replace(Some(Closure::wrap(Box::new(move || {
+            if let Some(inner) = weak.upgrade() {
+                inner.dispatch();
+            }
+        }) as Box<dyn FnMut()>)));
+        Self(inner)
+    }
+
+    /// Register (or remove) the callback for an event.
+    pub(crate) fn set_callback(&self, event: Event, callback: Option<js_sys::Function>) {
+        *self.0.callbacks.borrow_mut().get_mut(event) = callback;
+    }
+
+    /// Update the element handlers used to extract the contents.
+    pub(crate) fn set_element_handlers(&self, handlers: &ElementHandlers) {
+        self.0.element_handlers.replace(handlers.clone());
+    }
+
+    /// Report a change of the contents.
+    pub(crate) fn content_changed(&self) {
+        self.0.changed.set(true);
+        self.0.schedule();
+    }
+
+    /// Report a (potential) change of the selection.
+    pub(crate) fn selection_changed(&self) {
+        self.0.schedule();
+    }
+
+    /// Call the callbacks for pending events and changes immediately.
+    #[cfg(test)]
+    pub(crate) fn flush(&self) {
+        self.0.clear_timeouts();
+        self.0.dispatch();
+        self.0.flush();
+    }
+
+    /// Report that the wrapper element gained or lost the focus. This is not
+    /// debounced.
+    pub(crate) fn focus_changed(&self, focused: bool) {
+        let event = if focused { Event::Focus } else { Event::Blur };
+        self.0.enqueue(event, JsValue::UNDEFINED);
+    }
+
+    /// Report an operation that exceeded the maximum length. This is not
+    /// debounced.
+    pub(crate) fn over_limit(&self, details: LimitExceeded) {
+        self.0.enqueue(Event::OverLimit, details.into());
+    }
+
+    /// Cancel all pending notifications and remove the callbacks.
+    pub(crate) fn destroy(&self) {
+        self.0.clear_timeouts();
+        self.0.events.borrow_mut().clear();
+        self.0.changed.set(false);
+        self.0.callbacks.replace(Callbacks::default());
+    }
+}
+
+impl Inner {
+    /// (Re-)start the timeout.
+    fn schedule(&self) {
+        self.clear_timeout();
+        self.timeout.set(self.set_timeout(&self.flush, self.delay));
+    }
+
+    /// Queue an event and start the events timeout, unless it is pending.
+    fn enqueue(&self, event: Event, argument: JsValue) {
+        self.events.borrow_mut().push((event, argument));
+        if self.events_timeout.get().is_none() {
+            self.events_timeout.set(self.set_timeout(&self.dispatch, 0));
+        }
+    }
+
+    /// Call `closure` after `delay` milliseconds. Return the timeout handle.
+    fn set_timeout(
+        &self,
+        closure: &RefCell<Option<Closure<dyn FnMut()>>>,
+        delay: i32,
+    ) -> Option<i32> {
+        let closure = closure.borrow();
+        let callback = closure.as_ref()?.as_ref().unchecked_ref();
+        match self
+            .window
+            .set_timeout_with_callback_and_timeout_and_arguments_0(callback, delay)
+        {
+            Ok(handle) => Some(handle),
+            Err(_) => {
+                warn!("[compose_area] Could not schedule notification");
+                None
+            }
+        }
+    }
+
+    fn clear_timeout(&self) {
+        if let Some(handle) = self.timeout.take() {
+            self.window.clear_timeout_with_handle(handle);
+        }
+    }
+
+    /// Clear the debounce timeout and the events timeout.
+    fn clear_timeouts(&self) {
+        self.clear_timeout();
+        if let Some(handle) = self.events_timeout.take() {
+            self.window.clear_timeout_with_handle(handle);
+        }
+    }
+
+    /// Call the callbacks for all queued events.
+    fn dispatch(&self) {
+        self.events_timeout.set(None);
+        let events = self.events.replace(vec![]);
+        for (event, argument) in events {
+            self.call(event, &argument);
+        }
+    }
+
+    /// Call the callbacks for all changes since the last notification.
+    fn flush(&self) {
+        self.timeout.set(None);
+        let extraction = Extraction::with_handlers(&self.wrapper, &self.element_handlers.borrow());
+
+        if self.changed.replace(false) {
+            let summary = ContentSummary::of(&extraction.document);
+            let empty = summary.is_empty();
+            self.call(Event::Change, &summary.into());
+            if self.empty.replace(empty) != empty {
+                self.call(Event::EmptyStateChange, &empty.into());
+            }
+        }
+
+        let range = self.selection_range.borrow().clone();
+        let selection = range.and_then(|range| range_to_offsets(&extraction, &range));
+        if self.selection.replace(selection) != selection {
+            let offsets = selection.map_or(JsValue::UNDEFINED, |(start, end)| {
+                SelectionOffsets { start, end }.into()
+            });
+            self.call(Event::SelectionChange, &offsets);
+        }
+    }
+
+    /// Call the callback for an event, if registered.
+    fn call(&self, event: Event, argument: &JsValue) {
+        // Note: The callback is cloned, so that it may modify the callbacks.
+        let callback = self.callbacks.borrow_mut().get_mut(event).clone();
+        if let Some(callback) = callback {
+            if callback.call1(&JsValue::NULL, argument).is_err() {
+                warn!("[compose_area] {:?} callback threw an exception", event);
+            }
+        }
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // The closures are dropped together with this struct, so they must
+        // not be called anymore.
+        self.clear_timeouts();
+    }
+}
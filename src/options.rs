@@ -0,0 +1,304 @@
+//! Configuration of a compose area instance.
+use log::Level;
+use wasm_bindgen::prelude::*;
+
+use crate::length::LengthUnit;
+use crate::mention::MentionFormat;
+use crate::words::{WordMode, WordRules};
+
+/// Options for `ComposeArea.bind_with_options`.
+///
+/// All setters return the options object, so they can be chained:
+///
+/// ```js
+/// const options = new ComposeAreaOptions()
+///     .log_level('warn')
+///     .max_length(3500);
+/// ```
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct ComposeAreaOptions {
+    pub(crate) log_level: Option<Level>,
+    pub(crate) wrapper_classes: Vec<String>,
+    pub(crate) image_id_prefix: Option<String>,
+    pub(crate) strip_image_ids: bool,
+    pub(crate) trim_text: bool,
+    pub(crate) word_rules: WordRules,
+    pub(crate) max_length: Option<u32>,
+    pub(crate) max_length_unit: LengthUnit,
+    pub(crate) reject_over_limit: bool,
+    pub(crate) emoji_resolver: Option<js_sys::Function>,
+    pub(crate) undo_history: bool,
+    pub(crate) trailing_br_workaround: bool,
+    pub(crate) track_selection: bool,
+    pub(crate) handle_beforeinput: bool,
+    pub(crate) handle_paste: bool,
+    pub(crate) handle_clipboard: bool,
+    pub(crate) formatting_shortcuts: bool,
+    pub(crate) change_debounce: u32,
+    pub(crate) paste_image_classes: Vec<String>,
+    pub(crate) mention_format: MentionFormat,
+    pub(crate) mention_resolver: Option<js_sys::Function>,
+    pub(crate) highlight_markup: bool,
+}
+
+impl Default for ComposeAreaOptions {
+    fn default() -> Self {
+        Self {
+            log_level: None,
+            wrapper_classes: vec!["cawrapper".to_string(), "initialized".to_string()],
+            image_id_prefix: None,
+            strip_image_ids: false,
+            trim_text: true,
+            word_rules: WordRules::default(),
+            max_length: None,
+            max_length_unit: LengthUnit::Utf16,
+            reject_over_limit: false,
+            emoji_resolver: None,
+            undo_history: true,
+            trailing_br_workaround: true,
+            track_selection: true,
+            handle_beforeinput: false,
+            handle_paste: false,
+            handle_clipboard: false,
+            formatting_shortcuts: false,
+            change_debounce: 100,
+            paste_image_classes: vec![],
+            mention_format: MentionFormat::default(),
+            mention_resolver: None,
+            highlight_markup: false,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl ComposeAreaOptions {
+    /// Create options with the default values.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Initialize the console logger with the specified log level. Valid log
+    /// levels: `trace`, `debug`, `info`, `warn` or `error`. Default: The
+    /// logger is not initialized.
+    pub fn log_level(mut self, level: &str) -> Self {
+        self.log_level = match level {
+            "trace" => Some(Level::Trace),
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            other => {
+                web_sys::console::warn_1(&format!("Invalid log level: {}", other).into());
+                None
+            }
+        };
+        self
+    }
+
+    /// The classes added to the wrapper element, separated by whitespace.
+    /// Default: `"cawrapper initialized"`.
+    pub fn wrapper_classes(mut self, classes: &str) -> Self {
+        self.wrapper_classes = classes.split_whitespace().map(str::to_string).collect();
+        self
+    }
+
+    /// Give images inserted with `insert_image` an ID consisting of the
+    /// specified prefix and a counter. The caller must ensure that the prefix
+    /// is unique on the page. Default: A prefix that is unique across all
+    /// compose area instances.
+    pub fn image_id_prefix(mut self, prefix: &str) -> Self {
+        self.image_id_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Insert images without an ID attribute. This takes precedence over
+    /// `image_id_prefix`, which is used again once stripping is disabled.
+    /// Default: `false`.
+    pub fn strip_image_ids(mut self, strip: bool) -> Self {
+        self.strip_image_ids = strip;
+        self
+    }
+
+    /// Whether `get_text` trims leading / trailing whitespace if its
+    /// `no_trim` argument is omitted. Default: `true`.
+    pub fn trim_text(mut self, trim: bool) -> Self {
+        self.trim_text = trim;
+        self
+    }
+
+    /// How `get_word_at_caret` finds words:
+    ///
+    /// - `boundaries`: Words are delimited by the boundary characters (see
+    ///   `word_boundaries`).
+    /// - `unicode`: Unicode word segmentation (UAX #29). Punctuation is not
+    ///   part of words.
+    /// - `token`: Like `unicode`, but a token prefix (see `token_prefixes`)
+    ///   right before the word is part of it, e.g. `@alice`.
+    ///
+    /// Default: `boundaries`.
+    pub fn word_mode(mut self, mode: &str) -> Self {
+        match WordMode::from_name(mode) {
+            Some(mode) => self.word_rules.mode = mode,
+            None => web_sys::console::warn_1(&format!("Invalid word mode: {}", mode).into()),
+        }
+        self
+    }
+
+    /// The characters that delimit words in the `boundaries` word mode.
+    /// Default: All whitespace characters (including newlines and
+    /// non-breaking spaces).
+    pub fn word_boundaries(mut self, chars: &str) -> Self {
+        self.word_rules.boundaries = Some(chars.chars().collect());
+        self
+    }
+
+    /// The characters that start a token in the `token` word mode. Default:
+    /// `"@:"`.
+    pub fn token_prefixes(mut self, chars: &str) -> Self {
+        self.word_rules.token_prefixes = chars.chars().collect();
+        self
+    }
+
+    /// Whether images are part of words in `get_word_at_caret`. Otherwise,
+    /// images are word boundaries. Default: `false`.
+    pub fn images_in_words(mut self, images_in_words: bool) -> Self {
+        self.word_rules.images_in_words = images_in_words;
+        self
+    }
+
+    /// The maximum length of the contents, measured in the unit set with
+    /// `max_length_unit`. Typed, pasted and inserted contents that exceed it
+    /// are truncated to fit (or rejected, see `reject_over_limit`). Default:
+    /// No limit.
+    pub fn max_length(mut self, max_length: Option<u32>) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// The unit of the maximum length: `utf8` (bytes of the UTF-8 encoded
+    /// text), `utf16` (code units, like the logical offsets), `scalars`
+    /// (Unicode code points) or `graphemes` (user-perceived characters).
+    /// Images are measured by their alt text. Default: `utf16`.
+    pub fn max_length_unit(mut self, unit: &str) -> Self {
+        match LengthUnit::from_name(unit) {
+            Some(unit) => self.max_length_unit = unit,
+            None => web_sys::console::warn_1(&format!("Invalid length unit: {}", unit).into()),
+        }
+        self
+    }
+
+    /// Whether insertions that exceed the maximum length are rejected as a
+    /// whole instead of being truncated. Images are never truncated, so they
+    /// are always rejected. Default: `false`.
+    pub fn reject_over_limit(mut self, reject: bool) -> Self {
+        self.reject_over_limit = reject;
+        self
+    }
+
+    /// The emoji resolver, see `ComposeArea.set_emoji_resolver`. Default: No
+    /// emoji replacement.
+    pub fn emoji_resolver(mut self, resolver: Option<js_sys::Function>) -> Self {
+        self.emoji_resolver = resolver;
+        self
+    }
+
+    /// Whether changes are recorded in the undo history. Default: `true`.
+    pub fn undo_history(mut self, enabled: bool) -> Self {
+        self.undo_history = enabled;
+        self
+    }
+
+    /// Whether nodes appended without a stored selection range are inserted
+    /// before a trailing `<br>` element. This is needed because Firefox adds
+    /// a trailing line break that isn't rendered. Default: `true`.
+    pub fn trailing_br_workaround(mut self, enabled: bool) -> Self {
+        self.trailing_br_workaround = enabled;
+        self
+    }
+
+    /// Whether the compose area attaches event listeners that store the
+    /// selection range automatically (see `ComposeArea.store_selection_range`).
+    /// Default: `true`.
+    pub fn track_selection(mut self, enabled: bool) -> Self {
+        self.track_selection = enabled;
+        self
+    }
+
+    /// Whether the compose area attaches a `beforeinput` listener that
+    /// calls `ComposeArea.handle_beforeinput`. Default: `false`.
+    pub fn handle_beforeinput(mut self, enabled: bool) -> Self {
+        self.handle_beforeinput = enabled;
+        self
+    }
+
+    /// Whether the compose area attaches a `paste` listener that calls
+    /// `ComposeArea.handle_paste`. Default: `false`.
+    pub fn handle_paste(mut self, enabled: bool) -> Self {
+        self.handle_paste = enabled;
+        self
+    }
+
+    /// Whether the compose area attaches `copy` and `cut` listeners that call
+    /// `ComposeArea.handle_copy` and `handle_cut`. Default: `false`.
+    pub fn handle_clipboard(mut self, enabled: bool) -> Self {
+        self.handle_clipboard = enabled;
+        self
+    }
+
+    /// Whether the compose area attaches a `keydown` listener that calls
+    /// `ComposeArea.handle_keydown` for the formatting shortcuts. Default:
+    /// `false`.
+    pub fn formatting_shortcuts(mut self, enabled: bool) -> Self {
+        self.formatting_shortcuts = enabled;
+        self
+    }
+
+    /// The delay in milliseconds by which content and selection change
+    /// notifications are debounced (see `ComposeArea.on_change`). Default:
+    /// `100`.
+    pub fn change_debounce(mut self, delay: u32) -> Self {
+        self.change_debounce = delay;
+        self
+    }
+
+    /// The image classes (e.g. of emoji) that are kept when pasting HTML,
+    /// separated by whitespace. Pasted images without one of these classes
+    /// are replaced by their alt text. Default: No pasted images are kept.
+    pub fn paste_image_classes(mut self, classes: &str) -> Self {
+        self.paste_image_classes = classes.split_whitespace().map(str::to_string).collect();
+        self
+    }
+
+    /// The format of mention tokens in the text representation (see
+    /// `ComposeArea.insert_mention`). The pattern must contain the `{id}`
+    /// placeholder, preceded by a prefix. Default: `"@[{id}]"`.
+    pub fn mention_format(mut self, pattern: &str) -> Self {
+        match MentionFormat::from_pattern(pattern) {
+            Some(format) => self.mention_format = format,
+            None => {
+                web_sys::console::warn_1(&format!("Invalid mention format: {}", pattern).into())
+            }
+        }
+        self
+    }
+
+    /// A function that returns the display name for a mentioned ID, used when
+    /// `set_text` converts mention tokens to mentions. If it is not set (or
+    /// returns `undefined`), the ID is displayed. Default: No resolver.
+    pub fn mention_resolver(mut self, resolver: Option<js_sys::Function>) -> Self {
+        self.mention_resolver = resolver;
+        self
+    }
+
+    /// Whether markup in the text (`*bold*`, `_italic_`, `~strikethrough~` and
+    /// `` `monospace` ``) is highlighted while typing (see
+    /// `ComposeArea.update_markup`). The markup characters stay part of the
+    /// text. Default: `false`.
+    pub fn highlight_markup(mut self, enabled: bool) -> Self {
+        self.highlight_markup = enabled;
+        self
+    }
+}
@@ -0,0 +1,124 @@
+//! Measuring the position of the caret on the screen, e.g. to place an
+//! autocomplete popup next to it.
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{Document, DomRect, Element, Range};
+
+use crate::error::{ComposeAreaError, DomResultExt};
+
+/// A rectangle relative to the viewport (like the result of
+/// `getBoundingClientRect`), returned by `ComposeArea.caret_rect` and
+/// `word_at_caret_rect`.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CaretRect {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    line_height: f64,
+}
+
+#[wasm_bindgen]
+impl CaretRect {
+    /// The distance of the left edge from the left edge of the viewport.
+    pub fn x(&self) -> f64 {
+        self.x
+    }
+
+    /// The distance of the top edge from the top edge of the viewport.
+    pub fn y(&self) -> f64 {
+        self.y
+    }
+
+    /// The width. A caret has a width of 0.
+    pub fn width(&self) -> f64 {
+        self.width
+    }
+
+    /// The height.
+    pub fn height(&self) -> f64 {
+        self.height
+    }
+
+    /// The computed line height at the start of the range. If the line
+    /// height is `normal`, the height of the rectangle is used.
+    pub fn line_height(&self) -> f64 {
+        self.line_height
+    }
+}
+
+/// Return the client rectangle of a range. For collapsed ranges, a rectangle
+/// with a width of 0 is returned.
+pub(crate) fn range_rect(
+    document: &Document,
+    range: &Range,
+) -> Result<CaretRect, ComposeAreaError> {
+    let rect = if range.collapsed() {
+        match first_client_rect(range) {
+            Some(rect) => rect,
+            None => measure_with_marker(document, range)?,
+        }
+    } else {
+        range.get_bounding_client_rect()
+    };
+    let line_height = line_height(range).unwrap_or_else(|| rect.height());
+    Ok(CaretRect {
+        x: rect.x(),
+        y: rect.y(),
+        width: if range.collapsed() { 0.0 } else { rect.width() },
+        height: rect.height(),
+        line_height,
+    })
+}
+
+/// Return the first non-empty client rectangle of a range.
+///
+/// Browsers return no (or empty) rectangles for collapsed ranges that are
+/// not inside a text node, e.g. on empty lines or right after an image.
+fn first_client_rect(range: &Range) -> Option<DomRect> {
+    range
+        .get_client_rects()?
+        .get(0)
+        .filter(|rect| rect.height() > 0.0)
+}
+
+/// Measure the position of a collapsed range by temporarily inserting a
+/// marker element at its position.
+fn measure_with_marker(document: &Document, range: &Range) -> Result<DomRect, ComposeAreaError> {
+    let marker = document
+        .create_element("span")
+        .dom_err("create marker element")?;
+    // A zero width space gives the marker the height of the line
+    marker.set_text_content(Some("\u{200b}"));
+    range
+        .clone_range()
+        .insert_node(&marker)
+        .dom_err("insert marker element")?;
+    let rect = marker.get_bounding_client_rect();
+
+    // Remove the marker again. If a text node was split to insert it, the
+    // parts are joined again (this keeps live ranges intact).
+    let parent = marker.parent_node();
+    marker.remove();
+    if let Some(parent) = parent {
+        parent.normalize();
+    }
+    Ok(rect)
+}
+
+/// Return the computed line height (in pixels) of the element containing
+/// the start of a range. Return `None` if the line height is `normal`.
+fn line_height(range: &Range) -> Option<f64> {
+    let container = range.start_container().ok()?;
+    let element = match container.dyn_into::<Element>() {
+        Ok(element) => element,
+        Err(node) => node.parent_element()?,
+    };
+    let style = web_sys::window()?.get_computed_style(&element).ok()??;
+    style
+        .get_property_value("line-height")
+        .ok()?
+        .strip_suffix("px")?
+        .parse()
+        .ok()
+}
@@ -0,0 +1,554 @@
+//! Render the document model into the DOM.
+use wasm_bindgen::JsCast;
+use web_sys::{DocumentFragment, Element, Node, Text};
+
+use crate::error::{ComposeAreaError, DomResultExt};
+use crate::extract::token_segment;
+use crate::markup::{highlight, Styles};
+use crate::model::{Document, Segment};
+
+/// Attribute that marks a filler `<br>` element.
+///
+/// Browsers don't render a trailing line break, unless it is followed by
+/// another one. Thus, if the document ends with a newline, a filler element is
+/// added. It is ignored when extracting the document from the DOM.
+pub(crate) const FILLER_ATTRIBUTE: &str = "data-ca-filler";
+
+/// Attribute that contains the ID of a mention element.
+pub(crate) const MENTION_ATTRIBUTE: &str = "data-ca-mention";
+
+/// Attribute that contains the token of a mention element (see `mention`).
+pub(crate) const TOKEN_ATTRIBUTE: &str = "data-ca-token";
+
+/// The class of mention elements.
+pub(crate) const MENTION_CLASS: &str = "ca-mention";
+
+/// Attribute that contains the kind of a token element.
+pub(crate) const KIND_ATTRIBUTE: &str = "data-ca-kind";
+
+/// The class of token elements.
+pub(crate) const TOKEN_CLASS: &str = "ca-token";
+
+/// The class of elements that highlight markup. Additional classes
+/// (`ca-bold`, `ca-italic`, `ca-strikethrough`, `ca-monospace` and
+/// `ca-marker` for the markup characters) describe the styles.
+pub(crate) const MARKUP_CLASS: &str = "ca-markup";
+
+/// A child node of the wrapper element.
+enum Piece<'a> {
+    /// A text node.
+    Text(&'a str),
+    /// Text with markup styles, wrapped in a `<span>` element.
+    Markup(&'a str, Styles),
+    /// A segment that is not text.
+    Other(&'a Segment),
+}
+
+/// Split the document into the pieces that are rendered as child nodes of
+/// the wrapper element. If `highlight_markup` is set, text segments are split
+/// into runs with uniform markup styles.
+fn pieces(document: &Document, highlight_markup: bool) -> Vec<Piece> {
+    let runs = if highlight_markup {
+        highlight(document)
+    } else {
+        vec![]
+    };
+    let mut pieces = vec![];
+    for (index, segment) in document.segments().iter().enumerate() {
+        match (segment, runs.get(index)) {
+            (Segment::Text(text), Some(runs)) => {
+                pieces.extend(runs.iter().map(|run| {
+                    let text = &text[run.range.clone()];
+                    if run.styles.is_empty() {
+                        Piece::Text(text)
+                    } else {
+                        Piece::Markup(text, run.styles)
+                    }
+                }));
+            }
+            (Segment::Text(text), None) => pieces.push(Piece::Text(text)),
+            (segment, _) => pieces.push(Piece::Other(segment)),
+        }
+    }
+    pieces
+}
+
+/// Update the child nodes of the wrapper element so that they represent the
+/// specified document.
+///
+/// This is a simple reconciler: Existing child nodes are compared with the
+/// segments one by one and are only replaced if they differ. A node that was
+/// removed from the document is detected by comparing its next sibling as
+/// well. That way, references to unchanged nodes (e.g. to images returned by
+/// `insert_image`) stay valid.
+///
+/// If `highlight_markup` is set, markup in the text (e.g. `*bold*`) is
+/// wrapped in `<span>` elements with the `ca-markup` class. They are ignored
+/// when extracting the document from the DOM.
+pub(crate) fn render(
+    dom: &web_sys::Document,
+    wrapper: &Element,
+    document: &Document,
+    highlight_markup: bool,
+) -> Result<(), ComposeAreaError> {
+    trace!("[compose_area]   render");
+    let children = wrapper.child_nodes();
+    let mut index = 0;
+    for piece in pieces(document, highlight_markup) {
+        let existing = children.item(index);
+        match (&existing, &piece) {
+            (Some(node), piece) if piece_matches(node, piece) => {}
+            (Some(node), piece)
+                if node
+                    .next_sibling()
+                    .map_or(false, |next| piece_matches(&next, piece)) =>
+            {
+                wrapper.remove_child(node).dom_err("remove node")?;
+            }
+            (Some(node), Piece::Text(text)) if node.node_type() == Node::TEXT_NODE => {
+                node.unchecked_ref::<Text>().set_data(text);
+            }
+            (existing, piece) => {
+                let node = match piece {
+                    Piece::Text(text) => dom.create_text_node(text).unchecked_into(),
+                    Piece::Markup(text, styles) => {
+                        create_markup(dom, text, *styles)?.unchecked_into()
+                    }
+                    Piece::Other(segment) => create_node(dom, segment)?,
+                };
+                wrapper
+                    .insert_before(&node, existing.as_ref())
+                    .dom_err("insert node")?;
+            }
+        }
+        index += 1;
+    }
+
+    // Keep an existing filler element, it is updated below
+    if children.item(index).as_ref().map_or(false, is_filler) {
+        index += 1;
+    }
+
+    // Remove superfluous nodes
+    while children.length() > index {
+        if let Some(last_child) = wrapper.last_child() {
+            wrapper
+                .remove_child(&last_child)
+                .dom_err("remove last child")?;
+        }
+    }
+    update_filler(dom, wrapper)
+}
+
+/// Add a filler element to the wrapper element if its contents end with a
+/// newline, and remove filler elements that are not needed (anymore).
+///
+/// This must be called whenever nodes were inserted into the wrapper element
+/// without rendering the whole document.
+pub(crate) fn update_filler(
+    dom: &web_sys::Document,
+    wrapper: &Element,
+) -> Result<(), ComposeAreaError> {
+    let children = wrapper.child_nodes();
+    let mut nodes: Vec<Node> = (0..children.length())
+        .filter_map(|i| children.item(i))
+        .collect();
+    let trailing_filler = match nodes.last() {
+        Some(node) if is_filler(node) => nodes.pop(),
+        _ => None,
+    };
+    for filler in nodes.iter().filter(|node| is_filler(node)) {
+        wrapper.remove_child(filler).dom_err("remove filler node")?;
+    }
+    let ends_with_newline = nodes
+        .iter()
+        .rev()
+        .find(|node| !is_filler(node))
+        .and_then(|node| node.dyn_ref::<Element>())
+        .map_or(false, |element| element.tag_name() == "BR");
+    match (ends_with_newline, trailing_filler) {
+        (true, None) => {
+            wrapper
+                .append_child(&create_filler(dom)?)
+                .dom_err("append filler node")?;
+        }
+        (false, Some(filler)) => {
+            wrapper
+                .remove_child(&filler)
+                .dom_err("remove filler node")?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Create a document fragment containing the DOM nodes for the specified
+/// document.
+///
+/// The fragment does not contain a filler element. Once it has been inserted,
+/// call `update_filler`.
+pub(crate) fn render_fragment(
+    dom: &web_sys::Document,
+    document: &Document,
+) -> Result<DocumentFragment, ComposeAreaError> {
+    let fragment = dom.create_document_fragment();
+    for segment in document.segments() {
+        fragment
+            .append_child(&create_node(dom, segment)?)
+            .dom_err("append node to fragment")?;
+    }
+    Ok(fragment)
+}
+
+/// Return whether the DOM node represents the specified piece.
+fn piece_matches(node: &Node, piece: &Piece) -> bool {
+    match piece {
+        Piece::Text(text) => {
+            node.node_type() == Node::TEXT_NODE && node.node_value().as_deref() == Some(*text)
+        }
+        Piece::Markup(text, styles) => node.dyn_ref::<Element>().map_or(false, |element| {
+            element.tag_name() == "SPAN"
+                && element.get_attribute("class") == Some(markup_classes(*styles))
+                && element.child_element_count() == 0
+                && element.text_content().as_deref() == Some(*text)
+        }),
+        Piece::Other(segment) => node_matches(node, segment),
+    }
+}
+
+/// Return whether the DOM node represents the specified segment.
+fn node_matches(node: &Node, segment: &Segment) -> bool {
+    let element = node.dyn_ref::<Element>();
+    match (segment, element) {
+        (Segment::Text(text), None) => {
+            node.node_type() == Node::TEXT_NODE && node.node_value().as_ref() == Some(text)
+        }
+        (Segment::Image { src, alt, cls, id }, Some(element)) => {
+            element.tag_name() == "IMG"
+                && element.get_attribute("src").as_ref() == Some(src)
+                && element.get_attribute("alt").as_ref() == Some(alt)
+                && element.get_attribute("class").unwrap_or_default() == *cls
+                && element.get_attribute("id") == *id
+        }
+        (Segment::Mention { id, name, token }, Some(element)) => {
+            element.get_attribute(MENTION_ATTRIBUTE).as_ref() == Some(id)
+                && element.get_attribute(TOKEN_ATTRIBUTE).as_ref() == Some(token)
+                && element.text_content().as_ref() == Some(name)
+        }
+        (Segment::Token { text, .. }, Some(element)) => {
+            token_segment(element).as_ref() == Some(segment)
+                && element.text_content().as_ref() == Some(text)
+        }
+        (Segment::Newline, Some(element)) => element.tag_name() == "BR" && !is_filler(node),
+        _ => false,
+    }
+}
+
+/// Return whether the DOM node is a filler `<br>` element.
+pub(crate) fn is_filler(node: &Node) -> bool {
+    node.dyn_ref::<Element>().map_or(false, |element| {
+        element.tag_name() == "BR" && element.has_attribute(FILLER_ATTRIBUTE)
+    })
+}
+
+/// Create a filler `<br>` element.
+fn create_filler(dom: &web_sys::Document) -> Result<Node, ComposeAreaError> {
+    let br = dom.create_element("br").dom_err("create br element")?;
+    br.set_attribute(FILLER_ATTRIBUTE, "")
+        .dom_err("set filler attribute")?;
+    Ok(br.unchecked_into())
+}
+
+/// Create a DOM node for the specified segment.
+fn create_node(dom: &web_sys::Document, segment: &Segment) -> Result<Node, ComposeAreaError> {
+    Ok(match segment {
+        Segment::Text(text) => dom.create_text_node(text).unchecked_into(),
+        Segment::Image { src, alt, cls, id } => {
+            let img = create_image(dom, src, alt, cls)?;
+            if let Some(id) = id {
+                img.set_id(id);
+            }
+            img.unchecked_into()
+        }
+        Segment::Mention { id, name, token } => {
+            create_mention(dom, id, name, token)?.unchecked_into()
+        }
+        Segment::Token { kind, text, attrs } => {
+            create_token(dom, kind, text, attrs)?.unchecked_into()
+        }
+        Segment::Newline => dom
+            .create_element("br")
+            .dom_err("create br element")?
+            .unchecked_into(),
+    })
+}
+
+/// Create an `<img>` element.
+pub(crate) fn create_image(
+    dom: &web_sys::Document,
+    src: &str,
+    alt: &str,
+    cls: &str,
+) -> Result<Element, ComposeAreaError> {
+    let img = dom.create_element("img").dom_err("create img element")?;
+    img.set_attribute("src", src).dom_err("set src")?;
+    img.set_attribute("alt", alt).dom_err("set alt")?;
+    img.set_attribute("class", cls).dom_err("set class")?;
+    Ok(img)
+}
+
+/// Return the classes of an element that highlights markup.
+fn markup_classes(styles: Styles) -> String {
+    let mut classes = vec![MARKUP_CLASS];
+    for (style, class) in &[
+        (Styles::BOLD, "ca-bold"),
+        (Styles::ITALIC, "ca-italic"),
+        (Styles::STRIKETHROUGH, "ca-strikethrough"),
+        (Styles::MONOSPACE, "ca-monospace"),
+        (Styles::MARKER, "ca-marker"),
+    ] {
+        if styles.contains(*style) {
+            classes.push(class);
+        }
+    }
+    classes.join(" ")
+}
+
+/// Create a `<span>` element that highlights markup.
+fn create_markup(
+    dom: &web_sys::Document,
+    text: &str,
+    styles: Styles,
+) -> Result<Element, ComposeAreaError> {
+    let span = dom.create_element("span").dom_err("create span element")?;
+    span.set_attribute("class", &markup_classes(styles))
+        .dom_err("set class")?;
+    span.set_text_content(Some(text));
+    Ok(span)
+}
+
+/// Create a non-editable mention element that displays the name.
+pub(crate) fn create_mention(
+    dom: &web_sys::Document,
+    id: &str,
+    name: &str,
+    token: &str,
+) -> Result<Element, ComposeAreaError> {
+    let span = dom.create_element("span").dom_err("create span element")?;
+    span.set_attribute("class", MENTION_CLASS)
+        .dom_err("set class")?;
+    span.set_attribute("contenteditable", "false")
+        .dom_err("set contenteditable")?;
+    span.set_attribute(MENTION_ATTRIBUTE, id)
+        .dom_err("set mention id")?;
+    span.set_attribute(TOKEN_ATTRIBUTE, token)
+        .dom_err("set mention token")?;
+    span.set_text_content(Some(name));
+    Ok(span)
+}
+
+/// Create a non-editable token element that displays the text. The
+/// attributes must be allowed (see `sanitize::token_attributes`), classes are
+/// added to the `ca-token` class.
+pub(crate) fn create_token(
+    dom: &web_sys::Document,
+    kind: &str,
+    text: &str,
+    attrs: &[(String, String)],
+) -> Result<Element, ComposeAreaError> {
+    let span = dom.create_element("span").dom_err("create span element")?;
+    let cls = attrs.iter().find(|(name, _)| name == "class").map_or_else(
+        || TOKEN_CLASS.to_string(),
+        |(_, cls)| format!("{} {}", TOKEN_CLASS, cls),
+    );
+    span.set_attribute("class", &cls).dom_err("set class")?;
+    span.set_attribute("contenteditable", "false")
+        .dom_err("set contenteditable")?;
+    span.set_attribute(KIND_ATTRIBUTE, kind)
+        .dom_err("set token kind")?;
+    span.set_attribute(TOKEN_ATTRIBUTE, text)
+        .dom_err("set token text")?;
+    for (name, value) in attrs.iter().filter(|(name, _)| name != "class") {
+        span.set_attribute(name, value)
+            .dom_err("set token attribute")?;
+    }
+    span.set_text_content(Some(text));
+    Ok(span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    use crate::extract::extract_text;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn setup() -> (web_sys::Document, Element) {
+        let window = web_sys::window().expect("No global `window` exists");
+        let dom = window.document().expect("Should have a document on window");
+        let wrapper = dom
+            .create_element("div")
+            .expect("Could not create test wrapper");
+        (dom, wrapper)
+    }
+
+    #[wasm_bindgen_test]
+    fn render_segments() {
+        let (dom, wrapper) = setup();
+        let document = Document::from_segments(vec![
+            Segment::Text("hi ".into()),
+            Segment::image("img.jpg", "😀", "em"),
+            Segment::Newline,
+            Segment::Text("there".into()),
+        ]);
+        render(&dom, &wrapper, &document, false).unwrap();
+        assert_eq!(
+            wrapper.inner_html(),
+            r#"hi <img src="img.jpg" alt="😀" class="em"><br>there"#
+        );
+        assert_eq!(extract_text(&wrapper, true), document.to_text());
+    }
+
+    #[wasm_bindgen_test]
+    fn render_mention() {
+        let (dom, wrapper) = setup();
+        let document = Document::from_segments(vec![
+            Segment::Text("hi ".into()),
+            Segment::mention("ABCD1234", "Alice", "@[ABCD1234]"),
+        ]);
+        render(&dom, &wrapper, &document, false).unwrap();
+        assert_eq!(
+            wrapper.inner_html(),
+            "hi <span class=\"ca-mention\" contenteditable=\"false\" \
+             data-ca-mention=\"ABCD1234\" data-ca-token=\"@[ABCD1234]\">Alice</span>"
+        );
+        assert_eq!(extract_text(&wrapper, true), "hi @[ABCD1234]");
+
+        // The mention element is reused
+        let span = wrapper.last_child().unwrap();
+        render(&dom, &wrapper, &document, false).unwrap();
+        assert_eq!(wrapper.last_child().unwrap(), span);
+    }
+
+    #[wasm_bindgen_test]
+    fn render_token() {
+        let (dom, wrapper) = setup();
+        let attrs = vec![
+            ("title".to_string(), "Tag".to_string()),
+            ("class".to_string(), "tag".to_string()),
+        ];
+        let document = Document::from_segments(vec![Segment::token("hashtag", "#rust", attrs)]);
+        render(&dom, &wrapper, &document, false).unwrap();
+        assert_eq!(
+            wrapper.inner_html(),
+            "<span class=\"ca-token tag\" contenteditable=\"false\" data-ca-kind=\"hashtag\" \
+             data-ca-token=\"#rust\" title=\"Tag\">#rust</span>"
+        );
+        assert_eq!(
+            token_segment(&wrapper.first_element_child().unwrap()).as_ref(),
+            document.segments().first()
+        );
+
+        // The token element is reused
+        let span = wrapper.first_child().unwrap();
+        render(&dom, &wrapper, &document, false).unwrap();
+        assert_eq!(wrapper.first_child().unwrap(), span);
+    }
+
+    #[wasm_bindgen_test]
+    fn reuse_nodes() {
+        let (dom, wrapper) = setup();
+        let img = Segment::image_with_id("img.jpg", "😀", "em", Some("img-1"));
+        render(
+            &dom,
+            &wrapper,
+            &Document::from_segments(vec![Segment::Text("a".into()), img.clone()]),
+            false,
+        )
+        .unwrap();
+        let img_node = wrapper.child_nodes().item(1).unwrap();
+        assert_eq!(img_node.unchecked_ref::<Element>().id(), "img-1");
+
+        render(
+            &dom,
+            &wrapper,
+            &Document::from_segments(vec![Segment::Text("ab".into()), img.clone()]),
+            false,
+        )
+        .unwrap();
+        assert_eq!(wrapper.child_nodes().length(), 2);
+        assert_eq!(wrapper.child_nodes().item(1).unwrap(), img_node);
+
+        // Removed nodes before the image
+        render(&dom, &wrapper, &Document::from_segments(vec![img]), false).unwrap();
+        assert_eq!(wrapper.child_nodes().length(), 1);
+        assert_eq!(wrapper.first_child().unwrap(), img_node);
+
+        render(&dom, &wrapper, &Document::new(), false).unwrap();
+        assert_eq!(wrapper.child_nodes().length(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn trailing_newline() {
+        let (dom, wrapper) = setup();
+        let document = Document::from_text("a\n");
+        render(&dom, &wrapper, &document, false).unwrap();
+        assert_eq!(wrapper.inner_html(), r#"a<br><br data-ca-filler="">"#);
+        assert_eq!(extract_text(&wrapper, true), "a\n");
+
+        // Filler is not reused as a regular newline
+        render(&dom, &wrapper, &Document::from_text("a\n\n"), false).unwrap();
+        assert_eq!(wrapper.inner_html(), r#"a<br><br><br data-ca-filler="">"#);
+        render(&dom, &wrapper, &Document::from_text("a"), false).unwrap();
+        assert_eq!(wrapper.inner_html(), "a");
+    }
+
+    #[wasm_bindgen_test]
+    fn fragment_filler() {
+        let (dom, wrapper) = setup();
+        render(&dom, &wrapper, &Document::from_text("a"), false).unwrap();
+        let fragment = render_fragment(&dom, &Document::from_text("b\n")).unwrap();
+        wrapper.append_child(&fragment).unwrap();
+        update_filler(&dom, &wrapper).unwrap();
+        assert_eq!(wrapper.inner_html(), r#"ab<br><br data-ca-filler="">"#);
+
+        // Misplaced fillers are removed
+        let text = dom.create_text_node("c");
+        wrapper.append_child(&text).unwrap();
+        update_filler(&dom, &wrapper).unwrap();
+        assert_eq!(wrapper.inner_html(), "ab<br>c");
+    }
+
+    #[wasm_bindgen_test]
+    fn highlight_markup() {
+        let (dom, wrapper) = setup();
+        let document = Document::from_segments(vec![
+            Segment::Text("a *b ".into()),
+            Segment::image("img.jpg", "😀", "em"),
+            Segment::Text("* c".into()),
+        ]);
+        render(&dom, &wrapper, &document, true).unwrap();
+        assert_eq!(
+            wrapper.inner_html(),
+            "a <span class=\"ca-markup ca-bold ca-marker\">*</span>\
+             <span class=\"ca-markup ca-bold\">b </span>\
+             <img src=\"img.jpg\" alt=\"😀\" class=\"em\">\
+             <span class=\"ca-markup ca-bold ca-marker\">*</span> c"
+        );
+        assert_eq!(extract_text(&wrapper, true), document.to_text());
+
+        // Unchanged nodes are reused
+        let img = wrapper.child_nodes().item(3).unwrap();
+        render(&dom, &wrapper, &document, true).unwrap();
+        assert_eq!(wrapper.child_nodes().item(3).unwrap(), img);
+
+        render(&dom, &wrapper, &document, false).unwrap();
+        assert_eq!(
+            wrapper.inner_html(),
+            r#"a *b <img src="img.jpg" alt="😀" class="em">* c"#
+        );
+    }
+}
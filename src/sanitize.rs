@@ -0,0 +1,394 @@
+//! Sanitizing of pasted HTML.
+//!
+//! Pasted HTML is parsed into an inert document (scripts are not executed and
+//! images are not loaded) and converted to a document model using a strict
+//! allowlist: Only text, line breaks and images with a safe source and an
+//! allowed class are kept. Block elements are converted to line breaks and
+//! whitespace is collapsed like the browser would render it. All other
+//! markup is dropped.
+use wasm_bindgen::JsCast;
+use web_sys::{DomParser, Element, Node, SupportedType};
+
+use crate::error::{ComposeAreaError, DomResultExt};
+use crate::model::{Document, Segment};
+
+/// Elements whose contents are dropped entirely.
+const SKIPPED_TAGS: &[&str] = &[
+    "embed", "head", "iframe", "math", "meta", "noscript", "object", "script", "select", "style",
+    "svg", "template", "textarea", "title",
+];
+
+/// Elements that start and end a line.
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "tr",
+    "ul",
+];
+
+/// URL schemes that are allowed as image source.
+const SAFE_SCHEMES: &[&str] = &["https", "http", "blob"];
+
+/// Media types that are allowed for `data:` URLs.
+const SAFE_DATA_TYPES: &[&str] = &["image/png", "image/gif", "image/jpeg", "image/webp"];
+
+/// Attributes (apart from `data-*` and `aria-*` attributes) that are allowed
+/// on token elements.
+const TOKEN_ATTRIBUTES: &[&str] = &["class", "dir", "lang", "title"];
+
+/// Parse an HTML string into an inert document and return its body element.
+pub(crate) fn parse_html(html: &str) -> Result<Element, ComposeAreaError> {
+    let parser = DomParser::new().dom_err("create DOM parser")?;
+    let document = parser
+        .parse_from_string(html, SupportedType::TextHtml)
+        .dom_err("parse HTML")?;
+    document
+        .body()
+        .map(Into::into)
+        .ok_or_else(|| ComposeAreaError::InvalidState("Parsed HTML has no body".into()))
+}
+
+/// Convert the contents of `root` to a document model.
+///
+/// Images are only kept if their source is safe (see `is_safe_src`) and they
+/// have at least one of the `allowed_classes`. Other classes are removed.
+/// Images that are not allowed are replaced by their alt text.
+pub(crate) fn sanitize(root: &Element, allowed_classes: &[String]) -> Document {
+    let mut sanitizer = Sanitizer {
+        allowed_classes,
+        document: Document::new(),
+        line_break: false,
+        space: false,
+        preformatted: 0,
+    };
+    sanitizer.visit_child_nodes(root);
+    sanitizer.document
+}
+
+/// Replace images with an unsafe source (see `is_safe_src`) by their alt
+/// text and remove disallowed token attributes (see `token_attributes`).
+/// This is used for documents that were deserialized from untrusted data.
+pub(crate) fn sanitize_document(document: &Document) -> Document {
+    Document::from_segments(
+        document
+            .segments()
+            .iter()
+            .map(|segment| match segment {
+                Segment::Image { src, alt, .. } if !is_safe_src(&clean_src(src)) => {
+                    Segment::Text(alt.clone())
+                }
+                Segment::Token { kind, text, attrs } => {
+                    Segment::token(kind, text, token_attributes(attrs.clone()))
+                }
+                other => other.clone(),
+            })
+            .collect(),
+    )
+}
+
+/// Return the attributes that may be set on a token element. Names are
+/// converted to lowercase.
+///
+/// Only `class`, `dir`, `lang`, `title`, `data-*` and `aria-*` attributes
+/// are allowed, with the exception of the `data-ca-*` attributes used by the
+/// compose area itself. In particular, event handlers and styles are
+/// removed.
+pub(crate) fn token_attributes(attrs: Vec<(String, String)>) -> Vec<(String, String)> {
+    attrs
+        .into_iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value))
+        .filter(|(name, _)| {
+            name.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                && (TOKEN_ATTRIBUTES.contains(&&**name)
+                    || ((name.starts_with("data-") || name.starts_with("aria-"))
+                        && !name.starts_with("data-ca-")
+                        && name.len() > 5))
+        })
+        .collect()
+}
+
+/// Convert CRLF and CR line endings to LF.
+pub(crate) fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Return whether `src` may be used as the source of a pasted image.
+///
+/// Relative URLs, `http(s)` and `blob` URLs and `data` URLs of raster
+/// images are allowed. The check expects a cleaned URL (see `clean_src`).
+fn is_safe_src(src: &str) -> bool {
+    if src.is_empty() {
+        return false;
+    }
+    let scheme_end = match src.find(|c| c == ':' || c == '/' || c == '?' || c == '#') {
+        Some(index) if src[index..].starts_with(':') => index,
+        // No scheme, this is a relative URL
+        _ => return true,
+    };
+    let scheme = src[..scheme_end].to_ascii_lowercase();
+    if scheme == "data" {
+        let media_type = src[scheme_end + 1..]
+            .split(|c| c == ';' || c == ',')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        return SAFE_DATA_TYPES.contains(&&*media_type);
+    }
+    SAFE_SCHEMES.contains(&&*scheme)
+}
+
+/// Remove the characters that browsers ignore when parsing URLs: Leading and
+/// trailing C0 control characters and spaces, and ASCII tabs and newlines.
+fn clean_src(src: &str) -> String {
+    src.trim_matches(|c| c <= ' ')
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect()
+}
+
+/// Return whether `c` is HTML whitespace (which is collapsed).
+fn is_html_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\u{c}')
+}
+
+struct Sanitizer<'a> {
+    allowed_classes: &'a [String],
+    document: Document,
+    /// Whether further contents must start a new line (at the boundary of a
+    /// block element).
+    line_break: bool,
+    /// Whether collapsed whitespace must be added before further contents.
+    space: bool,
+    /// The number of `pre` elements that are currently open.
+    preformatted: u32,
+}
+
+impl<'a> Sanitizer<'a> {
+    /// Return whether the last line of the document is empty.
+    fn at_line_start(&self) -> bool {
+        matches!(
+            self.document.segments().last(),
+            None | Some(Segment::Newline)
+        )
+    }
+
+    /// Append a segment, preceded by a pending line break or space.
+    fn push(&mut self, segment: Segment) {
+        if self.line_break {
+            // Block boundaries only start a new line if the current line is
+            // not empty
+            if !self.at_line_start() {
+                self.document.push(Segment::Newline);
+            }
+        } else if self.space && !self.at_line_start() && segment != Segment::Newline {
+            self.document.push(Segment::Text(" ".into()));
+        }
+        self.line_break = false;
+        self.space = false;
+        self.document.push(segment);
+    }
+
+    /// Start a new line before further contents (at the boundary of a block
+    /// element).
+    fn break_line(&mut self) {
+        self.line_break = true;
+        self.space = false;
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if self.preformatted > 0 {
+            self.push(Segment::Text(normalize_newlines(text)));
+            return;
+        }
+        if text.starts_with(is_html_space) {
+            self.space = true;
+        }
+        let mut words = text
+            .split(is_html_space)
+            .filter(|word| !word.is_empty())
+            .peekable();
+        while let Some(word) = words.next() {
+            self.push(Segment::Text(word.to_string()));
+            self.space = words.peek().is_some() || text.ends_with(is_html_space);
+        }
+    }
+
+    fn push_image(&mut self, element: &Element) {
+        let alt = element.get_attribute("alt").unwrap_or_default();
+        let src = clean_src(&element.get_attribute("src").unwrap_or_default());
+        let cls = element.get_attribute("class").unwrap_or_default();
+        let classes: Vec<&str> = cls
+            .split_whitespace()
+            .filter(|cls| self.allowed_classes.iter().any(|allowed| allowed == cls))
+            .collect();
+        if !classes.is_empty() && is_safe_src(&src) {
+            self.push(Segment::image(&src, &alt, &classes.join(" ")));
+        } else if !alt.is_empty() {
+            self.push(Segment::Text(alt));
+        }
+    }
+
+    fn visit_child_nodes(&mut self, parent: &Element) {
+        let children = parent.child_nodes();
+        for i in 0..children.length() {
+            let node = match children.item(i) {
+                Some(node) => node,
+                None => return,
+            };
+            match node.node_type() {
+                Node::TEXT_NODE => self.push_text(&node.node_value().unwrap_or_default()),
+                Node::ELEMENT_NODE => self.visit_element(node.unchecked_ref()),
+                // Comments, processing instructions, ...
+                _ => {}
+            }
+        }
+    }
+
+    fn visit_element(&mut self, element: &Element) {
+        let tag = element.tag_name().to_lowercase();
+        match &*tag {
+            tag if SKIPPED_TAGS.contains(&tag) => {}
+            "br" => self.push(Segment::Newline),
+            "img" => self.push_image(element),
+            "td" | "th" => {
+                self.visit_child_nodes(element);
+                self.space = true;
+            }
+            tag if BLOCK_TAGS.contains(&tag) => {
+                let preformatted = tag == "pre";
+                self.break_line();
+                if preformatted {
+                    self.preformatted += 1;
+                }
+                self.visit_child_nodes(element);
+                if preformatted {
+                    self.preformatted -= 1;
+                }
+                self.break_line();
+            }
+            _ => self.visit_child_nodes(element),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn sanitize_html(html: &str) -> Document {
+        sanitize(&parse_html(html).unwrap(), &["emoji".to_string()])
+    }
+
+    #[wasm_bindgen_test]
+    fn safe_src() {
+        assert!(is_safe_src("emoji/1f600.png"));
+        assert!(is_safe_src("/emoji/1f600.png"));
+        assert!(is_safe_src("https://example.com/a.png"));
+        assert!(is_safe_src("HTTP://example.com/a.png"));
+        assert!(is_safe_src("data:image/png;base64,AAAA"));
+        assert!(!is_safe_src(""));
+        assert!(!is_safe_src("javascript:alert(1)"));
+        assert!(!is_safe_src("data:image/svg+xml;base64,AAAA"));
+        assert!(!is_safe_src("data:text/html,<b>"));
+        assert!(!is_safe_src(&clean_src(" java\tscript:alert(1)")));
+    }
+
+    #[wasm_bindgen_test]
+    fn token_attribute_names() {
+        let attrs = |names: &[&str]| -> Vec<String> {
+            token_attributes(
+                names
+                    .iter()
+                    .map(|name| ((*name).to_string(), String::new()))
+                    .collect(),
+            )
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+        };
+        assert_eq!(
+            attrs(&["Class", "title", "data-id", "aria-label"]),
+            vec!["class", "title", "data-id", "aria-label"]
+        );
+        assert!(attrs(&[
+            "onclick",
+            "style",
+            "data-",
+            "data-ca-token",
+            "data-a b",
+            "src"
+        ])
+        .is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn text_and_blocks() {
+        let document = sanitize_html(
+            "<html><body>\n<!--StartFragment--><p>Hello  <b>World</b></p>\n\
+             <p> Second<script>alert(1)</script></p><ul><li>a</li><li>b</li></ul>\
+             <!--EndFragment-->\n</body></html>",
+        );
+        assert_eq!(document.to_text(), "Hello World\nSecond\na\nb");
+    }
+
+    #[wasm_bindgen_test]
+    fn line_breaks() {
+        let document = sanitize_html("a<br><br>b<div>c</div><div><br></div>d");
+        assert_eq!(document.to_text(), "a\n\nb\nc\n\nd");
+    }
+
+    #[wasm_bindgen_test]
+    fn preformatted() {
+        let document = sanitize_html("<pre>a  b\r\n c</pre>d");
+        assert_eq!(document.to_text(), "a  b\n c\nd");
+    }
+
+    #[wasm_bindgen_test]
+    fn images() {
+        let document = sanitize_html(
+            r#"a<img src="e.png" alt="😀" class="big emoji" id="x" onerror="alert(1)">
+            <img src="javascript:alert(1)" alt="😎" class="emoji">
+            <img src="photo.jpg" alt="photo"><img src="spacer.gif">"#,
+        );
+        assert_eq!(
+            document.segments(),
+            &[
+                Segment::Text("a".into()),
+                Segment::image("e.png", "😀", "emoji"),
+                Segment::Text(" 😎 photo".into()),
+            ]
+        );
+    }
+}
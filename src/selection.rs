@@ -1,18 +1,23 @@
 /// Everything related to the caret position and DOM selection ranges.
-use wasm_bindgen::JsCast;
-use web_sys::{Node, Range, Selection, Text};
+use web_sys::{Node, Range, Selection};
+
+use crate::error::{ComposeAreaError, DomResultExt};
+use crate::extract::Extraction;
+use crate::utils::child_index;
+
+type Result<T> = std::result::Result<T, ComposeAreaError>;
 
 /// A position relative to a node.
 #[derive(Debug)]
 pub enum Position<'a> {
     /// Caret position is before the selected node.
+    #[allow(dead_code)]
     Before(&'a Node),
 
     /// Caret position is after the selected node.
     After(&'a Node),
 
     /// Caret position is at the specified offset from the start of the node.
-    #[allow(dead_code)]
     Offset(&'a Node, u32),
 }
 
@@ -21,71 +26,118 @@ pub enum Position<'a> {
 /// If the `end` parameter is `None`, then the selection range is collapsed.
 ///
 /// Return a reference to the created / updated range.
-pub fn set_selection_range(start: &Position, end: Option<&Position>) -> Option<Range> {
-    let window = web_sys::window().expect("no global `window` exists");
-    let document = window.document().expect("should have a document on window");
+pub fn set_selection_range(start: &Position, end: Option<&Position>) -> Result<Range> {
+    let window = web_sys::window().ok_or(ComposeAreaError::NoDocument)?;
+    let document = window.document().ok_or(ComposeAreaError::NoDocument)?;
 
     // Get selection
-    let selection = match window
+    let selection = window
         .get_selection()
-        .expect("Could not get selection from window")
-    {
-        Some(sel) => sel,
-        None => {
-            error!("Could not get window selection");
-            return None;
-        }
-    };
+        .dom_err("get selection from window")?
+        .ok_or(ComposeAreaError::NoSelection)?;
 
     // Get the current selection range. Create a new range if necessary.
     let range = if selection.range_count() == 0 {
-        document.create_range().expect("Could not create range")
+        document.create_range().dom_err("create range")?
     } else {
-        selection
-            .get_range_at(0)
-            .expect("Could not get range at index 0")
+        selection.get_range_at(0).dom_err("get range at index 0")?
     };
 
+    set_range_positions(&range, start, end)?;
+    activate_selection_range(&selection, &range)?;
+    Ok(range)
+}
+
+/// Update the boundary points of a range to match the specified `Position`.
+///
+/// If the `end` parameter is `None`, then the range is collapsed.
+pub fn set_range_positions(range: &Range, start: &Position, end: Option<&Position>) -> Result<()> {
     // Set range start
     match start {
-        Position::After(node) => {
-            range
-                .set_start_after(node)
-                .expect("Could not set_start_after");
-        }
-        Position::Before(node) => {
-            range
-                .set_start_before(node)
-                .expect("Could not set_start_before");
-        }
+        Position::After(node) => range.set_start_after(node).dom_err("set range start")?,
+        Position::Before(node) => range.set_start_before(node).dom_err("set range start")?,
         Position::Offset(node, offset) => {
-            range.set_start(node, *offset).expect("Could not set_start");
+            range.set_start(node, *offset).dom_err("set range start")?
         }
     }
 
     // Set range end
     match end {
-        Some(Position::After(node)) => {
-            range.set_end_after(node).expect("Could not set_end_after");
-        }
-        Some(Position::Before(node)) => {
-            range
-                .set_end_before(node)
-                .expect("Could not set_end_before");
-        }
+        Some(Position::After(node)) => range.set_end_after(node).dom_err("set range end")?,
+        Some(Position::Before(node)) => range.set_end_before(node).dom_err("set range end")?,
         Some(Position::Offset(node, offset)) => {
-            range.set_end(node, *offset).expect("Could not set_start");
+            range.set_end(node, *offset).dom_err("set range end")?
         }
         None => range.collapse_with_to_start(true),
     }
+    Ok(())
+}
+
+/// Convert a `Position` to a logical offset into the document model (see
+/// `model`) of the root element that was used to create the extraction.
+///
+/// Return `None` if the position is not inside that root element.
+pub(crate) fn position_to_offset(extraction: &Extraction, position: &Position) -> Option<u32> {
+    match position {
+        Position::Offset(node, offset) => extraction.offset_of(node, *offset),
+        Position::Before(node) | Position::After(node) => {
+            let parent = node.parent_node()?;
+            let index = child_index(&parent, node)?;
+            match position {
+                Position::After(_) => extraction.offset_of(&parent, index + 1),
+                _ => extraction.offset_of(&parent, index),
+            }
+        }
+    }
+}
+
+/// Convert the boundary points of a range to logical offsets (see
+/// `position_to_offset`).
+pub(crate) fn range_to_offsets(extraction: &Extraction, range: &Range) -> Option<(u32, u32)> {
+    let start_container = range.start_container().ok()?;
+    let end_container = range.end_container().ok()?;
+    let start = position_to_offset(
+        extraction,
+        &Position::Offset(&start_container, range.start_offset().ok()?),
+    )?;
+    let end = position_to_offset(
+        extraction,
+        &Position::Offset(&end_container, range.end_offset().ok()?),
+    )?;
+    Some((start, end))
+}
 
-    activate_selection_range(&selection, &range);
-    Some(range)
+/// Convert a logical offset into the document model (see `model`) of the root
+/// element that was used to create the extraction to a DOM node and offset,
+/// suitable for `Position::Offset`.
+pub(crate) fn offset_to_position(extraction: &Extraction, offset: u32) -> Option<(Node, u32)> {
+    extraction.position_at(offset)
+}
+
+/// Find the last range of the selection that is within `container`.
+///
+/// Return the range and `true` if such a range exists. Otherwise, return the
+/// last range of the selection (if any) and `false`.
+pub(crate) fn find_range(selection: &Selection, container: &Node) -> Result<Option<(Range, bool)>> {
+    let mut candidate: Option<Range> = None;
+    for i in 0..selection.range_count() {
+        let range = selection
+            .get_range_at(i)
+            .dom_err("get range from selection")?;
+        let ancestor = range
+            .common_ancestor_container()
+            .dom_err("get common ancestor container for range")?;
+        if container.contains(Some(&ancestor)) {
+            return Ok(Some((range, true)));
+        }
+        candidate = Some(range);
+    }
+    Ok(candidate.map(|range| (range, false)))
 }
 
 /// Activate the specified selection range in the DOM. Remove all previous
 /// ranges.
-pub fn activate_selection_range(selection: &Selection, range: &Range) {
+pub fn activate_selection_range(selection: &Selection, range: &Range) -> Result<()> {
     // Note: In theory we don't need to re-add the range to the document if
     //       it's already there. Unfortunately, Safari is not spec-compliant
     //       and returns a copy of the range instead of a reference when using
@@ -93,8 +145,8 @@ pub fn activate_selection_range(selection: &Selection, range: &Range) {
     //       ranges and (re-)add our range to the DOM.
     //
     //       See https://bugs.webkit.org/show_bug.cgi?id=145212
-    selection.remove_all_ranges().unwrap();
-    selection.add_range(&range).expect("Could not add range");
+    selection.remove_all_ranges().dom_err("remove ranges")?;
+    selection.add_range(range).dom_err("add range")
 }
 
 #[cfg(test)]
@@ -104,66 +156,6 @@ pub fn unset_selection_range() {
     sel.remove_all_ranges().unwrap();
 }
 
-/// This function will check whether the current selection range is adjacent to
-/// a text node. It will then modify the range so that the `startContainer` is
-/// that text node.
-///
-/// If the selection is not directly preceding a text node or within a text
-/// node with offset 0, `false` will be returned and the range will not be
-/// modified.
-///
-/// Non-collapsed ranges will be immediately rejected.
-///
-/// Examples of ranges (denoted with the `|`) that are successfully modified:
-///
-/// - `"|abc"` -> `"|abc"`
-/// - `"ab|c"` -> `"ab|c"`
-/// - `"abc|"` -> `"abc|"`
-/// - `"abc"|` -> `"abc|"`
-/// - `<span>"abc"|<span>` -> `<span>"abc|"</span>`
-///
-/// Examples of ranges where `false` will be returned:
-///
-/// - `<span>|"abc"</span>`
-/// - `<span>"abc"</span>|`
-/// - `<span>"abc"<img>|</span>
-/// - `<span>"abc"<span></span>|</span>
-///
-pub fn glue_range_to_text(range: &mut Range) -> bool {
-    // Reject non-collapsed ranges
-    if !range.collapsed() {
-        return false;
-    }
-
-    // Determine node type of container
-    let container = range
-        .start_container()
-        .expect("Could not get start container");
-    match container.node_type() {
-        Node::TEXT_NODE => true,
-        Node::ELEMENT_NODE => {
-            let offset: u32 = range.start_offset().expect("Could not get start offset");
-            if offset == 0 {
-                false
-            } else if let Some(prev_sibling) = container.child_nodes().get(offset - 1) {
-                if prev_sibling.node_type() == Node::TEXT_NODE {
-                    let length = prev_sibling.dyn_ref::<Text>().unwrap().length();
-                    range
-                        .set_start(&prev_sibling, length)
-                        .expect("Could not set_start");
-                    range.collapse_with_to_start(true);
-                    true
-                } else {
-                    false
-                }
-            } else {
-                true
-            }
-        }
-        _ => false,
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -177,112 +169,54 @@ mod tests {
         window.document().expect("Should have a document on window")
     }
 
-    mod glue_range_to_text {
+    mod offsets {
         use super::*;
 
-        /// "hell|o"
-        #[wasm_bindgen_test]
-        fn in_text_node() {
-            let node = document().create_text_node("hello");
-            let mut range = document().create_range().unwrap();
-            range.set_start(&node, 4).unwrap();
-            range.collapse_with_to_start(true);
-
-            assert!(glue_range_to_text(&mut range));
-            assert_eq!(range.start_offset().unwrap(), 4);
-        }
-
-        /// "|hello"
-        #[wasm_bindgen_test]
-        fn at_start_of_text_node() {
-            let node = document().create_text_node("hello");
-            let mut range = document().create_range().unwrap();
-            range.set_start(&node, 0).unwrap();
-            range.collapse_with_to_start(true);
-
-            assert!(glue_range_to_text(&mut range));
-            assert_eq!(range.start_offset().unwrap(), 0);
-        }
-
-        /// <div>"hello"|</div>
-        #[wasm_bindgen_test]
-        fn after_text_node() {
-            let div = document().create_element("div").unwrap();
-            let node = document().create_text_node("hello");
-            div.append_child(&node).unwrap();
-            let mut range = document().create_range().unwrap();
-            range.set_start_after(&node).unwrap();
-            range.collapse_with_to_start(true);
-            assert_eq!(range.start_offset().unwrap(), 1);
-            assert_eq!(range.end_offset().unwrap(), 1);
-
-            assert!(glue_range_to_text(&mut range));
-            assert_eq!(range.start_offset().unwrap(), 5);
-            assert_eq!(range.end_offset().unwrap(), 5);
-        }
-
-        #[wasm_bindgen_test]
-        fn not_collapsed() {
-            let node = document().create_text_node("hello");
-            let mut range = document().create_range().unwrap();
-            range.set_start(&node, 2).unwrap();
-            range.set_end(&node, 3).unwrap();
-
-            // Fails
-            assert!(!glue_range_to_text(&mut range));
+        use web_sys::Element;
 
-            // Unmodified
-            assert_eq!(range.start_offset().unwrap(), 2);
-            assert_eq!(range.end_offset().unwrap(), 3);
-        }
-
-        /// <div>|"hello"</div>
-        #[wasm_bindgen_test]
-        fn before_text() {
+        /// <div>"ab"<img alt="😀"><br>"c"</div>
+        fn wrapper() -> Element {
             let div = document().create_element("div").unwrap();
-            let text = document().create_text_node("hello");
-            div.append_child(&text).unwrap();
-
-            let mut range = document().create_range().unwrap();
-            range.set_start_before(&text).unwrap();
-            range.collapse_with_to_start(true);
-
-            // Fails
-            assert!(!glue_range_to_text(&mut range));
+            div.set_inner_html(r#"ab<img src="img.jpg" alt="😀"><br>c"#);
+            div
         }
 
-        /// <div>"hello"<img/>|</div>
         #[wasm_bindgen_test]
-        fn after_inner_element() {
-            let div = document().create_element("div").unwrap();
-            let img = document().create_element("img").unwrap();
-            let text = document().create_text_node("hello");
-            div.append_child(&text).unwrap();
-            div.append_child(&img).unwrap();
-
-            let mut range = document().create_range().unwrap();
-            range.set_start_after(&img).unwrap();
-            range.collapse_with_to_start(true);
-
-            // Fails
-            assert!(!glue_range_to_text(&mut range));
+        fn to_offset() {
+            let div = wrapper();
+            let extraction = Extraction::new(&div);
+            let text = div.first_child().unwrap();
+            let img = text.next_sibling().unwrap();
+            let last = div.last_child().unwrap();
+            assert_eq!(
+                position_to_offset(&extraction, &Position::Offset(&text, 1)),
+                Some(1)
+            );
+            assert_eq!(
+                position_to_offset(&extraction, &Position::Before(&img)),
+                Some(2)
+            );
+            assert_eq!(
+                position_to_offset(&extraction, &Position::After(&img)),
+                Some(4)
+            );
+            assert_eq!(
+                position_to_offset(&extraction, &Position::Offset(&last, 1)),
+                Some(6)
+            );
         }
 
-        /// <div><span>"hello"</span>|</div>
         #[wasm_bindgen_test]
-        fn after_outer_element() {
-            let div = document().create_element("div").unwrap();
-            let span = document().create_element("span").unwrap();
-            let text = document().create_text_node("hello");
-            span.append_child(&text).unwrap();
-            div.append_child(&span).unwrap();
-
-            let mut range = document().create_range().unwrap();
-            range.set_start_after(&span).unwrap();
-            range.collapse_with_to_start(true);
-
-            // Fails
-            assert!(!glue_range_to_text(&mut range));
+        fn roundtrip() {
+            let div = wrapper();
+            let extraction = Extraction::new(&div);
+            for offset in &[0, 1, 2, 4, 5, 6] {
+                let (node, node_offset) = offset_to_position(&extraction, *offset).unwrap();
+                assert_eq!(
+                    position_to_offset(&extraction, &Position::Offset(&node, node_offset)),
+                    Some(*offset)
+                );
+            }
         }
     }
 }
@@ -0,0 +1,325 @@
+//! Serialization of the compose area state (contents and selection).
+//!
+//! The state is serialized to a compact, versioned string format:
+//!
+//! ```text
+//! <version>|<selection>|<segments>
+//! ```
+//!
+//! The selection is either empty or `<start>,<end>` (logical offsets). The
+//! segments are concatenated without separators:
+//!
+//! - Text: `t<len>:<text>`
+//! - Image: `i<len>:<src><len>:<alt><len>:<cls>`
+//! - Mention: `m<len>:<id><len>:<name><len>:<token>`
+//! - Token: `k<len>:<kind><len>:<text><len>:<count>`, followed by `<count>`
+//!   attributes `<len>:<name><len>:<value>`
+//! - Newline: `n`
+//!
+//! All lengths are byte lengths of the UTF-8 encoded strings, so no escaping
+//! is required.
+//!
+//! When the format changes, the version is incremented and a migration from
+//! the previous version is added to `MIGRATIONS`. Serialized states of older
+//! versions are migrated step by step before parsing.
+use std::fmt;
+
+use crate::history::Snapshot;
+use crate::model::{Document, Segment};
+
+/// The current version of the state format.
+pub const STATE_VERSION: u32 = 1;
+
+/// The MIME type of contents copied from a compose area. The clipboard data
+/// is a serialized state without selection.
+pub const CLIPBOARD_TYPE: &str = "application/x-compose-area";
+
+/// A migration converts the payload (everything after the version) of a
+/// serialized state from one version to the next.
+type Migration = fn(&str) -> Result<String, StateError>;
+
+/// Migrations between format versions. The migration at index `i` converts
+/// version `i + 1` to version `i + 2`.
+const MIGRATIONS: &[Migration] = &[];
+
+/// An error that occurred while parsing a serialized state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The state was created by a newer (or unknown) version of the format.
+    UnsupportedVersion(u32),
+
+    /// The state is malformed.
+    InvalidFormat(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported state version: {}", version)
+            }
+            StateError::InvalidFormat(reason) => write!(f, "Invalid state format: {}", reason),
+        }
+    }
+}
+
+/// Serialize a snapshot using the current format version.
+pub fn serialize(snapshot: &Snapshot) -> String {
+    let mut out = format!("{}|", STATE_VERSION);
+    if let Some((start, end)) = snapshot.selection {
+        out.push_str(&format!("{},{}", start, end));
+    }
+    out.push('|');
+    for segment in snapshot.document.segments() {
+        match segment {
+            Segment::Text(text) => {
+                out.push('t');
+                push_string(&mut out, text);
+            }
+            // Image IDs are not serialized, they are only unique per page
+            Segment::Image { src, alt, cls, .. } => {
+                out.push('i');
+                push_string(&mut out, src);
+                push_string(&mut out, alt);
+                push_string(&mut out, cls);
+            }
+            Segment::Mention { id, name, token } => {
+                out.push('m');
+                push_string(&mut out, id);
+                push_string(&mut out, name);
+                push_string(&mut out, token);
+            }
+            Segment::Token { kind, text, attrs } => {
+                out.push('k');
+                push_string(&mut out, kind);
+                push_string(&mut out, text);
+                push_string(&mut out, &attrs.len().to_string());
+                for (name, value) in attrs {
+                    push_string(&mut out, name);
+                    push_string(&mut out, value);
+                }
+            }
+            Segment::Newline => out.push('n'),
+        }
+    }
+    out
+}
+
+/// Append a length prefixed string.
+fn push_string(out: &mut String, value: &str) {
+    out.push_str(&format!("{}:{}", value.len(), value));
+}
+
+/// Parse a serialized snapshot. States of older format versions are migrated.
+pub fn deserialize(state: &str) -> Result<Snapshot, StateError> {
+    let separator = state
+        .find('|')
+        .ok_or(StateError::InvalidFormat("Missing version"))?;
+    let version: u32 = state[..separator]
+        .parse()
+        .ok()
+        .ok_or(StateError::InvalidFormat("Invalid version"))?;
+    if version == 0 || version > STATE_VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    let mut payload = state[separator + 1..].to_string();
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        payload = migration(&payload)?;
+    }
+    parse_payload(&payload)
+}
+
+/// Parse the payload of a state in the current format version.
+fn parse_payload(payload: &str) -> Result<Snapshot, StateError> {
+    let separator = payload
+        .find('|')
+        .ok_or(StateError::InvalidFormat("Missing selection"))?;
+    let selection = match &payload[..separator] {
+        "" => None,
+        selection => {
+            let mut parts = selection.splitn(2, ',').map(str::parse::<u32>);
+            match (parts.next(), parts.next()) {
+                (Some(Ok(start)), Some(Ok(end))) => Some((start, end)),
+                _ => return Err(StateError::InvalidFormat("Invalid selection")),
+            }
+        }
+    };
+
+    let mut segments = vec![];
+    let mut rest = &payload[separator + 1..];
+    while !rest.is_empty() {
+        let mut chars = rest.chars();
+        let kind = chars.next();
+        rest = chars.as_str();
+        match kind {
+            Some('t') => {
+                let (text, remaining) = take_string(rest)?;
+                rest = remaining;
+                segments.push(Segment::Text(text.to_string()));
+            }
+            Some('i') => {
+                let (src, remaining) = take_string(rest)?;
+                let (alt, remaining) = take_string(remaining)?;
+                let (cls, remaining) = take_string(remaining)?;
+                rest = remaining;
+                segments.push(Segment::image(src, alt, cls));
+            }
+            Some('m') => {
+                let (id, remaining) = take_string(rest)?;
+                let (name, remaining) = take_string(remaining)?;
+                let (token, remaining) = take_string(remaining)?;
+                if token.is_empty() {
+                    return Err(StateError::InvalidFormat("Empty mention token"));
+                }
+                rest = remaining;
+                segments.push(Segment::mention(id, name, token));
+            }
+            Some('k') => {
+                let (kind, remaining) = take_string(rest)?;
+                let (text, remaining) = take_string(remaining)?;
+                let (count, mut remaining) = take_string(remaining)?;
+                if text.is_empty() {
+                    return Err(StateError::InvalidFormat("Empty token text"));
+                }
+                let count: usize = count
+                    .parse()
+                    .ok()
+                    .ok_or(StateError::InvalidFormat("Invalid attribute count"))?;
+                let mut attrs = vec![];
+                for _ in 0..count {
+                    let (name, rest) = take_string(remaining)?;
+                    let (value, rest) = take_string(rest)?;
+                    attrs.push((name.to_string(), value.to_string()));
+                    remaining = rest;
+                }
+                rest = remaining;
+                segments.push(Segment::token(kind, text, attrs));
+            }
+            Some('n') => segments.push(Segment::Newline),
+            _ => return Err(StateError::InvalidFormat("Invalid segment type")),
+        }
+    }
+
+    let document = Document::from_segments(segments);
+    let len = document.len();
+    if selection.map_or(false, |(start, end)| start > len || end > len) {
+        return Err(StateError::InvalidFormat("Selection out of bounds"));
+    }
+    Ok(Snapshot {
+        document,
+        selection,
+    })
+}
+
+/// Split off a length prefixed string. Return the string and the rest.
+fn take_string(input: &str) -> Result<(&str, &str), StateError> {
+    let separator = input
+        .find(':')
+        .ok_or(StateError::InvalidFormat("Missing length"))?;
+    let len: usize = input[..separator]
+        .parse()
+        .ok()
+        .ok_or(StateError::InvalidFormat("Invalid length"))?;
+    let start = separator + 1;
+    let end = start
+        .checked_add(len)
+        .ok_or(StateError::InvalidFormat("Invalid length"))?;
+    let value = input
+        .get(start..end)
+        .ok_or(StateError::InvalidFormat("Invalid length"))?;
+    Ok((value, &input[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            document: Document::from_segments(vec![
+                Segment::Text("hi |1:x ".into()),
+                Segment::image("img.jpg", "😀", "em"),
+                Segment::Newline,
+                Segment::Text("there".into()),
+            ]),
+            selection: Some((3, 5)),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let serialized = serialize(&snapshot());
+        assert_eq!(serialized, "1|3,5|t8:hi |1:x i7:img.jpg4:😀2:emnt5:there");
+        assert_eq!(deserialize(&serialized), Ok(snapshot()));
+
+        let empty = Snapshot {
+            document: Document::new(),
+            selection: None,
+        };
+        assert_eq!(serialize(&empty), "1||");
+        assert_eq!(deserialize("1||"), Ok(empty));
+    }
+
+    #[test]
+    fn mentions() {
+        let snapshot = Snapshot {
+            document: Document::from_segments(vec![
+                Segment::Text("hi ".into()),
+                Segment::mention("X1", "Zoë", "@[X1]"),
+            ]),
+            selection: None,
+        };
+        let serialized = serialize(&snapshot);
+        assert_eq!(serialized, "1||t3:hi m2:X14:Zoë5:@[X1]");
+        assert_eq!(deserialize(&serialized), Ok(snapshot));
+        assert!(deserialize("1||m2:X13:Zoe0:").is_err());
+    }
+
+    #[test]
+    fn tokens() {
+        let attrs = vec![("title".to_string(), "Tag".to_string())];
+        let snapshot = Snapshot {
+            document: Document::from_segments(vec![
+                Segment::token("hashtag", "#a", attrs),
+                Segment::token("file", "[file]", vec![]),
+            ]),
+            selection: None,
+        };
+        let serialized = serialize(&snapshot);
+        assert_eq!(
+            serialized,
+            "1||k7:hashtag2:#a1:15:title3:Tagk4:file6:[file]1:0"
+        );
+        assert_eq!(deserialize(&serialized), Ok(snapshot));
+        assert!(deserialize("1||k4:file0:1:0").is_err());
+        assert!(deserialize("1||k4:file1:x1:1").is_err());
+        assert!(deserialize("1||k4:file1:x1:x").is_err());
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(deserialize("2||"), Err(StateError::UnsupportedVersion(2)));
+        assert_eq!(deserialize("0||"), Err(StateError::UnsupportedVersion(0)));
+        assert_eq!(
+            deserialize("hello"),
+            Err(StateError::InvalidFormat("Missing version"))
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(deserialize("1|").is_err());
+        assert!(deserialize("1|1|").is_err());
+        assert!(deserialize("1|0,1|").is_err());
+        assert!(deserialize("1||t5:abc").is_err());
+        assert!(deserialize("1||x").is_err());
+        assert!(deserialize("1||i3:abc").is_err());
+        // Length pointing into a multibyte character
+        assert!(deserialize("1||t1:😀").is_err());
+        // Length that overflows the end index
+        assert_eq!(
+            deserialize(&format!("1||t{}:x", usize::MAX)),
+            Err(StateError::InvalidFormat("Invalid length"))
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use cfg_if::cfg_if;
 use log::Level;
-use web_sys::{Element, Node};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{DataTransfer, Element, Node};
 
 cfg_if! {
     // When the `console_error_panic_hook` feature is enabled, we can call the
@@ -40,9 +41,57 @@ pub(crate) fn get_last_child(parent: &Element) -> Option<Node> {
     if child_count == 0 {
         return None;
     }
-    Some(
-        child_nodes
-            .get(child_count - 1)
-            .expect("Could not access last child node"),
-    )
+    child_nodes.get(child_count - 1)
+}
+
+/// Return the index of `child` in the child nodes of `parent` (or `None`).
+pub(crate) fn child_index(parent: &Node, child: &Node) -> Option<u32> {
+    let children = parent.child_nodes();
+    (0..children.length()).find(|i| children.item(*i).as_ref() == Some(child))
+}
+
+/// Return the value of a string property of a JS object (or `None`).
+pub(crate) fn get_string_property(object: &JsValue, name: &str) -> Option<String> {
+    js_sys::Reflect::get(object, &JsValue::from_str(name))
+        .ok()?
+        .as_string()
+}
+
+/// Return the value of a `DataTransfer` property of a JS object (e.g. of an
+/// event) or `None`.
+pub(crate) fn get_data_transfer_property(object: &JsValue, name: &str) -> Option<DataTransfer> {
+    js_sys::Reflect::get(object, &JsValue::from_str(name))
+        .ok()?
+        .dyn_into()
+        .ok()
+}
+
+/// Return the boundary points of the first target range of an `InputEvent`
+/// (see `getTargetRanges()`) as container nodes and offsets, or `None`.
+///
+/// Note: `getTargetRanges` and `StaticRange` are not yet supported by
+/// web-sys, so they are accessed through reflection.
+pub(crate) fn get_target_range(event: &JsValue) -> Option<((Node, u32), (Node, u32))> {
+    let get_target_ranges: js_sys::Function =
+        js_sys::Reflect::get(event, &JsValue::from_str("getTargetRanges"))
+            .ok()?
+            .dyn_into()
+            .ok()?;
+    let ranges: js_sys::Array = get_target_ranges.call0(event).ok()?.dyn_into().ok()?;
+    let range = ranges.get(0);
+    let boundary = |container: &str, offset: &str| -> Option<(Node, u32)> {
+        let node = js_sys::Reflect::get(&range, &JsValue::from_str(container))
+            .ok()?
+            .dyn_into()
+            .ok()?;
+        let offset = js_sys::Reflect::get(&range, &JsValue::from_str(offset))
+            .ok()?
+            .as_f64()?;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        Some((node, offset as u32))
+    };
+    Some((
+        boundary("startContainer", "startOffset")?,
+        boundary("endContainer", "endOffset")?,
+    ))
 }
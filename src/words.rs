@@ -0,0 +1,287 @@
+//! Detection of the word at a caret position.
+//!
+//! All offsets used in this module are UTF-16 offsets, like the offsets of
+//! the DOM and the logical offsets of the document model.
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::model::{utf16_len, utf16_to_byte_index, Document, Segment};
+
+/// How words are delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WordMode {
+    /// Words are delimited by boundary characters.
+    Boundaries,
+    /// Words are determined by Unicode word segmentation (UAX #29).
+    Unicode,
+    /// Like `Unicode`, but a token prefix (e.g. `@` or `:`) right before the
+    /// word is part of it.
+    Token,
+}
+
+impl WordMode {
+    /// Parse a mode name: `boundaries`, `unicode` or `token`.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "boundaries" => Some(WordMode::Boundaries),
+            "unicode" => Some(WordMode::Unicode),
+            "token" => Some(WordMode::Token),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            WordMode::Boundaries => "boundaries",
+            WordMode::Unicode => "unicode",
+            WordMode::Token => "token",
+        }
+    }
+}
+
+/// The rules used to find the word at the caret.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct WordRules {
+    pub(crate) mode: WordMode,
+    /// The boundary characters used in `Boundaries` mode. If `None`, all
+    /// whitespace characters are boundaries.
+    pub(crate) boundaries: Option<Vec<char>>,
+    /// The characters that start a token in `Token` mode.
+    pub(crate) token_prefixes: Vec<char>,
+    /// Whether images are treated like letters. Otherwise, they are
+    /// boundaries.
+    pub(crate) images_in_words: bool,
+}
+
+impl Default for WordRules {
+    fn default() -> Self {
+        Self {
+            mode: WordMode::Boundaries,
+            boundaries: None,
+            token_prefixes: vec!['@', ':'],
+            images_in_words: false,
+        }
+    }
+}
+
+impl WordRules {
+    /// Return the start (inclusive) and end (exclusive) offset of the word
+    /// at the caret `offset`. If there is no word at the caret, both offsets
+    /// are equal to the caret offset.
+    pub(crate) fn word_at(&self, text: &str, offset: u32) -> (u32, u32) {
+        let caret = utf16_to_byte_index(text, offset);
+        let (start, end) = match self.mode {
+            WordMode::Boundaries => self.delimited_word_at(text, caret),
+            WordMode::Unicode => unicode_word_at(text, caret),
+            WordMode::Token => self.token_at(text, caret),
+        };
+        (utf16_len(&text[..start]), utf16_len(&text[..end]))
+    }
+
+    /// Return the start (inclusive) and end (exclusive) logical offset of the
+    /// word at the caret in a document. Words never span line breaks,
+    /// mentions or tokens.
+    pub(crate) fn word_in_document(&self, document: &Document, caret: u32) -> (u32, u32) {
+        // Split the document into runs of text that can contain words. Images
+        // that are part of words are replaced by letters, so that the offsets
+        // stay the same.
+        let mut runs: Vec<(u32, String)> = vec![];
+        let mut in_run = false;
+        let mut start = 0;
+        for segment in document.segments() {
+            let text = match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Image { alt, .. } if self.images_in_words => {
+                    "x".repeat(utf16_len(alt) as usize)
+                }
+                Segment::Image { .. }
+                | Segment::Mention { .. }
+                | Segment::Token { .. }
+                | Segment::Newline => {
+                    in_run = false;
+                    start += segment.len();
+                    continue;
+                }
+            };
+            match runs.last_mut() {
+                Some((_, run)) if in_run => run.push_str(&text),
+                _ => runs.push((start, text)),
+            }
+            in_run = true;
+            start += segment.len();
+        }
+
+        runs.iter()
+            .find(|(start, text)| *start <= caret && caret <= start + utf16_len(text))
+            .map_or((caret, caret), |(start, text)| {
+                let (word_start, word_end) = self.word_at(text, caret - start);
+                (
+                    document.snap_offset(start + word_start, false),
+                    document.snap_offset(start + word_end, true),
+                )
+            })
+    }
+
+    fn is_boundary(&self, c: char) -> bool {
+        match &self.boundaries {
+            Some(boundaries) => boundaries.contains(&c),
+            None => c.is_whitespace(),
+        }
+    }
+
+    /// Return the byte range of the word delimited by boundary characters.
+    fn delimited_word_at(&self, text: &str, caret: usize) -> (usize, usize) {
+        let start = text[..caret]
+            .char_indices()
+            .filter(|(_, c)| self.is_boundary(*c))
+            .last()
+            .map_or(0, |(index, c)| index + c.len_utf8());
+        let end = text[caret..]
+            .char_indices()
+            .find(|(_, c)| self.is_boundary(*c))
+            .map_or(text.len(), |(index, _)| caret + index);
+        (start, end)
+    }
+
+    /// Return the byte range of the Unicode word, including a token prefix
+    /// right before it. If the caret is right after a token prefix that is
+    /// not followed by a word, the range of the prefix is returned.
+    fn token_at(&self, text: &str, caret: usize) -> (usize, usize) {
+        let (start, end) = unicode_word_at(text, caret);
+        match text[..start].chars().last() {
+            Some(prefix) if self.token_prefixes.contains(&prefix) => {
+                (start - prefix.len_utf8(), end)
+            }
+            _ => (start, end),
+        }
+    }
+}
+
+/// Return the byte range of the word (according to UAX #29) that contains
+/// the caret or ends at the caret. If there is none, the word starting at the
+/// caret is used. Otherwise, an empty range at the caret is returned.
+fn unicode_word_at(text: &str, caret: usize) -> (usize, usize) {
+    let is_word = |segment: &str| segment.chars().any(char::is_alphanumeric);
+    let mut found = None;
+    for (start, segment) in text.split_word_bound_indices() {
+        let end = start + segment.len();
+        if start > caret {
+            break;
+        }
+        if end >= caret && is_word(segment) {
+            found = Some((start, end));
+            if start < caret {
+                break;
+            }
+        }
+    }
+    found.unwrap_or((caret, caret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return the word at the caret, marked by `|` in the text.
+    fn word(rules: &WordRules, text_with_caret: &str) -> String {
+        let caret = text_with_caret.find('|').expect("No caret");
+        let text = text_with_caret.replacen('|', "", 1);
+        let (start, end) = rules.word_at(&text, utf16_len(&text[..caret]));
+        let start = utf16_to_byte_index(&text, start);
+        let end = utf16_to_byte_index(&text, end);
+        text[start..end].to_string()
+    }
+
+    fn rules(mode: WordMode) -> WordRules {
+        WordRules {
+            mode,
+            ..WordRules::default()
+        }
+    }
+
+    #[test]
+    fn boundaries() {
+        let rules = rules(WordMode::Boundaries);
+        assert_eq!(word(&rules, "hello wo|rld"), "world");
+        assert_eq!(word(&rules, "hello| world"), "hello");
+        assert_eq!(word(&rules, "hello |world"), "world");
+        assert_eq!(word(&rules, "hello\u{a0}wo|rld"), "world");
+        assert_eq!(word(&rules, "say: hi,wo|rld!"), "hi,world!");
+        assert_eq!(word(&rules, "a |"), "");
+        assert_eq!(word(&rules, "|"), "");
+
+        let rules = WordRules {
+            boundaries: Some(vec![',', '😀']),
+            ..rules
+        };
+        assert_eq!(word(&rules, "a b,c|d😀e"), "cd");
+    }
+
+    #[test]
+    fn unicode() {
+        let rules = rules(WordMode::Unicode);
+        assert_eq!(word(&rules, "hello, wo|rld!"), "world");
+        assert_eq!(word(&rules, "hello|, world"), "hello");
+        assert_eq!(word(&rules, "hello,| world"), "");
+        assert_eq!(word(&rules, "hello, |world"), "world");
+        assert_eq!(word(&rules, "hello,\u{a0}|"), "");
+        assert_eq!(word(&rules, "can't sto|p"), "stop");
+        assert_eq!(word(&rules, "can'|t"), "can't");
+        assert_eq!(word(&rules, "caf\u{e9}\u{301}|"), "caf\u{e9}\u{301}");
+        assert_eq!(word(&rules, "@ali|ce"), "alice");
+        assert_eq!(word(&rules, "日本|語"), "本");
+    }
+
+    #[test]
+    fn token() {
+        let rules = rules(WordMode::Token);
+        assert_eq!(word(&rules, "hi @ali|ce!"), "@alice");
+        assert_eq!(word(&rules, "hi @|"), "@");
+        assert_eq!(word(&rules, "hi @|alice"), "@alice");
+        assert_eq!(word(&rules, "a :smi|le:"), ":smile");
+        assert_eq!(word(&rules, "mail@exam|ple.com"), "@example.com");
+        assert_eq!(word(&rules, "#ta|g"), "tag");
+        assert_eq!(word(&rules, "hi |"), "");
+
+        let rules = WordRules {
+            token_prefixes: vec!['#'],
+            ..rules
+        };
+        assert_eq!(word(&rules, "#ta|g"), "#tag");
+    }
+
+    #[test]
+    fn across_segments() {
+        let document = Document::from_segments(vec![
+            Segment::Text("hi wor".into()),
+            Segment::image("e.png", "😀", "em"),
+            Segment::Text("ld foo".into()),
+            Segment::Newline,
+            Segment::Text("bar".into()),
+        ]);
+        let rules = rules(WordMode::Unicode);
+        assert_eq!(rules.word_in_document(&document, 4), (3, 6));
+        assert_eq!(rules.word_in_document(&document, 6), (3, 6));
+        assert_eq!(rules.word_in_document(&document, 8), (8, 10));
+        assert_eq!(rules.word_in_document(&document, 14), (11, 14));
+        assert_eq!(rules.word_in_document(&document, 15), (15, 18));
+
+        let rules = WordRules {
+            images_in_words: true,
+            ..rules
+        };
+        assert_eq!(rules.word_in_document(&document, 4), (3, 10));
+        assert_eq!(rules.word_in_document(&document, 8), (3, 10));
+
+        let document = Document::from_segments(vec![Segment::Text("a".into()), Segment::Newline]);
+        assert_eq!(rules.word_in_document(&document, 2), (2, 2));
+        assert_eq!(rules.word_in_document(&Document::new(), 0), (0, 0));
+    }
+
+    #[test]
+    fn mode_names() {
+        for mode in &[WordMode::Boundaries, WordMode::Unicode, WordMode::Token] {
+            assert_eq!(WordMode::from_name(mode.name()), Some(*mode));
+        }
+        assert_eq!(WordMode::from_name("words"), None);
+    }
+}
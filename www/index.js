@@ -1,4 +1,4 @@
-import {ComposeArea} from 'compose-area';
+import {ComposeArea, ComposeAreaOptions} from 'compose-area';
 
 // Elements
 const wrapper = document.getElementById('wrapper');
@@ -9,7 +9,11 @@ const wordcontextDiv = document.querySelector('#wordcontext div');
 const rawDiv = document.querySelector('#raw div');
 
 // Initialize compose area
-const composeArea = ComposeArea.bind_to(wrapper, "trace");
+const options = new ComposeAreaOptions()
+    .log_level('trace')
+    .paste_image_classes('emoji')
+    .highlight_markup(true);
+const composeArea = ComposeArea.bind_with_options(wrapper, options);
 window.composeArea = composeArea;
 
 // Helper functions
@@ -27,9 +31,9 @@ function log() {
     logDiv.innerHTML += `${timestamp} ${arguments[0]}<br>`;
 }
 
-function updateSelectionRange(e) {
-    log('⚙️ store_selection_range');
-    let range_result = composeArea.store_selection_range();
+function logSelectionRange(e) {
+    // Note: The selection range is stored by the compose area itself
+    let range_result = composeArea.fetch_range();
     log('⚙️ ⤷ ' + range_result.to_string_compact());
     showState();
 }
@@ -60,6 +64,35 @@ function showState() {
 
 wrapper.addEventListener('keydown', (e) => {
     log('⚡ keydown', e);
+    if (composeArea.handle_keydown(e)) {
+        log('⚙️ ⤷ handled');
+        showState();
+        return;
+    }
+    if ((e.ctrlKey || e.metaKey) && e.key.toLowerCase() === 'z') {
+        e.preventDefault();
+        log(e.shiftKey ? '⚙️ redo' : '⚙️ undo');
+        e.shiftKey ? composeArea.redo() : composeArea.undo();
+        showState();
+    }
+});
+wrapper.addEventListener('beforeinput', (e) => {
+    log('⚡ beforeinput', e);
+    if (composeArea.handle_beforeinput(e)) {
+        log('⚙️ ⤷ handled');
+        showState();
+    }
+});
+wrapper.addEventListener('input', (e) => {
+    log('⚡ input', e);
+    if (!e.isComposing) {
+        composeArea.update_markup();
+    }
+});
+wrapper.addEventListener('compositionend', (e) => {
+    log('⚡ compositionend', e);
+    composeArea.update_markup();
+    showState();
 });
 wrapper.addEventListener('keyup', (e) => {
     log('⚡ keyup', e);
@@ -69,11 +102,23 @@ wrapper.addEventListener('mouseup', (e) => {
 });
 wrapper.addEventListener('paste', (e) => {
     log('⚡ paste', e);
-    e.preventDefault();
-    const clipboardData = e.clipboardData.getData('text/plain');
-    if (clipboardData) {
-        log('⚙️ insert_text');
-        composeArea.insert_text(clipboardData);
+    if (composeArea.handle_paste(e)) {
+        log('⚙️ ⤷ handled');
+        showState();
+    }
+});
+
+wrapper.addEventListener('copy', (e) => {
+    log('⚡ copy', e);
+    if (composeArea.handle_copy(e)) {
+        log('⚙️ ⤷ handled');
+    }
+});
+wrapper.addEventListener('cut', (e) => {
+    log('⚡ cut', e);
+    if (composeArea.handle_cut(e)) {
+        log('⚙️ ⤷ handled');
+        showState();
     }
 });
 
@@ -81,7 +126,7 @@ wrapper.addEventListener('paste', (e) => {
 // level, not on the wrapper itself.
 document.addEventListener('selectionchange', (e) => {
     log('⚡ selectionchange', e);
-    updateSelectionRange();
+    logSelectionRange();
 });
 
 // Emoji handling
@@ -92,8 +137,10 @@ function insertEmoji(e) {
     const elem = composeArea.insert_image(img.src, img.alt, 'emoji');
 
     // Ensure that emoji cannot be dragged
-    elem.draggable = false;
-    elem.ondragstart = (e) => e.preventDefault();
+    if (elem !== undefined) {
+        elem.draggable = false;
+        elem.ondragstart = (e) => e.preventDefault();
+    }
 
     showState();
 }
@@ -113,3 +160,10 @@ document.getElementById('focus').addEventListener('click', (e) => {
 document.getElementById('selectword').addEventListener('click', (e) => {
     composeArea.select_word_at_caret();
 });
+for (const style of ['bold', 'italic', 'strikethrough', 'monospace']) {
+    document.getElementById(style).addEventListener('click', (e) => {
+        log(`⚙️ toggle_${style}`);
+        composeArea[`toggle_${style}`]();
+        showState();
+    });
+}